@@ -0,0 +1,269 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use crate::{constants, util};
+use crate::error::Error;
+use crate::formats::bgzf::Bgzf;
+use crate::formats::codec::{Codec, Registry};
+use crate::formats::gz::{self, Subfield};
+use crate::formats::pkzip::Pkzip;
+use crate::options::{Mode, Options};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rustzip", about="GNU gzip ported to Rust; aka rustzip.", author="Will Fehrnstrom, wfehrnstrom@gmail.com")]
+/// Opt is used to store all the arguments passed through the command line
+pub struct Opt {
+    #[structopt(short="a", long, help="ascii text; convert end-of-line using local conventions")]
+    pub(crate) ascii: bool,
+    #[structopt(short="c", long, alias="to-stdout", help="write on standard output, keep original files unchanged")]
+    pub(crate) stdout: bool,
+    #[structopt(short, long, alias="uncompress", help="decompress")]
+    pub(crate) decompress: bool,
+    #[structopt(short, long, help="force overwrite of output file and compress links")]
+    pub(crate) force: bool,
+    #[structopt(short, long, help="keep (don't delete) input files")]
+    pub(crate) keep: bool,
+    #[structopt(short="l", long, help="list compressed file contents")]
+    pub(crate) list: bool,
+    #[structopt(short="L", long, help="display software license")]
+    pub(crate) license: bool,
+    #[structopt(short="n", long, help="don't save or restore original name and timestamp")]
+    pub(crate) no_name: bool,
+    #[structopt(short="N", long, help="save or restore original name and timestamp")]
+    pub(crate) name: bool,
+    #[structopt(short, long, help="suppress all warnings", conflicts_with="verbose")]
+    pub(crate) quiet: bool,
+    #[allow(dead_code)]
+    #[structopt(long, help="synchronous output (safer if system crashes, but slower)")]
+    pub(crate) synchronous: bool,
+    #[structopt(short, long, help="operate recursively on directories")]
+    pub(crate) recursive: bool,
+    #[structopt(short="S", long, help="use suffix SUF on compressed files", default_value=".gz")]
+    pub(crate) suffix: String,
+    #[structopt(short, long, help="test compressed file integrity")]
+    pub(crate) test: bool,
+    #[structopt(short="v", long, help="verbose mode", default_value="0")]
+    pub(crate) verbose: u8,
+    #[structopt(short="1", long, help="compress faster, but worse", conflicts_with="best")]
+    pub(crate) fast: bool,
+    #[structopt(short="9", long, help="compress better, but slower")]
+    pub(crate) best: bool,
+    #[structopt(long, help="specify compression level 1-9 (9: best, slow; 1: worst, fast)", default_value="6", parse(from_str="parse_level"))]
+    pub(crate) level: i8,
+    #[structopt(long="header-crc", help="store a CRC of the header (FHCRC) when compressing")]
+    pub(crate) header_crc: bool,
+    #[structopt(long="extra", number_of_values=1, help="store subfield ID=hex in the extra field when compressing")]
+    pub(crate) extra: Vec<Subfield>,
+    #[structopt(long, help="store comment TEXT in the header when compressing", conflicts_with="comment_file")]
+    pub(crate) comment: Option<String>,
+    #[structopt(long="comment-file", parse(from_os_str), help="store the contents of FILE as the comment when compressing")]
+    pub(crate) comment_file: Option<PathBuf>,
+    #[structopt(long, help="compress to FORMAT: gzip, bgzf (blocked gzip), zlib, raw deflate, or zip to pack directories into one archive", default_value="gzip")]
+    pub(crate) format: String,
+    #[structopt(long, help="with -r, pack each directory into a compressed tar archive; with -d, extract one")]
+    pub(crate) tar: bool,
+    #[structopt(long, help="with --format bgzf, also write a .gzi index of the blocks")]
+    pub(crate) gzi: bool,
+    #[structopt(long, help="save an index of each gzip file next to it, for --range")]
+    pub(crate) index: bool,
+    #[structopt(long, help="write LEN bytes starting at OFFSET of the decompressed data to standard output, using the file's index", parse(try_from_str="parse_range"))]
+    pub(crate) range: Option<(u64, u64)>,
+    #[structopt(long, help="make rsync-friendly archive")]
+    pub(crate) rsyncable: bool,
+    #[structopt(short="j", long, help="compress in parallel with THREADS number of threads")]
+    pub(crate) parallel: Option<u8>,
+    pub(crate) no_time: bool,
+    /// Files to process
+    #[structopt(name = "FILE", parse(from_os_str), last = true)]
+    pub(crate) files: Vec<PathBuf>
+}
+
+// TODO: make no_name and name exclusive arguments (e.g. cannot be passed together)
+impl Opt {
+    /// will error on being passed a suffix via --suffix greater than 30 characters
+    /// checks to ensure that all command line arguments are consistent (e.g. quiet and verbose
+    /// are not present at the same time). The following rules apply:
+    ///     if --quiet is passed, --verbose is coerced to false/0
+    ///     if --list is passed, we are getting statistics on compressed files. Therefore we are
+    ///         decompressing.
+    ///     if we are not restoring the name, we must also not be restoring the time
+    ///     if we are testing a compressed file, we decompress
+    ///     --ascii should only be present on windows systems
+    pub(crate) fn new () -> Result<Self, Error> {
+        let mut opt = Opt::from_args();
+        if opt.quiet {
+            opt.verbose = 0;
+        }
+        if opt.index && (opt.list || opt.test || opt.range.is_some()) {
+            return Err(Error::fatal("--index cannot be used with --list, --test or --range"));
+        }
+        if opt.index {
+            opt.decompress = true;
+        }
+        if opt.range.is_some() {
+            if opt.list || opt.test {
+                return Err(Error::fatal("--range cannot be used with --list or --test"));
+            }
+            opt.decompress = true;
+            opt.stdout = true;
+        }
+        if opt.list {
+            opt.decompress = true;
+            opt.stdout = true;
+        }
+        if opt.no_name {
+            opt.no_time = true;
+            opt.name = false;
+        }
+        if opt.name {
+            opt.no_time = false;
+            opt.no_name = false;
+        }
+        if opt.test {
+            opt.decompress = true;
+        }
+        if ! cfg!(target_os = "windows") {
+            if opt.ascii && !opt.quiet {
+                eprintln!("{}: option --ascii ignored on this system", constants::PROGRAM_NAME);
+            }
+            opt.ascii = false;
+        }
+        if opt.rsyncable && opt.parallel.is_some() {
+            if !opt.quiet {
+                eprintln!("{}: option --parallel ignored with --rsyncable", constants::PROGRAM_NAME);
+            }
+            opt.parallel = None;
+        }
+        if opt.fast {
+            opt.level = 1;
+        }
+        if opt.best {
+            opt.level = 9;
+        }
+        // Default situation where both not given
+        if !opt.name && !opt.no_name {
+            opt.no_name = opt.decompress;
+            opt.no_time = opt.decompress;
+        }
+        // Default to stdin
+        if opt.files.is_empty() {
+            opt.files = vec!(PathBuf::from("-"));
+        }
+        if Registry::new().get(&opt.format).is_none() {
+            return Err(Error::fatal(format!("unknown format '{}'", opt.format)));
+        }
+        if opt.tar && opt.format == Pkzip.name() {
+            return Err(Error::fatal("--tar cannot be used with --format zip"));
+        }
+        if opt.gzi && opt.format != Bgzf.name() {
+            return Err(Error::fatal("--gzi can only be used with --format bgzf"));
+        }
+        // archives are named after their format unless another suffix is asked for; BGZF
+        // files are gzip files, and keep its suffix
+        if opt.format != constants::DEFAULT_FORMAT && opt.format != Bgzf.name() && opt.suffix == format!(".{}", constants::DEFAULT_SUFFIX) {
+            opt.suffix = opt.format.clone();
+        }
+        if check_if_suffix_too_long(&opt.suffix).is_some() {
+            return Err(Error::fatal(format!("invalid suffix '{}'", opt.suffix)));
+        }
+        opt.suffix = String::from(util::strip_leading_dot(opt.suffix.as_str()));
+        if let Some(path) = opt.comment_file.take() {
+            // like a message given on the command line, the comment doesn't end in a newline
+            let comment = std::fs::read(&path).map_err(|e| Error::Fatal { path: Some(path.clone()), cause: e })?;
+            opt.comment = Some(String::from_utf8_lossy(&comment).trim_end_matches('\n').to_string());
+        }
+        if opt.comment.as_ref().is_some_and(|c| c.contains('\0')) {
+            return Err(Error::fatal("the comment cannot contain a NUL byte"));
+        }
+        if opt.comment.as_ref().is_some_and(|c| c.len() > gz::MAX_COMMENT) {
+            return Err(Error::fatal(format!("the comment cannot be longer than {} bytes", gz::MAX_COMMENT)));
+        }
+        Ok(opt)
+    }
+
+    /// The options the core works from, once the arguments have been made consistent by `new`
+    pub(crate) fn options (&self) -> Options {
+        let mode = if self.list {
+            Mode::List
+        }
+        else if self.index {
+            Mode::Index
+        }
+        else if self.test {
+            Mode::Test
+        }
+        else if self.decompress {
+            Mode::Decompress
+        }
+        else {
+            Mode::Compress
+        };
+        let mut options = Options::new(mode);
+        options.format = self.format.clone();
+        options.tar = self.tar;
+        options.gzi = self.gzi;
+        options.range = self.range;
+        options.level = self.level as u32;
+        options.threads = self.parallel.map(usize::from);
+        options.rsyncable = self.rsyncable;
+        options.header_crc = self.header_crc;
+        options.extra = self.extra.clone();
+        options.comment = self.comment.clone();
+        options.name = !self.no_name;
+        options.time = !self.no_time;
+        options.suffix = self.suffix.clone();
+        options.stdout = self.stdout;
+        options.force = self.force;
+        options.keep = self.keep;
+        options.recursive = self.recursive;
+        options.quiet = self.quiet;
+        options.verbose = self.verbose;
+        options
+    }
+}
+
+fn parse_level(levelstr: &str) -> i8 {
+    let level = match levelstr.parse::<i8>() {
+        Err(_e) => constants::DEFAULT_LEVEL,
+        Ok(u) => u
+    };
+    level.clamp(1, 9)
+}
+
+/// Parses `OFFSET:LEN`, as given to `--range`
+fn parse_range (range: &str) -> Result<(u64, u64), String> {
+    let (offset, len) = range.split_once(':').ok_or_else(|| format!("'{}' is not of the form OFFSET:LEN", range))?;
+    let number = |n: &str| n.parse::<u64>().map_err(|_| format!("'{}' is not a number of bytes", n));
+    Ok((number(offset)?, number(len)?))
+}
+
+fn check_if_suffix_too_long (s: &str) -> Option<String> {
+    if s.len() > constants::MAX_SUFFIX {
+        return Some(String::from(s));
+    }
+    None
+}
+
+fn print_license () {
+    println!("Copyright (C) 2019 Free Software Foundation, Inc. Copyright (C) 1993 Jean-loup Gailly.\n\
+     This is free software.  You may redistribute copies of it under the terms of\n\
+     the GNU General Public License <https://www.gnu.org/licenses/gpl.html>.\n\
+     There is NO WARRANTY, to the extent permitted by law.")
+}
+
+/// Runs rstzip on the arguments this process was started with, returning the exit status
+pub fn main_helper () -> i8 {
+    let opt = match Opt::new() {
+        Ok(opt) => opt,
+        Err(err) => {
+            eprintln!("{}: {}", constants::PROGRAM_NAME, err);
+            return err.exit_code();
+        }
+    };
+    if opt.license {
+        print_license ();
+    }
+    match crate::run(opt.files.clone(), &opt.options()) {
+        Ok(()) => constants::OK,
+        Err(err) => err.exit_code()
+    }
+}
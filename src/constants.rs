@@ -2,6 +2,9 @@ pub const DEFAULT_LEVEL: i8 = 6;
 pub const PROGRAM_NAME: &str = "rstzip";
 pub const MAX_SUFFIX: usize = 30;
 pub const DEFAULT_SUFFIX: &str = "gz";
+pub const DEFAULT_FORMAT: &str = "gzip";
+/// how many directories deep --recursive descends
+pub const MAX_DEPTH: usize = 128;
 
 // File mode constants
 pub const S_ISUID: u32 = 0o04000;
@@ -12,3 +15,10 @@ pub const S_ISVTX: u32 = 0o01000;
 pub const OK: i8 = 0;
 pub const WARNING: i8 = 2;
 pub const ERROR: i8 = 1;
+
+// Parallel compression
+pub const BLOCK_SIZE: usize = 128 * 1024;
+pub const WINDOW_SIZE: usize = 32 * 1024;
+
+// --rsyncable
+pub const RSYNC_WIN: usize = 4096;
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use crate::constants;
+
+/// Why a file was left alone. None of these are errors: gzip reports them unless `--quiet` is
+/// given, carries on, and exits with status 2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// a directory was given without `--recursive`
+    Directory,
+    /// neither a directory nor a regular file, e.g. a device or a fifo
+    NotRegular,
+    SetUid,
+    SetGid,
+    Sticky,
+    /// the file has this many other hard links
+    Links(u64),
+    /// compressing a file that already ends in this compressed suffix
+    HasSuffix(String),
+    /// decompressing a file whose suffix isn't one rstzip knows
+    UnknownSuffix,
+    /// a directory deeper than `Options::max_depth` below the operand it was found in
+    TooDeep,
+    /// a directory that is also one of its own ancestors, reached through a symbolic link
+    Loop,
+    /// the compressed data checked out, but was followed by something other than padding
+    TrailingGarbage,
+    /// only the first entry of an archive was decompressed
+    MoreEntries
+}
+
+/// Everything that can go wrong while processing files, from a file that was skipped to a
+/// condition that stops rstzip altogether. Each variant carries the path it concerns, if any,
+/// and what went wrong.
+#[derive(Debug)]
+pub enum Error {
+    /// a file was skipped; the remaining files are still processed
+    Warning { path: PathBuf, warning: Warning },
+    /// processing one file failed
+    File { path: PathBuf, cause: io::Error },
+    /// nothing further can be processed
+    Fatal { path: Option<PathBuf>, cause: io::Error }
+}
+
+impl Error {
+    pub fn file<P: Into<PathBuf>> (path: P, cause: io::Error) -> Self {
+        Error::File { path: path.into(), cause }
+    }
+
+    pub fn warning<P: Into<PathBuf>> (path: P, warning: Warning) -> Self {
+        Error::Warning { path: path.into(), warning }
+    }
+
+    /// A fatal error that doesn't concern any one file
+    pub fn fatal<M: Into<String>> (msg: M) -> Self {
+        Error::Fatal { path: None, cause: io::Error::other(msg.into()) }
+    }
+
+    pub fn is_warning (&self) -> bool {
+        matches!(self, Error::Warning { .. })
+    }
+
+    pub fn is_fatal (&self) -> bool {
+        matches!(self, Error::Fatal { .. })
+    }
+
+    /// The exit status gzip uses for this error: 2 for warnings, 1 for anything else
+    pub fn exit_code (&self) -> i8 {
+        match self {
+            Error::Warning { .. } => constants::WARNING,
+            _ => constants::ERROR
+        }
+    }
+
+    /// Whichever of `self` and `other` deserves the higher exit status, preferring `self`
+    pub(crate) fn worst (self, other: Error) -> Error {
+        if other.exit_code() == constants::ERROR && self.is_warning() {
+            other
+        }
+        else {
+            self
+        }
+    }
+}
+
+/// Describes `cause` the way gzip does, without Rust's "(os error N)" suffix for the common cases
+fn describe (cause: &io::Error) -> String {
+    match cause.kind() {
+        io::ErrorKind::NotFound if cause.raw_os_error().is_some() => String::from("No such file or directory"),
+        io::ErrorKind::PermissionDenied if cause.raw_os_error().is_some() => String::from("Permission denied"),
+        io::ErrorKind::UnexpectedEof => String::from("unexpected end of file"),
+        _ => cause.to_string()
+    }
+}
+
+impl fmt::Display for Error {
+    /// Formats the error as gzip words it, without the leading program name
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Warning { path, warning } => {
+                let path = path.display();
+                match warning {
+                    Warning::Directory => write!(f, "{} is a directory -- ignored", path),
+                    Warning::NotRegular => write!(f, "{} is not a directory or a regular file - ignored", path),
+                    Warning::SetUid => write!(f, "{} is set-user-ID on execution - ignored", path),
+                    Warning::SetGid => write!(f, "{} is set-group-ID on execution - ignored", path),
+                    Warning::Sticky => write!(f, "{} has the sticky bit set - file ignored", path),
+                    Warning::Links(n) => write!(f, "{} has {} other link{} -- unchanged", path, n,
+                        if *n == 1 { "" } else { "s" }),
+                    Warning::HasSuffix(suffix) => write!(f, "{} already has .{} suffix -- unchanged", path, suffix),
+                    Warning::UnknownSuffix => write!(f, "{}: unknown suffix -- ignored", path),
+                    Warning::TooDeep => write!(f, "{} is nested too deeply -- ignored", path),
+                    Warning::Loop => write!(f, "{}: directory loop -- ignored", path),
+                    Warning::TrailingGarbage => write!(f, "{}: decompression OK, trailing garbage ignored", path),
+                    Warning::MoreEntries => write!(f, "{} has more than one entry--rest ignored", path)
+                }
+            },
+            Error::File { path, cause } => write!(f, "{}: {}", path.display(), describe(cause)),
+            Error::Fatal { path: Some(path), cause } => write!(f, "{}: {}", path.display(), describe(cause)),
+            Error::Fatal { path: None, cause } => write!(f, "{}", describe(cause))
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source (&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Warning { .. } => None,
+            Error::File { cause, .. } | Error::Fatal { cause, .. } => Some(cause)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes () {
+        let warning = Error::warning("a.gz", Warning::HasSuffix(String::from("gz")));
+        let file = Error::file("b", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(warning.exit_code(), constants::WARNING);
+        assert_eq!(file.exit_code(), constants::ERROR);
+        assert_eq!(Error::fatal("stop").exit_code(), constants::ERROR);
+        assert!(!warning.worst(file).is_warning());
+    }
+
+    #[test]
+    fn messages () {
+        assert_eq!(Error::warning("a.gz", Warning::HasSuffix(String::from("gz"))).to_string(),
+            "a.gz already has .gz suffix -- unchanged");
+        assert_eq!(Error::warning("dir", Warning::Directory).to_string(), "dir is a directory -- ignored");
+        let missing = io::Error::from_raw_os_error(libc::ENOENT);
+        assert_eq!(Error::file("b", missing).to_string(), "b: No such file or directory");
+    }
+}
@@ -0,0 +1,85 @@
+//! What the archive formats share: the Unix modes recorded for files, and the checks and
+//! metadata restoring that extraction needs
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// The Unix mode of a file, as archives record it
+#[cfg(unix)]
+pub(crate) fn unix_mode (meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    meta.mode()
+}
+
+/// The Unix mode of a file, made up from what the system tells of its permissions
+#[cfg(not(unix))]
+pub(crate) fn unix_mode (meta: &fs::Metadata) -> u32 {
+    let mode = if meta.is_dir() { 0o40755 } else { 0o100644 };
+    if meta.permissions().readonly() { mode & !0o222 } else { mode }
+}
+
+/// The relative path an entry named `name` is extracted to, or `None` if the name is absolute
+/// or climbs out of the destination
+pub(crate) fn safe_path (name: &str) -> Option<PathBuf> {
+    // names use `/`, but some archivers write `\`
+    let name = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+    for component in Path::new(&name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => (),
+            _ => return None
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return None;
+    }
+    Some(path)
+}
+
+/// Fails if any directory between `dest` and `path`, or `path` itself, is a symbolic link, which
+/// a malicious archive could have planted to write outside `dest`
+pub(crate) fn check_no_links (dest: &Path, path: &Path) -> io::Result<()> {
+    let mut current = dest.to_path_buf();
+    for component in path.strip_prefix(dest).unwrap_or(path).components() {
+        current.push(component);
+        if fs::symlink_metadata(&current).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}: not extracting through a symbolic link",
+                current.display())));
+        }
+    }
+    Ok(())
+}
+
+/// Gives an extracted file or directory the permissions and mtime recorded for it
+pub(crate) fn restore (path: &Path, mode: Option<u32>, mtime: Option<i64>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some(mode) = mode {
+            // set-id bits are never restored from an archive
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o1777))?;
+        }
+    }
+    if let Some(mtime) = mtime.and_then(|t| u64::try_from(t).ok()) {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        utime::set_file_times(path, now, mtime)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsafe_paths () {
+        assert_eq!(safe_path("a/./b"), Some(PathBuf::from("a/b")));
+        for name in &["/etc/passwd", "../up", "a/../../up", "a\\..\\..\\up", ".", ""] {
+            assert_eq!(safe_path(name), None, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use flate2::{Compression, Crc};
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::DeflateEncoder;
+use crate::formats::codec::{Codec, Header, Tested};
+use crate::formats::gz::{GzFile, Gzip, Subfield};
+use crate::formats::list::ListEntry;
+use crate::options::Options;
+use crate::util::WorkData;
+
+/// the most data a block holds, leaving room for the block to stay within `MAX_BLOCK` even
+/// when the data doesn't compress
+const BLOCK_DATA: usize = 0xff00;
+/// the largest block, header and trailer included, that BSIZE can describe
+const MAX_BLOCK: usize = 0x10000;
+const HEADER_LEN: usize = 18;
+const TRAILER_LEN: usize = 8;
+/// the ID of the subfield holding BSIZE
+const BC: [u8; 2] = *b"BC";
+/// the empty block every BGZF file ends with
+pub const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0,
+    3, 0, 0, 0, 0, 0, 0, 0, 0, 0
+];
+
+/// Blocked gzip, as written by `bgzip` for BAM and tabix-indexed files: gzip members of at
+/// most 64 KiB, each recording its own size in a `BC` subfield of the extra field, and an
+/// empty member at the end. Any gzip reader decompresses it; knowing where each block starts
+/// lets a `.gzi` index jump to any offset of the data.
+///
+/// BGZF can't be told from gzip by its magic number, so it is never detected: the gzip codec
+/// hands BGZF files over when it finds the `BC` subfield.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bgzf;
+
+impl Codec for Bgzf {
+    fn name (&self) -> &'static str {
+        "bgzf"
+    }
+
+    fn detect (&self, _magic: &[u8]) -> bool {
+        false
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let header = Gzip.read_header(input)?;
+        if block_size(&header.subfields).is_none() {
+            return Err(not_bgzf());
+        }
+        Ok(header)
+    }
+
+    fn decode (&self, header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        Gzip.decode(header, input, output)
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        Gzip.test(header, input)
+    }
+
+    /// Compresses `input` into blocks, writing a `.gzi` index next to the output file when
+    /// `opt.gzi` asks for one
+    fn encode (&self, input: &mut dyn Read, output: &mut dyn Write, wdata: Option<WorkData>,
+        opt: &Options) -> io::Result<()>
+    {
+        let index = compress(input, output, Compression::new(opt.level.min(9)))?;
+        match wdata {
+            Some(wdata) if opt.gzi && !opt.stdout => {
+                let mut out = io::BufWriter::new(File::create(index_path(Path::new(&wdata.ofname)))?);
+                index.write(&mut out)?;
+                out.flush()
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Describes `file` with the sizes its blocks record, walking their headers
+    fn list (&self, file: &mut File, _opt: &Options) -> io::Result<Vec<ListEntry>> {
+        file.seek(SeekFrom::Start(0))?;
+        let gz = GzFile::read_header(&mut *file)?;
+        let blocks = blocks(file)?;
+        Ok(vec![ListEntry {
+            method: self.name(),
+            crc32: None,
+            mtime: Some(gz.mtime().into()),
+            compressed_size: file.metadata()?.len(),
+            uncompressed_size: Some(blocks.iter().map(|block| u64::from(block.data_size)).sum()),
+            header_size: (blocks.len() * (HEADER_LEN + TRAILER_LEN)) as u64,
+            stored_name: None,
+            subfields: gz.subfields().unwrap_or_default(),
+            comment: None,
+            members: Vec::new(),
+            trailing_garbage: false,
+            path: None
+        }])
+    }
+}
+
+/// The size of the whole block a header with `subfields` starts, from its `BC` subfield, or
+/// `None` if it isn't a BGZF block
+pub fn block_size (subfields: &[Subfield]) -> Option<usize> {
+    subfields.iter().find(|subfield| subfield.id == BC).and_then(|subfield| match subfield.data[..] {
+        [lo, hi] => Some(usize::from(u16::from_le_bytes([lo, hi])) + 1),
+        _ => None
+    })
+}
+
+/// Where the `.gzi` index of the BGZF file at `path` is kept: next to it, named after it
+pub fn index_path (path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gzi");
+    PathBuf::from(name)
+}
+
+/// Compresses all of `input` into `output` as BGZF blocks followed by the EOF marker,
+/// returning the index of the blocks written
+pub fn compress<R: Read + ?Sized, W: Write + ?Sized> (input: &mut R, output: &mut W, level: Compression)
+    -> io::Result<Index>
+{
+    let mut index = Index::default();
+    let (mut compressed, mut uncompressed) = (0u64, 0u64);
+    let mut data = vec![0u8; BLOCK_DATA];
+    loop {
+        let n = read_full(input, &mut data)?;
+        if n == 0 {
+            break;
+        }
+        let block = block(&data[..n], level)?;
+        output.write_all(&block)?;
+        compressed += block.len() as u64;
+        uncompressed += n as u64;
+        index.blocks.push((compressed, uncompressed));
+    }
+    output.write_all(&EOF_MARKER)?;
+    Ok(index)
+}
+
+/// Compresses `data` into one block, stored rather than deflated if deflating doesn't shrink
+/// it enough to fit
+fn block (data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let mut body = deflate(data, level)?;
+    if HEADER_LEN + body.len() + TRAILER_LEN > MAX_BLOCK {
+        body = deflate(data, Compression::none())?;
+    }
+    let bsize = (HEADER_LEN + body.len() + TRAILER_LEN - 1) as u16;
+    let mut block = vec![0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, BC[0], BC[1], 2, 0];
+    block.extend_from_slice(&bsize.to_le_bytes());
+    block.extend_from_slice(&body);
+    let mut crc = Crc::new();
+    crc.update(data);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+fn deflate (data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), level);
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Reads until `buf` is full or `input` ends, returning how much was read
+fn read_full<R: Read + ?Sized> (input: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(len)
+}
+
+/// Where each block starts, both in the compressed file and in the data: the contents of a
+/// `.gzi` file, as `bgzip -i` writes it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// the compressed and uncompressed offsets at which each block after the first starts
+    pub blocks: Vec<(u64, u64)>
+}
+
+impl Index {
+    /// Reads an index laid out as `write` lays it out
+    pub fn read<R: Read> (mut input: R) -> io::Result<Self> {
+        let mut word = [0u8; 8];
+        input.read_exact(&mut word)?;
+        let count = u64::from_le_bytes(word);
+        let mut blocks = Vec::new();
+        for _ in 0..count {
+            input.read_exact(&mut word)?;
+            let compressed = u64::from_le_bytes(word);
+            input.read_exact(&mut word)?;
+            blocks.push((compressed, u64::from_le_bytes(word)));
+        }
+        if blocks.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(Error::new(ErrorKind::InvalidData, "corrupted BGZF index"));
+        }
+        Ok(Index { blocks })
+    }
+
+    /// Whether this index may describe a BGZF file of `len` bytes: every block it names starts
+    /// within the file
+    pub fn describes (&self, len: u64) -> bool {
+        self.blocks.last().is_none_or(|&(compressed, _)| compressed < len)
+    }
+
+    /// Writes the number of blocks, then their offsets, all as little endian 64 bit integers
+    pub fn write<W: Write> (&self, mut output: W) -> io::Result<()> {
+        output.write_all(&(self.blocks.len() as u64).to_le_bytes())?;
+        for (compressed, uncompressed) in &self.blocks {
+            output.write_all(&compressed.to_le_bytes())?;
+            output.write_all(&uncompressed.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Builds the index of the BGZF file `input` by walking the headers of its blocks
+    pub fn scan<R: Read + Seek> (input: &mut R) -> io::Result<Self> {
+        let mut index = Index::default();
+        let mut uncompressed = 0u64;
+        for block in blocks(input)? {
+            if block.data_size > 0 {
+                uncompressed += u64::from(block.data_size);
+                index.blocks.push((block.offset + block.size, uncompressed));
+            }
+        }
+        Ok(index)
+    }
+
+    /// The compressed and uncompressed offsets of the start of the block holding the byte at
+    /// `offset` of the data
+    fn locate (&self, offset: u64) -> (u64, u64) {
+        match self.blocks.iter().rposition(|&(_, start)| start <= offset) {
+            Some(i) => self.blocks[i],
+            None => (0, 0)
+        }
+    }
+}
+
+/// One block of a BGZF file, as its header and trailer describe it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Block {
+    offset: u64,
+    size: u64,
+    data_size: u32
+}
+
+/// Walks the blocks of the BGZF file `input` from its start, reading only their headers and
+/// sizes
+fn blocks<R: Read + Seek + ?Sized> (input: &mut R) -> io::Result<Vec<Block>> {
+    let len = input.seek(SeekFrom::End(0))?;
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        input.seek(SeekFrom::Start(offset))?;
+        let gz = GzFile::read_header(&mut *input)?;
+        let size = gz.subfields().ok().and_then(|subfields| block_size(&subfields)).ok_or_else(not_bgzf)?;
+        if size < gz.header_len() + TRAILER_LEN || offset + size as u64 > len {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--BGZF block size"));
+        }
+        input.seek(SeekFrom::Start(offset + size as u64 - 4))?;
+        let mut data_size = [0u8; 4];
+        input.read_exact(&mut data_size)?;
+        blocks.push(Block { offset, size: size as u64, data_size: u32::from_le_bytes(data_size) });
+        offset += size as u64;
+    }
+    Ok(blocks)
+}
+
+/// Decompresses `len` bytes starting at `offset` of the data of the BGZF file `input` into
+/// `output`, starting from the block `index` says holds `offset`. Returns how many bytes were
+/// written, fewer than `len` if the data ends first.
+pub fn read_range<R: Read + Seek, W: Write + ?Sized> (input: &mut R, index: &Index, offset: u64, len: u64,
+    output: &mut W) -> io::Result<u64>
+{
+    let (compressed, uncompressed) = index.locate(offset);
+    input.seek(SeekFrom::Start(compressed))?;
+    let mut data = MultiGzDecoder::new(BufReader::new(input));
+    let skipped = io::copy(&mut (&mut data).take(offset - uncompressed), &mut io::sink())?;
+    if skipped < offset - uncompressed {
+        return Ok(0);
+    }
+    io::copy(&mut data.take(len), output)
+}
+
+fn not_bgzf () -> Error {
+    Error::new(ErrorKind::InvalidData, "not in BGZF format")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn data () -> Vec<u8> {
+        (0..300_000u32).map(|i| (i % 251) as u8 ^ (i / 7000) as u8).collect()
+    }
+
+    #[test]
+    fn blocks_and_index () {
+        let data = data();
+        let mut compressed = Vec::new();
+        let index = compress(&mut &data[..], &mut compressed, Compression::default()).unwrap();
+        assert!(compressed.ends_with(&EOF_MARKER));
+        assert_eq!(index.blocks.len(), data.len().div_ceil(BLOCK_DATA));
+        assert_eq!(index.blocks.last().unwrap(), &((compressed.len() - EOF_MARKER.len()) as u64, data.len() as u64));
+        assert_eq!(Index::scan(&mut Cursor::new(&compressed)).unwrap(), index);
+
+        let mut gzi = Vec::new();
+        index.write(&mut gzi).unwrap();
+        assert_eq!(gzi.len(), 8 + 16 * index.blocks.len());
+        assert_eq!(Index::read(&gzi[..]).unwrap(), index);
+
+        let mut input = &compressed[..];
+        let header = Bgzf.read_header(&mut input).unwrap();
+        assert_eq!(block_size(&header.subfields), Some(blocks(&mut Cursor::new(&compressed)).unwrap()[0].size as usize));
+        let mut out = Vec::new();
+        Bgzf.decode(&header, &mut input, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn ranges () {
+        let data = data();
+        let mut compressed = Vec::new();
+        let index = compress(&mut &data[..], &mut compressed, Compression::fast()).unwrap();
+        for &(offset, len) in &[(0, 10), (BLOCK_DATA as u64 - 3, 6), (200_000, 70_000), (299_990, 100), (400_000, 5)] {
+            let mut out = Vec::new();
+            let n = read_range(&mut Cursor::new(&compressed), &index, offset, len, &mut out).unwrap();
+            let start = (offset as usize).min(data.len());
+            let end = (start + len as usize).min(data.len());
+            assert_eq!((n, &out[..]), ((end - start) as u64, &data[start..end]));
+        }
+    }
+
+    #[test]
+    fn incompressible_and_empty () {
+        let mut noise = Vec::with_capacity(2 * BLOCK_DATA);
+        let mut x = 0x2545_f491u32;
+        while noise.len() < 2 * BLOCK_DATA {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            noise.push(x as u8);
+        }
+        let mut compressed = Vec::new();
+        compress(&mut &noise[..], &mut compressed, Compression::best()).unwrap();
+        let blocks = blocks(&mut Cursor::new(&compressed)).unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks.iter().all(|block| block.size as usize <= MAX_BLOCK));
+
+        let mut empty = Vec::new();
+        assert_eq!(compress(&mut &b""[..], &mut empty, Compression::default()).unwrap(), Index::default());
+        assert_eq!(empty, EOF_MARKER);
+
+        let plain = crate::compress(&b"not blocked"[..], Vec::new(), 6).unwrap();
+        assert_eq!(Index::scan(&mut Cursor::new(&plain)).unwrap_err().to_string(), "not in BGZF format");
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::Arc;
+use crate::error::Warning;
+use crate::formats::bgzf::Bgzf;
+use crate::formats::gz::{Gzip, Subfield};
+use crate::formats::lzh::Lzh;
+use crate::formats::lzw::Lzw;
+use crate::formats::pack::Pack;
+use crate::formats::pkzip::Pkzip;
+use crate::formats::list::ListEntry;
+use crate::formats::zlib::{Raw, Zlib};
+use crate::formats::{sniff, Sniffed};
+use crate::options::Options;
+use crate::util::WorkData;
+
+/// One compressed format: how to recognize it, read its header, decode, encode, test and list
+/// it. Data is streamed, so memory use never depends on the size of the input.
+///
+/// Codecs are stateless; everything a codec learns about one stream is kept in the `Header`
+/// it reads from the front of that stream. Adding a format means implementing this trait and
+/// registering the codec in a `Registry`.
+pub trait Codec: Send + Sync {
+    /// The name the format is known by, e.g. `gzip`
+    fn name (&self) -> &'static str;
+
+    /// Whether a stream starting with `magic` is in this format. `magic` holds the first
+    /// `MAGIC_LEN` bytes of the stream, or all of it if it is shorter.
+    fn detect (&self, magic: &[u8]) -> bool;
+
+    /// Reads the header from the front of `input`, leaving it positioned just past the header
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header>;
+
+    /// Decompresses `input`, which must be positioned just past the header `header` was read
+    /// from, into `output`. Returns what was found along the way; codecs that don't count
+    /// members leave `members` at 0.
+    fn decode (&self, header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested>;
+
+    /// Decompresses `input` like `decode` without writing the data anywhere, checking every
+    /// integrity value the format records. The error explains the first problem found.
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested>;
+
+    /// Compresses all of `input` into `output`. Formats that can only be read leave this
+    /// unimplemented.
+    fn encode (&self, _input: &mut dyn Read, _output: &mut dyn Write, _wdata: Option<WorkData>,
+        _opt: &Options) -> io::Result<()>
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} compression is not supported", self.name())))
+    }
+
+    /// Describes the compressed `file` for `--list`, with one entry per file an archive holds.
+    /// By default only what the header and the size of the file tell is reported, whatever
+    /// `opt` asks for.
+    fn list (&self, file: &mut File, _opt: &Options) -> io::Result<Vec<ListEntry>> {
+        let compressed_size = file.metadata()?.len();
+        let header = self.read_header(&mut io::BufReader::new(file))?;
+        Ok(vec![ListEntry {
+            method: self.name(),
+            crc32: None,
+            mtime: header.mtime,
+            compressed_size,
+            uncompressed_size: None,
+            header_size: header.raw.len() as u64,
+            stored_name: header.name,
+            subfields: header.subfields,
+            comment: header.comment,
+            members: Vec::new(),
+            trailing_garbage: false,
+            path: None
+        }])
+    }
+}
+
+/// The metadata a codec reads from the front of a compressed stream
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// the original file name
+    pub name: Option<String>,
+    /// the modification time of the original file, in seconds since the epoch
+    pub mtime: Option<i64>,
+    pub comment: Option<String>,
+    /// the subfields of a gzip extra field
+    pub subfields: Vec<Subfield>,
+    /// the header exactly as it was read from the stream, for codecs that need more of it
+    /// than the fields above
+    pub raw: Vec<u8>
+}
+
+/// What `Codec::test` or `Codec::decode` found in a stream whose data checked out
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tested {
+    /// how many members the stream held
+    pub members: u64,
+    /// the total decompressed size
+    pub size: u64,
+    /// whether data other than zero padding followed the last member
+    pub trailing_garbage: bool,
+    /// whether the stream was an archive with more entries than the one decompressed
+    pub more_entries: bool
+}
+
+impl Tested {
+    /// The warning gzip gives about a stream that decompressed fine, if any
+    pub(crate) fn warning (&self) -> Option<Warning> {
+        if self.more_entries {
+            Some(Warning::MoreEntries)
+        }
+        else if self.trailing_garbage {
+            Some(Warning::TrailingGarbage)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// The codecs rstzip recognizes compressed data with, and compresses with. Cloning a registry
+/// is cheap, as the codecs themselves are shared.
+///
+/// ```
+/// let registry = rstzip::Registry::new();
+/// let codec = registry.detect(&[0x1f, 0x8b, 8, 0]).unwrap();
+/// assert_eq!(codec.name(), "gzip");
+/// assert!(registry.detect(b"text").is_none());
+/// ```
+#[derive(Clone)]
+pub struct Registry {
+    codecs: Vec<Arc<dyn Codec>>
+}
+
+impl Registry {
+    /// A registry holding the built-in codecs
+    pub fn new () -> Self {
+        let mut registry = Registry { codecs: Vec::new() };
+        registry.register(Pkzip);
+        registry.register(Lzw);
+        registry.register(Pack);
+        registry.register(Lzh);
+        registry.register(Raw);
+        registry.register(Bgzf);
+        registry.register(Zlib);
+        registry.register(Gzip);
+        registry
+    }
+
+    /// Adds `codec`. Codecs registered later are tried first, so a registered codec can take
+    /// over a built-in format.
+    pub fn register<C: Codec + 'static> (&mut self, codec: C) {
+        self.codecs.insert(0, Arc::new(codec));
+    }
+
+    /// The codec for data starting with `magic`, if any
+    pub fn detect (&self, magic: &[u8]) -> Option<&dyn Codec> {
+        self.codecs.iter().find(|codec| codec.detect(magic)).map(|codec| &**codec)
+    }
+
+    /// The codec named `name`, if any
+    pub fn get (&self, name: &str) -> Option<&dyn Codec> {
+        self.codecs.iter().find(|codec| codec.name() == name).map(|codec| &**codec)
+    }
+
+    /// Every codec, in the order they are tried
+    pub fn codecs (&self) -> impl Iterator<Item = &dyn Codec> {
+        self.codecs.iter().map(|codec| &**codec)
+    }
+
+    /// Identifies the format of `input` from its magic number, returning its codec and a
+    /// reader that still yields the whole stream
+    pub(crate) fn sniff<R: Read> (&self, input: R) -> io::Result<(Option<&dyn Codec>, Sniffed<R>)> {
+        let (magic, input) = sniff(input)?;
+        Ok((self.detect(&magic), input))
+    }
+}
+
+impl Default for Registry {
+    fn default () -> Self {
+        Registry::new()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.codecs.iter().map(|codec| codec.name())).finish()
+    }
+}
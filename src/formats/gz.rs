@@ -1,36 +1,61 @@
 use crate::util::WorkData;
-use crate::formats::zip::Test;
-use crate::formats::list::List;
-use crate::formats::zip::Zip;
-use std::path::PathBuf;
+use crate::formats::bgzf::{self, Bgzf};
+use crate::formats::codec::{Codec, Header, Tested};
+use crate::formats::list::ListEntry;
 use std::convert::{TryFrom, TryInto};
-use std::io::{Error, ErrorKind, Read, Write};
-use crate::{util, Opt, zip};
-use chrono::DateTime;
-use chrono::offset::{Local, TimeZone};
-use crate::util::WrappedFile;
-use flate2::{GzBuilder, Compression};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use crate::{parallel, rsyncable, util, zip};
+use crate::options::Options;
+use flate2::{GzBuilder, Compression, Crc, CrcReader};
+use flate2::write::DeflateEncoder;
 use flate2::read::MultiGzDecoder;
-use crate::constants;
 
-#[derive(Debug)]
+mod extra;
+mod header;
+mod index;
+mod inflate;
+mod members;
+pub use extra::Subfield;
+pub use header::{HeaderError, HeaderParser, MAX_COMMENT, MAX_NAME};
+pub use index::{index_path, GzIndex, IndexedGz};
+pub use members::{Member, Members};
+
+/// the CRC32 and size that end every member
+const TRAILER_LEN: usize = 8;
+
+/// The metadata of a gzip stream. Only the header is held in memory: the compressed body is
+/// streamed from the reader the header was parsed from when decompressing.
+#[derive(Debug, Clone)]
 pub struct GzFile {
-    pub path: Option<PathBuf>,
     compression_method: u8,
-    pub mtime: u32,
-    pub stored_filename: Option<String>,
+    mtime: u32,
+    stored_filename: Option<String>,
     flag: GzFlags,
     comment: Option<String>,
     os: u8,
-    data: Vec<u8>,
-    uncompressed_size: u32,
-    crc32: u32,
     hcrc16: Option<u16>,
-    xfield: Option<(u16, Vec<u8>)>,
-    raw: Vec<u8>
+    xfield: Option<Vec<u8>>,
+    /// the header exactly as it was read, replayed ahead of the body on decompression
+    header: Vec<u8>,
+    /// size of the whole compressed file, when the input is a seekable file
+    compressed_size: u64,
+    uncompressed_size: u32,
+    crc32: u32
 }
 
+/// The optional fields written into the header of a member being compressed
 #[derive(Debug)]
+struct Fields<'a> {
+    name: Option<&'a str>,
+    mtime: Option<u32>,
+    /// the contents of the extra field, already laid out as subfields
+    extra: Option<Vec<u8>>,
+    comment: Option<&'a str>,
+    hcrc: bool
+}
+
+#[derive(Debug, Clone)]
 struct GzFlags {
     pub ftext: bool,
     pub fhcrc: bool,
@@ -39,183 +64,297 @@ struct GzFlags {
     pub fcomment: bool
 }
 
-impl TryFrom<Vec<u8>> for GzFile {
-    type Error = std::io::Error;
-    fn try_from (buf: Vec<u8>) -> Result<Self, Self::Error> {
-        if buf.len() < GzFile::HEADER_SIZE_USIZE {
-            return Err(Error::new(ErrorKind::InvalidInput, "invalid compressed file size"));
-        }
-        if !Self::is_magic_num(&buf[0..2]) {
-            println!("uh oh, not a .gz-file");
-            return Err(Error::new(ErrorKind::InvalidInput, "invalid compressed file type"));
-        }
-
-        let compression_method = buf[2];
-        let flag = buf[3];
-        let mtime: u32 = util::shift_left(4, &buf[4..8]);
-        let os = buf[9];
-        let flag = GzFile::parse_flags(flag);
-        let mut pos: usize = 10;
-        let mut xfield = None;
-        let mut hcrc16: Option<u16> = None;
-
-        if flag.fextra {
-            let extra_field_size: u16 = util::shift_left(2, &buf[pos..pos+2])
-                                            .try_into().unwrap();
-            pos += 2;
-            let size: usize = extra_field_size.into();
-            let extra_field = &buf[pos..(pos+size)];
-            pos += size;
-            xfield = Some((extra_field_size, extra_field.to_vec()));
-        }
-
-        let stored_filename = util::get_str_if_set(&mut pos, buf.as_slice(), flag.fname);
-        let comment = util::get_str_if_set(&mut pos, buf.as_slice(), flag.fcomment);
-
-        if flag.fhcrc {
-            hcrc16 = Some(util::shift_left(2, &buf[pos..pos+2]).try_into().unwrap());
-            pos += 2;
-        }
-
-        let data = buf[pos..buf.len()-8].to_vec();
-        pos = buf.len() - 8;
-        let crc32 = util::shift_left(4, &buf[pos..pos+4]);
-        pos += 4;
-        let uncompressed_size = util::shift_left(4, &buf[pos..pos+4]);
-        // pos += 4;
-        Ok(GzFile {
-            path: None,
-            compression_method,
-            mtime,
-            stored_filename,
-            flag,
-            comment,
-            os,
-            data,
-            uncompressed_size,
-            crc32,
-            hcrc16,
-            xfield,
-            raw: buf
+/// The gzip format (RFC 1952), as a codec
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Gzip;
+
+impl Codec for Gzip {
+    fn name (&self) -> &'static str {
+        "gzip"
+    }
+
+    fn detect (&self, magic: &[u8]) -> bool {
+        GzFile::is_magic_num(magic)
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> std::io::Result<Header> {
+        let gz = GzFile::read_header(input)?;
+        Ok(Header {
+            name: gz.stored_filename.clone(),
+            mtime: Some(gz.mtime.into()),
+            comment: gz.comment.clone(),
+            subfields: gz.subfields().unwrap_or_default(),
+            raw: gz.header
         })
     }
-}
 
-impl TryFrom<WrappedFile<'_, '_>> for GzFile
+    fn decode (&self, header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> std::io::Result<Tested> {
+        Members::with_output(Cursor::new(&header.raw[..]).chain(input), output).tested()
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> std::io::Result<Tested> {
+        Members::new(Cursor::new(&header.raw[..]).chain(input)).tested()
+    }
+
+    fn encode (&self, input: &mut dyn Read, output: &mut dyn Write, wdata: Option<WorkData>,
+        opt: &Options) -> std::io::Result<()>
     {
+        GzFile::compress(input, output, wdata, opt).map(|_| ())
+    }
+
+    /// Describes `file` from its first header and the trailer at its end, as gzip does, which
+    /// only describes the last member of a file holding several. With `--verbose` the members
+    /// are walked instead, decompressing all of them, for the sizes and CRC of the whole data
+    /// and a line per member. A BGZF file is described from the sizes its block headers record.
+    fn list (&self, file: &mut File, opt: &Options) -> std::io::Result<Vec<ListEntry>> {
+        let gz = GzFile::try_from(&*file)?;
+        if gz.is_bgzf() {
+            return Bgzf.list(file, opt);
+        }
+        if opt.verbose == 0 {
+            return Ok(vec![gz.entry()]);
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let mut members = Members::new(BufReader::new(&*file));
+        let found = (&mut members).collect::<std::io::Result<Vec<Member>>>()?;
+        Ok(vec![gz.members_entry(members.crc32(), found, members.trailing_garbage())])
+    }
+}
+
+impl TryFrom<&File> for GzFile {
     type Error = std::io::Error;
-    fn try_from (wf: WrappedFile) -> Result<Self, Self::Error> {
-        let mut buf: Vec<u8> = Vec::new();
-        let mut f = wf.file;
-        f.read_to_end(&mut buf)?;
-        let mut gz_file: GzFile = TryFrom::try_from(buf)?;
-        gz_file.path = Some(wf.path.to_path_buf());
+    /// Reads the header from the front of the file and the CRC and size from the trailer at its
+    /// end, without reading the compressed body.
+    fn try_from (mut f: &File) -> Result<Self, Self::Error> {
+        let mut gz_file = GzFile::read_header(&mut BufReader::new(f))?;
+        gz_file.compressed_size = f.seek(SeekFrom::End(0))?;
+        if gz_file.compressed_size < (gz_file.header.len() + TRAILER_LEN) as u64 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "invalid compressed file size"));
+        }
+        f.seek(SeekFrom::End(-8))?;
+        let mut trailer = [0u8; 8];
+        f.read_exact(&mut trailer)?;
+        gz_file.crc32 = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        gz_file.uncompressed_size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
         Ok(gz_file)
     }
 }
 
-impl List for GzFile {
+fn header_crc16 (header: &[u8]) -> u16 {
+    let mut crc = Crc::new();
+    crc.update(header);
+    crc.sum() as u16
+}
 
-    fn header_size(&self) -> f64 {
-        return 18.0;
+impl GzFile {
+    /// The listing of a file this is the first header of, from the trailer at its end
+    fn entry (&self) -> ListEntry {
+        ListEntry {
+            method: "defla",
+            crc32: Some(self.crc32),
+            mtime: Some(self.mtime.into()),
+            compressed_size: self.compressed_size,
+            uncompressed_size: Some(self.uncompressed_size.into()),
+            header_size: (self.header.len() + TRAILER_LEN) as u64,
+            stored_name: self.stored_filename.clone(),
+            subfields: self.subfields().unwrap_or_default(),
+            comment: self.comment.clone(),
+            members: Vec::new(),
+            trailing_garbage: false,
+            path: None
+        }
     }
 
-    fn list(&self, opt: &Opt) -> (f64, f64) {
-        if opt.verbose > 0 {
-            let dt = self.modified_on();
-            print!("{:<8}{:<12x}{:<8}{:<8}", "defla", self.crc32, Self::datestring(&dt), Self::timestring(&dt))
+    /// The listing of a file this is the first header of, holding `members` whose data has
+    /// the CRC `crc32` as a whole
+    fn members_entry (&self, crc32: u32, members: Vec<Member>, trailing_garbage: bool) -> ListEntry {
+        ListEntry {
+            crc32: Some(crc32),
+            uncompressed_size: Some(members.iter().map(|member| member.size).sum()),
+            header_size: members.iter().map(Member::header_size).sum(),
+            members,
+            trailing_garbage,
+            ..self.entry()
         }
-        let uncompressed_filename: String = Self::get_filename_str (&self.stored_filename, &self.path, opt);
-        let compressed_size: u32 = self.raw.len().try_into().unwrap();
-        let compressed_size: f64 = match Self::bytes_bound_check(compressed_size.try_into().unwrap(), opt, true, self.header_size()) {
-            Ok(s) => s,
-            Err(_) => {
-                eprintln!("{}: number of bytes in compressed file is less than header size", constants::PROGRAM_NAME);
-                std::process::exit(constants::ERROR.into());
-            }
-        };
-        let uncompressed_size: f64 = match Self::bytes_bound_check(self.uncompressed_size.try_into().unwrap(), opt, false, self.header_size()) {
-            Ok(s) => s,
-            Err(_) => {
-                eprintln!("{}: number of bytes in compressed file is less than header size", constants::PROGRAM_NAME);
-                std::process::exit(constants::ERROR.into());
-            }
-        };
-        println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t", compressed_size, self.uncompressed_size,
-            Self::calculate_ratio(compressed_size, Some(uncompressed_size), self.header_size()), uncompressed_filename);
-        return (compressed_size, uncompressed_size)
     }
-}
 
-impl Zip for GzFile {
-    fn compress<R: Read>(input: R, wdata: Option<WorkData>, opt: &Opt) -> Result<Vec<u8>, std::io::Error> {
-        let os = GzFile::os();
-        let gz = match wdata {
-            Some(wdata) => GzBuilder::new()
-                            .filename(wdata.orig_name.unwrap().trim_end_matches(char::from(0)))
-                            .mtime(wdata.mtime.unwrap().try_into().unwrap())
-                            .operating_system(os)
-                            .write(Vec::new(), Compression::new(opt.level.try_into().unwrap())),
-            None => GzBuilder::new()
-                        .operating_system(os)
-                        .write(Vec::new(), Compression::new(opt.level.try_into().unwrap()))
+    /// Compresses all of `input` into `output` as a single gzip member, as configured by
+    /// `opt`, returning the writer once the trailer has been written
+    pub fn compress<R: Read, W: Write>(input: R, output: W, wdata: Option<WorkData>, opt: &Options) -> Result<W, std::io::Error> {
+        let level = Compression::new(opt.level.min(9));
+        let (name, mtime) = match wdata {
+            Some(wdata) => (wdata.orig_name.filter(|_| opt.name),
+                wdata.mtime.filter(|_| opt.time).and_then(|t| t.try_into().ok())),
+            None => (None, None)
+        };
+        let extra = if opt.extra.is_empty() {
+            None
+        }
+        else {
+            Some(Subfield::encode_all(&opt.extra)?)
         };
-        let compressed = zip::from_encoder(input, gz)?;
-        Ok(compressed)
+        let fields = Fields { name: name.as_deref(), mtime, extra, comment: opt.comment.as_deref(),
+            hcrc: opt.header_crc };
+        if let Some(threads) = opt.threads {
+            return Self::compress_raw(input, output, &fields, level,
+                |input, output| parallel::deflate(input, output, level, threads));
+        }
+        if opt.rsyncable {
+            return Self::compress_raw(input, output, &fields, level,
+                |input, output| rsyncable::deflate(input, output, level));
+        }
+        if fields.hcrc || fields.extra.is_some() || fields.comment.is_some() {
+            return Self::compress_raw(input, output, &fields, level,
+                |input, output| deflate(input, output, level));
+        }
+        Self::encode(input, output, name.as_deref(), mtime, level)
     }
 
-    fn compress_into<R: Read>(input: R, wdata: Option<WorkData>, opt: &Opt) -> Result<Box<Self>, std::io::Error> {
-        let res = Self::compress(input, wdata, opt)?;
-        let s = std::convert::TryFrom::try_from (res)?;
-        Ok(Box::new(s))
+    /// Decompresses `input`, which must be positioned immediately after the header that `self`
+    /// was read from, into `output`. Returns the number of bytes written.
+    pub fn decompress<R: Read, W: Write> (&self, input: R, output: &mut W) -> Result<u64, std::io::Error> {
+        let mut gz = MultiGzDecoder::new(Cursor::new(&self.header[..]).chain(input));
+        std::io::copy(&mut gz, output)
     }
 
-    fn decompress (self) -> Result<Vec<u8>, std::io::Error> {
-        let mut gz = MultiGzDecoder::new(&self.raw[..]);
-        let mut outbuf: Vec<u8> = Vec::new();
-        gz.read_to_end(&mut outbuf)?;
-        Ok(outbuf)
+    /// Checks the integrity of `input`, which must be positioned immediately after the header
+    /// that `self` was read from, without writing the data anywhere
+    pub fn test<R: Read> (self, input: R) -> Result<Tested, std::io::Error> {
+        Members::new(Cursor::new(&self.header[..]).chain(BufReader::new(input))).tested()
     }
 }
 
-impl Test for GzFile {
-    fn test (self, opt: &Opt) -> bool {
-        fn err(opt: &Opt) -> bool {
-            if opt.verbose > 0 {
-                println!(" CORRUPTED");
-            }
-            return false
-        }
-
-        match GzFile::decompress(self) {
-            Ok(b) => {
-                if let Err(_) = std::io::stdout().write_all(b.as_slice()){
-                    return err(opt);
-                }
-                if let Err(_) = std::io::stdout().flush() {
-                    return err(opt);
-                }
-                if opt.verbose > 0 {
-                    println!(" OK");
-                }
-                true
-            }
-            Err(_) => {
-                return err(opt);
-            }
+/// Deflates all of `input` into `output` on this thread, returning the CRC of the input
+fn deflate<R: Read, W: Write> (input: R, output: &mut W, level: Compression) -> std::io::Result<Crc> {
+    let mut input = CrcReader::new(input);
+    let mut encoder = DeflateEncoder::new(output, level);
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    let mut crc = Crc::new();
+    crc.combine(input.crc());
+    Ok(crc)
+}
+
+/// Reads `input` to the end, returning whether it held nothing but zero bytes
+pub(crate) fn only_zeros<R: BufRead> (mut input: R) -> std::io::Result<bool> {
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(true);
+        }
+        if buf.iter().any(|&b| b != 0) {
+            return Ok(false);
         }
+        let n = buf.len();
+        input.consume(n);
     }
 }
 
 impl GzFile {
-    const HEADER_SIZE_USIZE: usize = 18;
-
     pub fn is_magic_num (bytes: &[u8]) -> bool {
-        return bytes[0] == 31 && bytes[1] == 139
+        bytes.starts_with(&[31, 139])
+    }
+
+    /// Reads the gzip header from the front of `input`, leaving `input` positioned at the start
+    /// of the compressed data so it can be handed to `decompress`
+    pub fn read_header<R: Read + ?Sized> (input: &mut R) -> std::io::Result<Self> {
+        let mut parser = HeaderParser::new();
+        let mut buf = [0u8; 64];
+        while !parser.is_done() {
+            let wanted = parser.wanted().min(buf.len());
+            let n = match input.read(&mut buf[..wanted]) {
+                Ok(0) => return Err(HeaderError::Truncated.into()),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            parser.feed(&buf[..n])?;
+        }
+        Ok(parser.finish()?)
+    }
+
+    /// The original file name stored in the header (FNAME)
+    pub fn name (&self) -> Option<&str> {
+        self.stored_filename.as_deref()
+    }
+
+    /// The modification time stored in the header, in seconds since the epoch. 0 means no
+    /// time was stored.
+    pub fn mtime (&self) -> u32 {
+        self.mtime
+    }
+
+    /// The comment stored in the header (FCOMMENT)
+    pub fn comment (&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// The compression method (CM). 8 is deflate, the only method in use.
+    pub fn compression_method (&self) -> u8 {
+        self.compression_method
+    }
+
+    /// The operating system the file was compressed on (OS), as numbered by RFC 1952
+    pub fn os (&self) -> u8 {
+        self.os
+    }
+
+    /// Whether the compressor flagged the contents as probably text (FTEXT)
+    pub fn is_text (&self) -> bool {
+        self.flag.ftext
+    }
+
+    /// The raw contents of the extra field (FEXTRA), without its length
+    pub fn extra (&self) -> Option<&[u8]> {
+        self.xfield.as_deref()
+    }
+
+    /// The subfields of the extra field, in the order they were stored. Empty when there is no
+    /// extra field.
+    pub fn subfields (&self) -> Result<Vec<Subfield>, HeaderError> {
+        Subfield::parse_all(self.extra().unwrap_or(&[]))
     }
 
-    pub fn modified_on(&self) -> DateTime<Local> {
-        Local.timestamp (self.mtime.into(), 0)
+    /// Whether this is the header of a BGZF block: its extra field records the size of the
+    /// block in a `BC` subfield
+    pub fn is_bgzf (&self) -> bool {
+        self.xfield.is_some() && bgzf::block_size(&self.subfields().unwrap_or_default()).is_some()
+    }
+
+    /// The CRC16 of the header (FHCRC), when one was stored
+    pub fn header_crc (&self) -> Option<u16> {
+        self.hcrc16
+    }
+
+    /// The length of the header in bytes
+    pub fn header_len (&self) -> usize {
+        self.header.len()
+    }
+
+    /// The CRC32 and uncompressed size recorded in the trailer. Only known when the header was
+    /// read from a seekable file.
+    pub fn trailer (&self) -> Option<(u32, u32)> {
+        if self.compressed_size > 0 {
+            Some((self.crc32, self.uncompressed_size))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Compresses `input` into `output` as a single gzip member, storing `name` and `mtime` in
+    /// the header when given
+    pub fn encode<R: Read, W: Write> (input: R, output: W, name: Option<&str>, mtime: Option<u32>,
+        level: Compression) -> std::io::Result<W>
+    {
+        let mut builder = GzBuilder::new().operating_system(Self::host_os());
+        if let Some(name) = name {
+            builder = builder.filename(name.trim_end_matches(char::from(0)));
+        }
+        if let Some(mtime) = mtime {
+            builder = builder.mtime(mtime);
+        }
+        zip::from_encoder(input, builder.write(output, level))
     }
 
     fn parse_flags (byte: u8) -> GzFlags {
@@ -228,7 +367,74 @@ impl GzFile {
         }
     }
 
-    pub fn os () -> u8 {
+    /// Compresses `input` as a single gzip member whose raw deflate body is produced by
+    /// `deflate`, for encoders that `GzBuilder` cannot drive (see `parallel` and `rsyncable`)
+    fn compress_raw<R, W, F> (input: R, mut output: W, fields: &Fields, level: Compression, deflate: F)
+        -> Result<W, std::io::Error>
+    where
+        R: Read,
+        W: Write,
+        F: FnOnce(R, &mut W) -> std::io::Result<Crc>
+    {
+        output.write_all(&Self::header_bytes(fields, level))?;
+        let crc = deflate(input, &mut output)?;
+        output.write_all(&crc.sum().to_le_bytes())?;
+        output.write_all(&crc.amount().to_le_bytes())?;
+        Ok(output)
+    }
+
+    /// Builds a gzip member header laid out the same way as `GzBuilder`'s, followed by its
+    /// CRC16 when `fields.hcrc` is set
+    fn header_bytes (fields: &Fields, level: Compression) -> Vec<u8> {
+        let mut flg = 0u8;
+        if fields.extra.is_some() {
+            flg |= 0b0000_0100;
+        }
+        if fields.name.is_some() {
+            flg |= 0b0000_1000;
+        }
+        if fields.comment.is_some() {
+            flg |= 0b0001_0000;
+        }
+        if fields.hcrc {
+            flg |= 0b0000_0010;
+        }
+        let xfl = if level.level() >= Compression::best().level() {
+            2
+        }
+        else if level.level() <= Compression::fast().level() {
+            4
+        }
+        else {
+            0
+        };
+        let mut header: Vec<u8> = vec![31, 139, 8, flg];
+        header.extend_from_slice(&fields.mtime.unwrap_or(0).to_le_bytes());
+        header.push(xfl);
+        header.push(Self::host_os());
+        if let Some(extra) = &fields.extra {
+            // encode_all keeps the field within 65535 bytes
+            header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            header.extend_from_slice(extra);
+        }
+        if let Some(name) = fields.name {
+            header.extend_from_slice(name.trim_end_matches(char::from(0)).as_bytes());
+            header.push(0);
+        }
+        if let Some(comment) = fields.comment {
+            // the comment ends at its first NUL
+            header.extend_from_slice(comment.split('\0').next().unwrap_or("").as_bytes());
+            header.push(0);
+        }
+        if fields.hcrc {
+            let crc = header_crc16(&header);
+            header.extend_from_slice(&crc.to_le_bytes());
+        }
+        header
+    }
+
+    /// The OS byte written into the headers of files compressed on this system
+    pub fn host_os () -> u8 {
         #[cfg(target_os = "windows")]
         return 0;
         #[cfg(any(target_os = "ios", target_os = "macos", target_os = "linux"))]
@@ -237,3 +443,97 @@ impl GzFile {
         return 255;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_then_streamed_body () {
+        let opt = Options::default();
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = GzFile::compress(&data[..], Vec::new(), None, &opt).unwrap();
+
+        let mut input = &compressed[..];
+        let gz = GzFile::read_header(&mut input).unwrap();
+        // only the header may have been consumed from the input
+        assert_eq!(input.len(), compressed.len() - gz.header.len());
+        let mut out: Vec<u8> = Vec::new();
+        let n = gz.decompress(input, &mut out).unwrap();
+        assert_eq!(n, data.len() as u64);
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_padding_and_garbage () {
+        let member = GzFile::encode(&b"one small step"[..], Vec::new(), None, None, Compression::default()).unwrap();
+        let tested = |data: Vec<u8>| {
+            let mut input = &data[..];
+            let gz = GzFile::read_header(&mut input).unwrap();
+            gz.test(input).unwrap()
+        };
+        let two = tested([&member[..], &member[..]].concat());
+        assert_eq!((two.members, two.size, two.trailing_garbage), (2, 28, false));
+        assert!(!tested([&member[..], &[0u8; 512][..]].concat()).trailing_garbage);
+        assert!(tested([&member[..], &b"\0\0junk"[..]].concat()).trailing_garbage);
+    }
+
+    #[test]
+    fn list_from_trailer_or_members () {
+        let path = std::env::temp_dir().join(format!("rstzip-gz-list-{}", std::process::id()));
+        let wdata = WorkData { orig_name: Some(String::from("a-rather-long-name.txt")), mtime: Some(util::Timespec(1, 0)),
+            ofname: String::from("a-rather-long-name.txt.gz") };
+        let member = GzFile::compress(&b"tiny"[..], Vec::new(), Some(wdata), &Options::default()).unwrap();
+        let header_size = GzFile::read_header(&mut &member[..]).unwrap().header_len() as u64 + 8;
+        assert_eq!(header_size, 41);
+        let listed = |data: &[u8], verbose: u8| {
+            std::fs::write(&path, data).unwrap();
+            let mut opt = Options::default();
+            opt.verbose = verbose;
+            Gzip.list(&mut File::open(&path).unwrap(), &opt).unwrap().remove(0)
+        };
+
+        // one member is described from its trailer alone
+        let single = listed(&member, 0);
+        assert!(single.members.is_empty());
+        assert_eq!((single.uncompressed_size, single.header_size), (Some(4), header_size));
+        let walked = listed(&member, 1);
+        assert_eq!(walked.members.len(), 1);
+        assert_eq!((walked.uncompressed_size, walked.header_size), (Some(4), header_size));
+
+        // without --verbose, only the last member's trailer is read, as in gzip
+        let two = [&member[..], &member[..]].concat();
+        let last = listed(&two, 0);
+        assert!(last.members.is_empty());
+        assert_eq!((last.uncompressed_size, last.header_size), (Some(4), header_size));
+        let two = listed(&two, 1);
+        assert_eq!(two.members.len(), 2);
+        assert_eq!((two.uncompressed_size, two.header_size), (Some(8), 2 * header_size));
+        assert!(!two.trailing_garbage);
+        assert!(listed(&[&member[..], &b"junk"[..]].concat(), 1).trailing_garbage);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn header_crc () {
+        let mut opt = Options::default();
+        opt.header_crc = true;
+        let wdata = WorkData { orig_name: Some(String::from("moon.txt")), mtime: Some(util::Timespec(1, 0)),
+            ofname: String::from("moon.txt.gz") };
+        let compressed = GzFile::compress(&b"Tranquility Base"[..], Vec::new(), Some(wdata), &opt).unwrap();
+
+        let mut input = &compressed[..];
+        let gz = GzFile::read_header(&mut input).unwrap();
+        assert_eq!(gz.name(), Some("moon.txt"));
+        assert!(gz.header_crc().is_some());
+        let mut out: Vec<u8> = Vec::new();
+        gz.decompress(input, &mut out).unwrap();
+        assert_eq!(out, b"Tranquility Base");
+
+        let mut corrupt = compressed.clone();
+        corrupt[10] = b'n';
+        let err = GzFile::read_header(&mut &corrupt[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("header checksum"));
+    }
+}
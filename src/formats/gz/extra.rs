@@ -0,0 +1,130 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use super::HeaderError;
+
+/// One subfield of a gzip extra field (FEXTRA), as laid out by RFC 1952: a two byte ID, SI1
+/// and SI2, followed by a little endian length and that many bytes of data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subfield {
+    /// SI1 and SI2. IDs whose SI2 is 0 are reserved.
+    pub id: [u8; 2],
+    pub data: Vec<u8>
+}
+
+impl Subfield {
+    pub fn new (id: [u8; 2], data: Vec<u8>) -> Self {
+        Subfield { id, data }
+    }
+
+    /// Splits the contents of an extra field into its subfields. The field must consist of
+    /// whole subfields; a length running past the end of the field is `CorruptExtra`.
+    pub fn parse_all (mut extra: &[u8]) -> Result<Vec<Subfield>, HeaderError> {
+        let mut subfields = Vec::new();
+        while !extra.is_empty() {
+            if extra.len() < 4 {
+                return Err(HeaderError::CorruptExtra);
+            }
+            let len = usize::from(u16::from_le_bytes([extra[2], extra[3]]));
+            let data = extra.get(4..4 + len).ok_or(HeaderError::CorruptExtra)?;
+            subfields.push(Subfield::new([extra[0], extra[1]], data.to_vec()));
+            extra = &extra[4 + len..];
+        }
+        Ok(subfields)
+    }
+
+    /// Lays `subfields` out as the contents of an extra field, failing if they don't fit in
+    /// the 65535 bytes the field can hold
+    pub fn encode_all (subfields: &[Subfield]) -> io::Result<Vec<u8>> {
+        let mut extra = Vec::new();
+        for subfield in subfields {
+            let len = u16::try_from(subfield.data.len()).map_err(|_| too_long())?;
+            extra.extend_from_slice(&subfield.id);
+            extra.extend_from_slice(&len.to_le_bytes());
+            extra.extend_from_slice(&subfield.data);
+        }
+        if extra.len() > usize::from(u16::MAX) {
+            return Err(too_long());
+        }
+        Ok(extra)
+    }
+
+    /// The ID as text: its two bytes when they are printable ASCII, escaped otherwise
+    pub fn id_str (&self) -> String {
+        self.id.iter().map(|&b| if b.is_ascii_graphic() {
+            char::from(b).to_string()
+        }
+        else {
+            format!("\\x{:02x}", b)
+        }).collect()
+    }
+}
+
+fn too_long () -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "extra field too long")
+}
+
+impl fmt::Display for Subfield {
+    /// Formats the subfield as `ID=hex`, the form `from_str` accepts
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}=", self.id_str())?;
+        for b in &self.data {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Subfield {
+    type Err = String;
+
+    /// Parses `ID=hex`, where ID is the two ASCII characters SI1 and SI2 and hex is the data,
+    /// e.g. `AP=0102ff`
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let (id, hex) = s.split_once('=').ok_or_else(|| format!("'{}' is not of the form ID=hex", s))?;
+        let id = match id.as_bytes() {
+            &[si1, si2] if si1.is_ascii_graphic() && si2.is_ascii_graphic() => [si1, si2],
+            _ => return Err(format!("subfield ID '{}' is not two ASCII characters", id))
+        };
+        if hex.len() % 2 != 0 {
+            return Err(format!("odd number of hex digits in '{}'", hex));
+        }
+        let data = (0..hex.len()).step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| format!("'{}' is not hexadecimal", hex))?;
+        if data.len() > usize::from(u16::MAX) {
+            return Err(String::from("subfield data too long"));
+        }
+        Ok(Subfield::new(id, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip () {
+        let subfields = vec![Subfield::new(*b"AP", vec![1, 2, 0xff]), Subfield::new(*b"BC", vec![])];
+        let extra = Subfield::encode_all(&subfields).unwrap();
+        assert_eq!(extra, b"AP\x03\x00\x01\x02\xffBC\x00\x00");
+        assert_eq!(Subfield::parse_all(&extra).unwrap(), subfields);
+        assert_eq!(Subfield::parse_all(&extra[..extra.len() - 1]).unwrap_err(), HeaderError::CorruptExtra);
+        assert_eq!(Subfield::parse_all(b"AP\x05\x00\x01").unwrap_err(), HeaderError::CorruptExtra);
+        assert!(Subfield::encode_all(&[Subfield::new(*b"AP", vec![0; 70_000])]).is_err());
+    }
+
+    #[test]
+    fn from_str () {
+        let subfield: Subfield = "AP=0102Ff".parse().unwrap();
+        assert_eq!(subfield, Subfield::new(*b"AP", vec![1, 2, 0xff]));
+        assert_eq!(subfield.to_string(), "AP=0102ff");
+        assert_eq!("BC=".parse::<Subfield>().unwrap().data, Vec::<u8>::new());
+        for bad in &["AP", "A=00", "APX=00", "AP=0", "AP=zz", "AP=é0"] {
+            assert!(bad.parse::<Subfield>().is_err(), "{}", bad);
+        }
+        assert_eq!(Subfield::new([1, b'x'], vec![]).id_str(), "\\x01x");
+    }
+}
@@ -0,0 +1,409 @@
+use std::fmt;
+use std::io;
+use super::{GzFile, GzFlags};
+
+/// Why a gzip header could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    /// the input doesn't start with the gzip magic number
+    NotGzip,
+    /// the input ended before the header did
+    Truncated,
+    /// a compression method other than deflate (8)
+    UnknownMethod(u8),
+    /// some of the flag bits RFC 1952 reserves are set
+    ReservedFlags(u8),
+    /// the header CRC16 (FHCRC) doesn't match the header
+    HeaderCrc { stored: u16, computed: u16 },
+    /// the extra field (FEXTRA) doesn't consist of whole subfields
+    CorruptExtra,
+    /// the stored file name (FNAME) is longer than `MAX_NAME` bytes
+    NameTooLong,
+    /// the comment (FCOMMENT) is longer than `MAX_COMMENT` bytes
+    CommentTooLong
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::NotGzip => write!(f, "not in gzip format"),
+            HeaderError::Truncated => write!(f, "unexpected end of file"),
+            HeaderError::UnknownMethod(method) => write!(f, "unknown method {} -- not supported", method),
+            HeaderError::ReservedFlags(flags) => write!(f, "has flags 0x{:02x} -- not supported", flags),
+            HeaderError::HeaderCrc { stored, computed } =>
+                write!(f, "header checksum 0x{:04x} != computed checksum 0x{:04x}", stored, computed),
+            HeaderError::CorruptExtra => write!(f, "corrupted extra field"),
+            HeaderError::NameTooLong => write!(f, "stored file name longer than {} bytes", MAX_NAME),
+            HeaderError::CommentTooLong => write!(f, "comment longer than {} bytes", MAX_COMMENT)
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+impl HeaderError {
+    /// The header error an `io::Error` was made from, if any
+    pub fn of (err: &io::Error) -> Option<&HeaderError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<HeaderError>())
+    }
+}
+
+impl From<HeaderError> for io::Error {
+    fn from (err: HeaderError) -> Self {
+        let kind = match err {
+            HeaderError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+const FIXED_LEN: usize = 10;
+/// the longest stored file name read, as in gzip, which keeps names to 1 KiB
+pub const MAX_NAME: usize = 1024;
+/// the longest comment read or written
+pub const MAX_COMMENT: usize = 64 * 1024;
+const RESERVED_FLAGS: u8 = 0b1110_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Fixed,
+    ExtraLen,
+    /// the extra field ends at this offset
+    Extra(usize),
+    /// the name starts at this offset
+    Name(usize),
+    /// the comment starts at this offset
+    Comment(usize),
+    /// the CRC16 ends at this offset
+    Crc(usize),
+    Done
+}
+
+/// Parses a gzip header handed to it in pieces of any size. Every length is checked against
+/// the bytes actually received, so neither truncated nor hostile input can make it read out
+/// of bounds, and the name and comment are capped, so the header it holds stays small; it also
+/// never consumes a byte past the end of the header.
+///
+/// ```
+/// use rstzip::formats::gz::HeaderParser;
+///
+/// let member = rstzip::compress(&b"hello"[..], Vec::new(), 6).unwrap();
+/// let mut parser = HeaderParser::new();
+/// let mut used = 0;
+/// for piece in member.chunks(3) {
+///     used += parser.feed(piece).unwrap();
+///     if parser.is_done() {
+///         break;
+///     }
+/// }
+/// let header = parser.finish().unwrap();
+/// assert_eq!(used, header.header_len());
+/// ```
+#[derive(Debug)]
+pub struct HeaderParser {
+    state: State,
+    header: Vec<u8>,
+    extra: Option<Vec<u8>>,
+    name: Option<String>,
+    comment: Option<String>,
+    hcrc16: Option<u16>
+}
+
+impl Default for HeaderParser {
+    fn default () -> Self {
+        HeaderParser::new()
+    }
+}
+
+impl HeaderParser {
+    pub fn new () -> Self {
+        HeaderParser {
+            state: State::Fixed,
+            header: Vec::with_capacity(FIXED_LEN),
+            extra: None,
+            name: None,
+            comment: None,
+            hcrc16: None
+        }
+    }
+
+    /// Whether the whole header has been parsed
+    pub fn is_done (&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// How many more bytes the parser is sure to need. It is at least 1 until the header is
+    /// done, so reading exactly this many bytes from a stream never reads past the header.
+    pub fn wanted (&self) -> usize {
+        let len = self.header.len();
+        match self.state {
+            State::Fixed => FIXED_LEN - len,
+            State::ExtraLen => FIXED_LEN + 2 - len,
+            State::Extra(end) => end - len,
+            State::Name(_) | State::Comment(_) => 1,
+            State::Crc(end) => end - len,
+            State::Done => 0
+        }
+    }
+
+    /// Consumes as much of `input` as belongs to the header, returning how many bytes that was.
+    /// Once an error has been returned the parser should be dropped.
+    pub fn feed (&mut self, mut input: &[u8]) -> Result<usize, HeaderError> {
+        let available = input.len();
+        while !input.is_empty() && !self.is_done() {
+            let take = match self.state {
+                State::Name(_) | State::Comment(_) => match input.iter().position(|&b| b == 0) {
+                    Some(nul) => nul + 1,
+                    None => input.len()
+                },
+                _ => self.wanted().min(input.len())
+            };
+            self.header.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            self.advance()?;
+        }
+        Ok(available - input.len())
+    }
+
+    /// The parsed header, or `Truncated` if it isn't complete
+    pub fn finish (self) -> Result<GzFile, HeaderError> {
+        if !self.is_done() {
+            return Err(HeaderError::Truncated);
+        }
+        let fixed = &self.header[..FIXED_LEN];
+        Ok(GzFile {
+            compression_method: fixed[2],
+            mtime: u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+            stored_filename: self.name,
+            flag: GzFile::parse_flags(fixed[3]),
+            comment: self.comment,
+            os: fixed[9],
+            hcrc16: self.hcrc16,
+            xfield: self.extra,
+            header: self.header,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            crc32: 0
+        })
+    }
+
+    fn flags (&self) -> GzFlags {
+        GzFile::parse_flags(self.header[3])
+    }
+
+    /// Moves on through every state the bytes received so far complete
+    fn advance (&mut self) -> Result<(), HeaderError> {
+        loop {
+            let len = self.header.len();
+            match self.state {
+                State::Fixed => {
+                    if self.header.first().is_some_and(|&b| b != 0x1f)
+                        || self.header.get(1).is_some_and(|&b| b != 0x8b) {
+                        return Err(HeaderError::NotGzip);
+                    }
+                    if len < FIXED_LEN {
+                        return Ok(());
+                    }
+                    if self.header[2] != 8 {
+                        return Err(HeaderError::UnknownMethod(self.header[2]));
+                    }
+                    if self.header[3] & RESERVED_FLAGS != 0 {
+                        return Err(HeaderError::ReservedFlags(self.header[3]));
+                    }
+                    self.state = if self.flags().fextra { State::ExtraLen } else { self.after_extra() };
+                },
+                State::ExtraLen => {
+                    if len < FIXED_LEN + 2 {
+                        return Ok(());
+                    }
+                    let xlen = u16::from_le_bytes([self.header[FIXED_LEN], self.header[FIXED_LEN + 1]]);
+                    self.state = State::Extra(len + usize::from(xlen));
+                },
+                State::Extra(end) => {
+                    if len < end {
+                        return Ok(());
+                    }
+                    self.extra = Some(self.header[FIXED_LEN + 2..end].to_vec());
+                    self.state = self.after_extra();
+                },
+                State::Name(start) | State::Comment(start) => {
+                    let ended = len > start && self.header[len - 1] == 0;
+                    // the terminating NUL isn't counted
+                    let text_len = len - start - usize::from(ended);
+                    match self.state {
+                        State::Name(_) if text_len > MAX_NAME => return Err(HeaderError::NameTooLong),
+                        State::Comment(_) if text_len > MAX_COMMENT => return Err(HeaderError::CommentTooLong),
+                        _ => ()
+                    }
+                    if !ended {
+                        return Ok(());
+                    }
+                    let text = String::from_utf8_lossy(&self.header[start..len - 1]).into_owned();
+                    if let State::Name(_) = self.state {
+                        self.name = Some(text);
+                        self.state = self.after_name();
+                    }
+                    else {
+                        self.comment = Some(text);
+                        self.state = self.after_comment();
+                    }
+                },
+                State::Crc(end) => {
+                    if len < end {
+                        return Ok(());
+                    }
+                    // the CRC16 is the low half of the CRC32 of everything before it
+                    let stored = u16::from_le_bytes([self.header[end - 2], self.header[end - 1]]);
+                    let computed = super::header_crc16(&self.header[..end - 2]);
+                    if stored != computed {
+                        return Err(HeaderError::HeaderCrc { stored, computed });
+                    }
+                    self.hcrc16 = Some(stored);
+                    self.state = State::Done;
+                },
+                State::Done => return Ok(())
+            }
+        }
+    }
+
+    fn after_extra (&self) -> State {
+        if self.flags().fname { State::Name(self.header.len()) } else { self.after_name() }
+    }
+
+    fn after_name (&self) -> State {
+        if self.flags().fcomment { State::Comment(self.header.len()) } else { self.after_comment() }
+    }
+
+    fn after_comment (&self) -> State {
+        if self.flags().fhcrc {
+            State::Crc(self.header.len() + 2)
+        }
+        else {
+            State::Done
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// A header with every optional field, CRC16 included
+    fn full_header () -> Vec<u8> {
+        let mut header = vec![0x1f, 0x8b, 8, 0b0001_1110, 1, 2, 3, 4, 0, 3];
+        header.extend_from_slice(&[4, 0, b'A', b'B', 0, 0]);
+        header.extend_from_slice(b"name.txt\0a comment\0");
+        let crc = super::super::header_crc16(&header);
+        header.extend_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    fn parse (bytes: &[u8]) -> Result<(GzFile, usize), HeaderError> {
+        let mut parser = HeaderParser::new();
+        let used = parser.feed(bytes)?;
+        Ok((parser.finish()?, used))
+    }
+
+    #[test]
+    fn every_field () {
+        let mut bytes = full_header();
+        let len = bytes.len();
+        bytes.extend_from_slice(b"the body");
+        let (gz, used) = parse(&bytes).unwrap();
+        assert_eq!(used, len);
+        assert_eq!(gz.mtime(), 0x0403_0201);
+        assert_eq!(gz.extra(), Some(&[b'A', b'B', 0, 0][..]));
+        assert_eq!(gz.subfields().unwrap(), vec![super::super::Subfield::new(*b"AB", vec![])]);
+        assert_eq!(gz.name(), Some("name.txt"));
+        assert_eq!(gz.comment(), Some("a comment"));
+        assert!(gz.header_crc().is_some());
+        assert_eq!(gz.header_len(), len);
+    }
+
+    #[test]
+    fn structured_errors () {
+        let header = full_header();
+        assert_eq!(parse(b"PK\x03\x04").unwrap_err(), HeaderError::NotGzip);
+        assert_eq!(parse(b"\x1f").unwrap_err(), HeaderError::Truncated);
+        assert_eq!(parse(&header[..header.len() - 1]).unwrap_err(), HeaderError::Truncated);
+        let mut method = header.clone();
+        method[2] = 9;
+        assert_eq!(parse(&method).unwrap_err(), HeaderError::UnknownMethod(9));
+        let mut reserved = header.clone();
+        reserved[3] |= 0b1000_0000;
+        assert_eq!(parse(&reserved).unwrap_err(), HeaderError::ReservedFlags(0b1001_1110));
+        let mut name = header.clone();
+        name[16] = b'N';
+        match parse(&name).unwrap_err() {
+            HeaderError::HeaderCrc { .. } => {},
+            err => panic!("expected a CRC error, got {:?}", err)
+        }
+        // an extra field longer than the input
+        assert_eq!(parse(&[0x1f, 0x8b, 8, 0b100, 0, 0, 0, 0, 0, 3, 0xff, 0xff, 1]).unwrap_err(),
+            HeaderError::Truncated);
+        // a name or comment that never ends is given up on, NUL or not
+        let fixed = |flags: u8| vec![0x1f, 0x8b, 8, flags, 0, 0, 0, 0, 0, 3];
+        let longest = [&fixed(0b1000)[..], &[b'n'; MAX_NAME][..], &[0][..]].concat();
+        assert_eq!(parse(&longest).unwrap().0.name().map(str::len), Some(MAX_NAME));
+        assert_eq!(parse(&[&fixed(0b1000)[..], &[b'n'; MAX_NAME + 1][..], &[0][..]].concat()).unwrap_err(),
+            HeaderError::NameTooLong);
+        assert_eq!(parse(&[&fixed(0b1_0000)[..], &vec![b'c'; MAX_COMMENT + 1][..]].concat()).unwrap_err(),
+            HeaderError::CommentTooLong);
+    }
+
+    /// Bytes that start like a gzip header, so that parsing gets past the magic number
+    fn gzipish () -> impl Strategy<Value = Vec<u8>> {
+        (any::<u8>(), proptest::collection::vec(any::<u8>(), 0..256)).prop_map(|(flags, rest)| {
+            let mut bytes = vec![0x1f, 0x8b, 8, flags & 0b0001_1111];
+            bytes.extend(rest);
+            bytes
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_never_panic (bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = parse(&bytes);
+            let _ = GzFile::read_header(&mut &bytes[..]);
+        }
+
+        #[test]
+        fn pieces_parse_like_the_whole (bytes in gzipish(), piece in 1usize..16) {
+            let whole = parse(&bytes).map(|(gz, used)| (gz.header, used));
+            let mut parser = HeaderParser::new();
+            let mut used = 0;
+            let mut res = Ok(());
+            for chunk in bytes.chunks(piece) {
+                match parser.feed(chunk) {
+                    Ok(n) => used += n,
+                    Err(e) => {
+                        res = Err(e);
+                        break;
+                    }
+                }
+            }
+            let pieces = res.and_then(|_| parser.finish()).map(|gz| (gz.header, used));
+            prop_assert_eq!(whole, pieces);
+        }
+
+        #[test]
+        fn arbitrary_bodies_never_panic (bytes in gzipish()) {
+            let mut input = &bytes[..];
+            if let Ok(gz) = GzFile::read_header(&mut input) {
+                let _ = gz.test(input);
+            }
+        }
+
+        #[test]
+        fn arbitrary_files_list_without_panicking (bytes in prop_oneof![
+            proptest::collection::vec(any::<u8>(), 0..32), gzipish()]) {
+            let path = std::env::temp_dir().join(format!("rstzip-header-{}", std::process::id()));
+            std::fs::write(&path, &bytes).unwrap();
+            let mut file = std::fs::File::open(&path).unwrap();
+            let _ = crate::formats::parse_list(&mut file, &crate::options::Options::default());
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use flate2::{Compression, Crc};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use super::GzFile;
+use super::inflate::{Bits, Inflater, WINDOW};
+
+const MAGIC: [u8; 4] = *b"GZIX";
+const VERSION: u8 = 1;
+
+/// A place decompression can start from without decompressing what comes before it: a block
+/// boundary of the deflate data, and the 32 KiB of data that preceded it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Point {
+    /// where the block starts in the compressed file, in bits
+    bits: u64,
+    /// where the block starts in the data
+    out: u64,
+    window: Vec<u8>
+}
+
+/// An index for random access into an ordinary gzip file, as zlib's zran example builds one:
+/// access points roughly every `span` bytes of the data, each one a block boundary and the
+/// window needed to decompress from it. Reading any range then takes decompressing at most
+/// `span` bytes before it.
+///
+/// ```
+/// use std::io::{Cursor, Read, Seek, SeekFrom};
+/// use rstzip::{GzIndex, IndexedGz};
+///
+/// let data: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_string().into_bytes()).collect();
+/// let compressed = rstzip::compress(&data[..], Vec::new(), 6).unwrap();
+/// let index = GzIndex::build(&compressed[..], 64 * 1024).unwrap();
+///
+/// let mut gz = IndexedGz::new(Cursor::new(compressed), index);
+/// gz.seek(SeekFrom::Start(300_000)).unwrap();
+/// let mut slice = [0u8; 10];
+/// gz.read_exact(&mut slice).unwrap();
+/// assert_eq!(slice, data[300_000..300_010]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzIndex {
+    span: u64,
+    /// the size of the data
+    size: u64,
+    /// the size of the compressed file, to tell when the index no longer describes it
+    compressed_size: u64,
+    points: Vec<Point>
+}
+
+impl GzIndex {
+    /// The span `rstzip --index` builds indexes with
+    pub const DEFAULT_SPAN: u64 = 1 << 20;
+
+    /// Decompresses the gzip file `input` from its start, checking every member, and indexes
+    /// it with an access point at least every `span` bytes of the data
+    pub fn build<R: Read> (input: R, span: u64) -> io::Result<Self> {
+        let mut bits = Bits::new(BufReader::new(input));
+        GzFile::read_header(&mut bits)?;
+        let mut inflater = Inflater::new(bits);
+        inflater.stop_at_blocks();
+        let mut points: Vec<Point> = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        let (mut size, mut crc) = (0u64, Crc::new());
+        loop {
+            let due = points.last().is_none_or(|point| size - point.out >= span);
+            if inflater.at_block() && due {
+                points.push(Point { bits: inflater.position(), out: size, window: inflater.window() });
+            }
+            let n = inflater.read(&mut buf)?;
+            crc.update(&buf[..n]);
+            size += n as u64;
+            if inflater.finished() {
+                let (sum, amount) = trailer(&mut inflater)?;
+                if sum != crc.sum() {
+                    return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--crc error"));
+                }
+                if amount != crc.amount() {
+                    return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--length error"));
+                }
+                crc = Crc::new();
+                if !next_member(&mut inflater)? {
+                    break;
+                }
+            }
+        }
+        io::copy(inflater.bits(), &mut io::sink())?;
+        Ok(GzIndex { span, size, compressed_size: inflater.position() / 8, points })
+    }
+
+    /// The size of the data
+    pub fn size (&self) -> u64 {
+        self.size
+    }
+
+    /// How many access points there are
+    pub fn len (&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Whether this index may have been built for a gzip file of `len` bytes. A file changed
+    /// since it was indexed most likely changed size.
+    pub fn describes (&self, len: u64) -> bool {
+        self.compressed_size == len
+    }
+
+    /// Reads an index laid out as `write` lays it out
+    pub fn read<R: Read> (mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 5];
+        input.read_exact(&mut magic)?;
+        if magic[..4] != MAGIC || magic[4] != VERSION {
+            return Err(corrupt());
+        }
+        let span = read_u64(&mut input)?;
+        let size = read_u64(&mut input)?;
+        let compressed_size = read_u64(&mut input)?;
+        let count = read_u64(&mut input)?;
+        let mut points: Vec<Point> = Vec::new();
+        for _ in 0..count {
+            let bits = read_u64(&mut input)?;
+            let out = read_u64(&mut input)?;
+            let len = read_u64(&mut input)?;
+            let mut window = Vec::with_capacity(WINDOW);
+            DeflateDecoder::new((&mut input).take(len)).take(WINDOW as u64 + 1).read_to_end(&mut window)?;
+            let sane = bits / 8 < compressed_size && out <= size && window.len() <= WINDOW
+                && window.len() as u64 <= out && points.last().map_or(out == 0, |point| point.out <= out);
+            if !sane {
+                return Err(corrupt());
+            }
+            points.push(Point { bits, out, window });
+        }
+        if points.is_empty() {
+            return Err(corrupt());
+        }
+        Ok(GzIndex { span, size, compressed_size, points })
+    }
+
+    /// Writes the index: a magic number and version, the span, the sizes of the data and of the
+    /// compressed file, then each access point with its window deflated. Numbers are little
+    /// endian 64 bit integers.
+    pub fn write<W: Write> (&self, mut output: W) -> io::Result<()> {
+        output.write_all(&MAGIC)?;
+        output.write_all(&[VERSION])?;
+        for n in &[self.span, self.size, self.compressed_size, self.points.len() as u64] {
+            output.write_all(&n.to_le_bytes())?;
+        }
+        for point in &self.points {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&point.window)?;
+            let window = encoder.finish()?;
+            for n in &[point.bits, point.out, window.len() as u64] {
+                output.write_all(&n.to_le_bytes())?;
+            }
+            output.write_all(&window)?;
+        }
+        Ok(())
+    }
+
+    /// The last access point at or before `offset` of the data
+    fn locate (&self, offset: u64) -> &Point {
+        let i = self.points.iter().rposition(|point| point.out <= offset).unwrap_or(0);
+        &self.points[i]
+    }
+}
+
+/// Where the index of the gzip file at `path` is kept: next to it, named after it
+pub fn index_path (path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gzidx");
+    PathBuf::from(name)
+}
+
+/// Reads the trailer that follows the final block of a member: its CRC32 and size
+fn trailer<R: BufRead> (inflater: &mut Inflater<R>) -> io::Result<(u32, u32)> {
+    let mut trailer = [0u8; 8];
+    inflater.bits().read_exact(&mut trailer)?;
+    Ok((u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]),
+        u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]])))
+}
+
+/// Moves on to the member after the trailer just read, returning whether there is one. As in
+/// gunzip, data after the last member that isn't another member is ignored.
+fn next_member<R: BufRead> (inflater: &mut Inflater<R>) -> io::Result<bool> {
+    let mut magic = [0u8; 1];
+    if inflater.bits().read(&mut magic)? == 0 || magic[0] != 0x1f {
+        return Ok(false);
+    }
+    match GzFile::read_header(&mut Read::chain(&magic[..], inflater.bits())) {
+        Ok(_) => {
+            inflater.restart();
+            Ok(true)
+        },
+        Err(ref e) if e.kind() == ErrorKind::InvalidData => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+fn read_u64<R: Read> (input: &mut R) -> io::Result<u64> {
+    let mut n = [0u8; 8];
+    input.read_exact(&mut n)?;
+    Ok(u64::from_le_bytes(n))
+}
+
+fn corrupt () -> Error {
+    Error::new(ErrorKind::InvalidData, "corrupted gzip index")
+}
+
+/// Reads the data of a gzip file as a seekable stream, decompressing from the access point of
+/// a `GzIndex` nearest to where it is asked to read. Check values aren't verified, as reads
+/// may start in the middle of a member; `GzIndex::build` verified them.
+pub struct IndexedGz<R: Read + Seek> {
+    index: GzIndex,
+    /// decompressing from an access point, up to `at`; `None` until the first read, and after
+    /// a failed one
+    inflater: Option<Inflater<BufReader<R>>>,
+    input: Option<R>,
+    at: u64,
+    /// where the next read starts
+    pos: u64
+}
+
+impl<R: Read + Seek> IndexedGz<R> {
+    /// Reads the gzip file `input`, which `index` was built for
+    pub fn new (input: R, index: GzIndex) -> Self {
+        IndexedGz { index, inflater: None, input: Some(input), at: 0, pos: 0 }
+    }
+
+    pub fn index (&self) -> &GzIndex {
+        &self.index
+    }
+
+    /// The input, unless a failed read lost it
+    pub fn into_inner (self) -> Option<R> {
+        match self.inflater {
+            Some(inflater) => Some(inflater.into_inner().into_inner().into_inner()),
+            None => self.input
+        }
+    }
+
+    /// Positions the inflater at `pos`, starting again from an access point unless `pos` is
+    /// less than a span ahead of where it is
+    fn reach (&mut self) -> io::Result<()> {
+        let point = self.index.locate(self.pos);
+        let ahead = self.inflater.is_some() && self.at <= self.pos && self.at >= point.out;
+        if !ahead {
+            let mut input = match self.inflater.take() {
+                Some(inflater) => inflater.into_inner().into_inner().into_inner(),
+                None => self.input.take().ok_or_else(|| Error::other("input lost to an earlier error"))?
+            };
+            input.seek(SeekFrom::Start(point.bits / 8))?;
+            let mut bits = Bits::new(BufReader::new(input));
+            bits.read_bits((point.bits % 8) as u32)?;
+            self.inflater = Some(Inflater::resume(bits, &point.window));
+            self.at = point.out;
+        }
+        let mut skip = vec![0u8; WINDOW];
+        while self.at < self.pos {
+            let want = usize::try_from(self.pos - self.at).unwrap_or(usize::MAX).min(skip.len());
+            let n = self.fill(&mut skip[..want])?;
+            if n == 0 {
+                break;
+            }
+            self.at += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Decompresses into `buf`, crossing into the next member at the end of one
+    fn fill (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inflater = self.inflater.as_mut().expect("reach positions the inflater first");
+        loop {
+            if inflater.finished() {
+                trailer(inflater)?;
+                if !next_member(inflater)? {
+                    return Ok(0);
+                }
+            }
+            let n = inflater.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedGz<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.index.size || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.inflater.is_none() || self.at != self.pos {
+            self.reach()?;
+        }
+        let n = self.fill(buf);
+        let n = n.inspect_err(|_| { self.inflater = None; })?;
+        self.at += n as u64;
+        self.pos = self.at;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedGz<R> {
+    /// Moves where the next read starts. Nothing is decompressed until then.
+    fn seek (&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.index.size.checked_add_signed(n)
+        };
+        self.pos = pos.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample () -> Vec<u8> {
+        (0..300_000u32).flat_map(|i| format!("{} {}\n", i, i.wrapping_mul(2_654_435_761) % 1000).into_bytes()).collect()
+    }
+
+    #[test]
+    fn build_write_read () {
+        let data = sample();
+        let compressed = crate::compress(&data[..], Vec::new(), 6).unwrap();
+        let index = GzIndex::build(&compressed[..], 256 * 1024).unwrap();
+        assert_eq!(index.size(), data.len() as u64);
+        assert!(index.describes(compressed.len() as u64));
+        assert!(index.len() > 4);
+        assert!(index.points.windows(2).all(|pair| pair[1].out - pair[0].out >= 256 * 1024));
+
+        let mut saved = Vec::new();
+        index.write(&mut saved).unwrap();
+        assert_eq!(GzIndex::read(&saved[..]).unwrap(), index);
+        // an access point past the end of the compressed file
+        saved[21..29].iter_mut().for_each(|b| *b = 0);
+        assert!(GzIndex::read(&saved[..]).is_err());
+        assert!(GzIndex::read(&b"GZIX\x02"[..]).is_err());
+
+        let mut corrupt = compressed.clone();
+        let at = corrupt.len() - 3;
+        corrupt[at] ^= 1;
+        assert_eq!(GzIndex::build(&corrupt[..], 1 << 20).unwrap_err().to_string(), "invalid compressed data--length error");
+    }
+
+    #[test]
+    fn seek_and_read () {
+        let data = sample();
+        // two members, the second read through from a point in the first
+        let half = data.len() / 2;
+        let mut compressed = crate::compress(&data[..half], Vec::new(), 6).unwrap();
+        compressed.extend(crate::compress(&data[half..], Vec::new(), 9).unwrap());
+        let index = GzIndex::build(&compressed[..], 100_000).unwrap();
+        assert_eq!(index.size(), data.len() as u64);
+        let mut gz = IndexedGz::new(Cursor::new(compressed), index);
+        for &offset in &[0, 5, 150_000, half as u64 - 3, 1_000_000, data.len() as u64 - 7, 20, data.len() as u64 + 9] {
+            gz.seek(SeekFrom::Start(offset)).unwrap();
+            let mut slice = Vec::new();
+            (&mut gz).take(5000).read_to_end(&mut slice).unwrap();
+            let start = (offset as usize).min(data.len());
+            assert_eq!(slice, &data[start..(start + 5000).min(data.len())]);
+        }
+        assert_eq!(gz.seek(SeekFrom::End(-4)).unwrap(), data.len() as u64 - 4);
+        let mut tail = Vec::new();
+        gz.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[data.len() - 4..]);
+        assert!(gz.seek(SeekFrom::Current(-(data.len() as i64) - 1)).is_err());
+    }
+}
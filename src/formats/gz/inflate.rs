@@ -0,0 +1,509 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read};
+
+/// the window deflate matches reach back into
+pub(crate) const WINDOW: usize = 32 * 1024;
+const MAX_BITS: usize = 15;
+/// base lengths and extra bits of the length symbols 257 to 285
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59,
+    67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4,
+    5, 5, 5, 5, 0];
+/// base distances and extra bits of the distance symbols
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513,
+    769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10,
+    11, 11, 12, 12, 13, 13];
+/// the order the lengths of the code length code are sent in
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Reads bits least significant first, as deflate packs them, keeping count of how many have
+/// been read so that decompression can later resume at the same bit
+pub(crate) struct Bits<R> {
+    input: R,
+    buf: u64,
+    count: u32,
+    /// bytes taken from `input`, some of which may still be in `buf`
+    consumed: u64
+}
+
+impl<R: BufRead> Bits<R> {
+    pub(crate) fn new (input: R) -> Self {
+        Bits { input, buf: 0, count: 0, consumed: 0 }
+    }
+
+    /// How many bits have been read, counting from where `input` was when `new` was given it
+    pub(crate) fn position (&self) -> u64 {
+        self.consumed * 8 - u64::from(self.count)
+    }
+
+    pub(crate) fn into_inner (self) -> R {
+        self.input
+    }
+
+    /// Tops `buf` up to at least 57 bits, unless the input ends first
+    fn refill (&mut self) -> io::Result<()> {
+        while self.count <= 56 {
+            let available = self.input.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            let mut used = 0;
+            for &byte in available {
+                if self.count > 56 {
+                    break;
+                }
+                self.buf |= u64::from(byte) << self.count;
+                self.count += 8;
+                used += 1;
+            }
+            self.input.consume(used);
+            self.consumed += used as u64;
+        }
+        Ok(())
+    }
+
+    /// The next `n` bits without reading them, padded with zeros past the end of the input
+    fn peek (&mut self, n: u32) -> io::Result<u64> {
+        if self.count < n {
+            self.refill()?;
+        }
+        Ok(self.buf & ((1 << n) - 1))
+    }
+
+    fn drop_bits (&mut self, n: u32) -> io::Result<()> {
+        if self.count < n {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "unexpected end of file"));
+        }
+        self.buf >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    pub(crate) fn read_bits (&mut self, n: u32) -> io::Result<u64> {
+        let bits = self.peek(n)?;
+        self.drop_bits(n)?;
+        Ok(bits)
+    }
+
+    /// Skips to the next byte boundary
+    pub(crate) fn align (&mut self) {
+        let partial = self.count % 8;
+        self.buf >>= partial;
+        self.count -= partial;
+    }
+}
+
+/// Reads whole bytes; the bits must be aligned on a byte boundary
+impl<R: BufRead> Read for Bits<R> {
+    fn read (&mut self, out: &mut [u8]) -> io::Result<usize> {
+        debug_assert_eq!(self.count % 8, 0);
+        if self.count == 0 {
+            let n = self.input.read(out)?;
+            self.consumed += n as u64;
+            return Ok(n);
+        }
+        let mut n = 0;
+        while n < out.len() && self.count > 0 {
+            out[n] = self.buf as u8;
+            self.buf >>= 8;
+            self.count -= 8;
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// A canonical Huffman code, decoded by looking the next `bits` bits up in a table
+struct Huffman {
+    /// the symbol and code length for every value of the next `bits` bits; a length of 0
+    /// marks bits no code starts with
+    table: Vec<(u16, u8)>,
+    bits: u32
+}
+
+impl Huffman {
+    /// The code with these code lengths per symbol, failing if more codes are asked for than
+    /// there are bit patterns. A code with patterns left over is accepted, as zlib accepts it;
+    /// reading one of them is an error.
+    fn new (lengths: &[u8]) -> io::Result<Self> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            count[usize::from(len)] += 1;
+        }
+        count[0] = 0;
+        let mut left = 1i32;
+        for &n in &count[1..] {
+            left = left * 2 - i32::from(n);
+            if left < 0 {
+                return Err(corrupt("over-subscribed code"));
+            }
+        }
+        let bits = (1..=MAX_BITS).rev().find(|&len| count[len] > 0).unwrap_or(1) as u32;
+        let mut next = [0u32; MAX_BITS + 1];
+        let mut code = 0;
+        for len in 1..=MAX_BITS {
+            code = (code + u32::from(count[len - 1])) << 1;
+            next[len] = code;
+        }
+        let mut table = vec![(0u16, 0u8); 1 << bits];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let len = u32::from(len);
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            // codes are sent most significant bit first, into a stream read least significant first
+            let reversed = (code.reverse_bits() >> (32 - len)) as usize;
+            for entry in table.iter_mut().skip(reversed).step_by(1 << len) {
+                *entry = (symbol as u16, len as u8);
+            }
+        }
+        Ok(Huffman { table, bits })
+    }
+
+    fn decode<R: BufRead> (&self, bits: &mut Bits<R>) -> io::Result<usize> {
+        let (symbol, len) = self.table[bits.peek(self.bits)? as usize];
+        if len == 0 {
+            return Err(corrupt("invalid code"));
+        }
+        bits.drop_bits(u32::from(len))?;
+        Ok(usize::from(symbol))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// a block header is next
+    Block,
+    /// inside a stored block, with this many bytes left
+    Stored(u16),
+    /// inside a compressed block
+    Codes,
+    /// copying a match
+    Copy { len: usize, dist: usize },
+    /// the final block has ended
+    Done
+}
+
+/// Inflates deflate data (RFC 1951) one block at a time. It can stop at each block boundary,
+/// report where in the input it stands and what the last 32 KiB of output were, and later
+/// resume from exactly there, which is all random access into deflate data takes.
+pub(crate) struct Inflater<R> {
+    bits: Bits<R>,
+    state: State,
+    last: bool,
+    codes: Option<(Huffman, Huffman)>,
+    window: Vec<u8>,
+    /// where the next byte goes in `window`
+    wpos: usize,
+    /// how much of `window` holds output that may be matched
+    have: usize,
+    /// return from `read` at every block boundary
+    stop_at_blocks: bool
+}
+
+impl<R: BufRead> Inflater<R> {
+    /// An inflater for the deflate data `bits` is positioned at the start of
+    pub(crate) fn new (bits: Bits<R>) -> Self {
+        Inflater { bits, state: State::Block, last: false, codes: None, window: vec![0; WINDOW], wpos: 0,
+            have: 0, stop_at_blocks: false }
+    }
+
+    /// An inflater that picks up at the block boundary `bits` is positioned at, `window`
+    /// being the output that preceded it
+    pub(crate) fn resume (bits: Bits<R>, window: &[u8]) -> Self {
+        let mut inflater = Inflater::new(bits);
+        let window = &window[window.len().saturating_sub(WINDOW)..];
+        inflater.window[..window.len()].copy_from_slice(window);
+        inflater.wpos = window.len() % WINDOW;
+        inflater.have = window.len();
+        inflater
+    }
+
+    /// Makes `read` return at every block boundary, even with none of its buffer filled
+    pub(crate) fn stop_at_blocks (&mut self) {
+        self.stop_at_blocks = true;
+    }
+
+    /// Whether the next thing to read is a block header
+    pub(crate) fn at_block (&self) -> bool {
+        self.state == State::Block
+    }
+
+    /// Whether the final block has been read
+    pub(crate) fn finished (&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Where in the input inflation stands, in bits
+    pub(crate) fn position (&self) -> u64 {
+        self.bits.position()
+    }
+
+    /// The last 32 KiB of output, or all of it if there was less, oldest first
+    pub(crate) fn window (&self) -> Vec<u8> {
+        let start = (self.wpos + WINDOW - self.have) % WINDOW;
+        if start + self.have <= WINDOW {
+            self.window[start..start + self.have].to_vec()
+        }
+        else {
+            [&self.window[start..], &self.window[..self.wpos]].concat()
+        }
+    }
+
+    /// The input, aligned on the byte following the final block, for reading what follows it
+    pub(crate) fn bits (&mut self) -> &mut Bits<R> {
+        &mut self.bits
+    }
+
+    /// Starts on another stream of deflate data, which can't match anything before it
+    pub(crate) fn restart (&mut self) {
+        self.state = State::Block;
+        self.last = false;
+        self.have = 0;
+    }
+
+    pub(crate) fn into_inner (self) -> Bits<R> {
+        self.bits
+    }
+
+    fn push (&mut self, byte: u8) {
+        self.window[self.wpos] = byte;
+        self.wpos = (self.wpos + 1) % WINDOW;
+        self.have = (self.have + 1).min(WINDOW);
+    }
+
+    /// Inflates into `out` until it is full or the final block ends, or with `stop_at_blocks`
+    /// until a block ends. Returns how much was written.
+    pub(crate) fn read (&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < out.len() {
+            match self.state {
+                State::Done => break,
+                State::Block => {
+                    if n > 0 && self.stop_at_blocks {
+                        break;
+                    }
+                    self.block()?;
+                },
+                State::Stored(left) => {
+                    if left == 0 {
+                        self.end_block();
+                        continue;
+                    }
+                    let byte = self.bits.read_bits(8)? as u8;
+                    out[n] = byte;
+                    n += 1;
+                    self.push(byte);
+                    self.state = State::Stored(left - 1);
+                },
+                State::Copy { len, dist } => {
+                    let byte = self.window[(self.wpos + WINDOW - dist) % WINDOW];
+                    out[n] = byte;
+                    n += 1;
+                    self.push(byte);
+                    self.state = if len > 1 { State::Copy { len: len - 1, dist } } else { State::Codes };
+                },
+                State::Codes => {
+                    let (lit, dist) = self.codes.as_ref().expect("codes are read with the block header");
+                    let symbol = lit.decode(&mut self.bits)?;
+                    if symbol < 256 {
+                        out[n] = symbol as u8;
+                        n += 1;
+                        self.push(symbol as u8);
+                    }
+                    else if symbol == 256 {
+                        self.end_block();
+                        if self.stop_at_blocks && !self.last {
+                            break;
+                        }
+                    }
+                    else {
+                        let i = symbol - 257;
+                        if i >= LENGTH_BASE.len() {
+                            return Err(corrupt("invalid literal/length code"));
+                        }
+                        let len = usize::from(LENGTH_BASE[i]) + self.bits.read_bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+                        let i = dist.decode(&mut self.bits)?;
+                        if i >= DIST_BASE.len() {
+                            return Err(corrupt("invalid distance code"));
+                        }
+                        let dist = usize::from(DIST_BASE[i]) + self.bits.read_bits(u32::from(DIST_EXTRA[i]))? as usize;
+                        if dist > self.have {
+                            return Err(corrupt("invalid distance too far back"));
+                        }
+                        self.state = State::Copy { len, dist };
+                    }
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    fn end_block (&mut self) {
+        if self.last {
+            self.bits.align();
+            self.state = State::Done;
+        }
+        else {
+            self.state = State::Block;
+        }
+    }
+
+    /// Reads a block header, and the codes of a compressed block
+    fn block (&mut self) -> io::Result<()> {
+        self.last = self.bits.read_bits(1)? == 1;
+        match self.bits.read_bits(2)? {
+            0 => {
+                self.bits.align();
+                let len = self.bits.read_bits(16)? as u16;
+                let nlen = self.bits.read_bits(16)? as u16;
+                if len != !nlen {
+                    return Err(corrupt("invalid stored block lengths"));
+                }
+                self.state = State::Stored(len);
+            },
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].iter_mut().for_each(|len| *len = 9);
+                lengths[256..280].iter_mut().for_each(|len| *len = 7);
+                self.codes = Some((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?));
+                self.state = State::Codes;
+            },
+            2 => {
+                self.codes = Some(self.dynamic_codes()?);
+                self.state = State::Codes;
+            },
+            _ => return Err(corrupt("invalid block type"))
+        }
+        Ok(())
+    }
+
+    fn dynamic_codes (&mut self) -> io::Result<(Huffman, Huffman)> {
+        let nlen = self.bits.read_bits(5)? as usize + 257;
+        let ndist = self.bits.read_bits(5)? as usize + 1;
+        let ncode = self.bits.read_bits(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(corrupt("too many length or distance symbols"));
+        }
+        let mut clens = [0u8; 19];
+        for &i in &CLEN_ORDER[..ncode] {
+            clens[i] = self.bits.read_bits(3)? as u8;
+        }
+        let clen = Huffman::new(&clens)?;
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = clen.decode(&mut self.bits)?;
+            let (len, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => match i {
+                    0 => return Err(corrupt("invalid bit length repeat")),
+                    _ => (lengths[i - 1], 3 + self.bits.read_bits(2)? as usize)
+                },
+                17 => (0, 3 + self.bits.read_bits(3)? as usize),
+                _ => (0, 11 + self.bits.read_bits(7)? as usize)
+            };
+            if i + repeat > lengths.len() {
+                return Err(corrupt("invalid bit length repeat"));
+            }
+            lengths[i..i + repeat].iter_mut().for_each(|l| *l = len);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(corrupt("invalid code -- missing end-of-block"));
+        }
+        Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+    }
+}
+
+fn corrupt (reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid compressed data--{}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+    use std::io::Write;
+
+    fn deflate (data: &[u8], level: u32) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn inflate_all<R: BufRead> (inflater: &mut Inflater<R>) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 1000];
+        while !inflater.finished() {
+            let n = inflater.read(&mut buf)?;
+            out.extend_from_slice(&buf[..n]);
+        }
+        Ok(out)
+    }
+
+    /// Text-like data, compressible enough for matches but varied enough for many blocks
+    fn sample () -> Vec<u8> {
+        let mut x = 0x9e37_79b9u32;
+        (0..400_000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            b"etaoin shrdlu\n"[(x % 14) as usize]
+        }).collect()
+    }
+
+    #[test]
+    fn every_block_type () {
+        let data = sample();
+        for level in &[0, 1, 6, 9] {
+            let compressed = deflate(&data, *level);
+            let mut inflater = Inflater::new(Bits::new(&compressed[..]));
+            assert_eq!(inflate_all(&mut inflater).unwrap(), data);
+            assert_eq!(inflater.position(), compressed.len() as u64 * 8);
+        }
+        let empty = deflate(b"", 6);
+        let mut inflater = Inflater::new(Bits::new(&empty[..]));
+        assert!(inflate_all(&mut inflater).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resume_at_block () {
+        let data = sample();
+        let compressed = deflate(&data, 6);
+        let mut inflater = Inflater::new(Bits::new(&compressed[..]));
+        inflater.stop_at_blocks();
+        let mut buf = vec![0u8; 100_000];
+        let mut out = 0;
+        let mut stops = Vec::new();
+        while !inflater.finished() {
+            if inflater.at_block() {
+                stops.push((inflater.position(), out, inflater.window()));
+            }
+            out += inflater.read(&mut buf).unwrap();
+        }
+        assert!(stops.len() > 2);
+        for (position, out, window) in stops {
+            let mut bits = Bits::new(&compressed[(position / 8) as usize..]);
+            bits.read_bits((position % 8) as u32).unwrap();
+            let mut inflater = Inflater::resume(bits, &window);
+            assert_eq!(window.len(), out.min(WINDOW));
+            assert_eq!(inflate_all(&mut inflater).unwrap(), &data[out..]);
+        }
+    }
+
+    #[test]
+    fn bad_data () {
+        let compressed = deflate(&sample(), 6);
+        let err = inflate_all(&mut Inflater::new(Bits::new(&compressed[..1000]))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = inflate_all(&mut Inflater::new(Bits::new(&[0xff, 0xff][..]))).unwrap_err();
+        assert_eq!(err.to_string(), "invalid compressed data--invalid block type");
+        // a match before any output
+        let err = inflate_all(&mut Inflater::new(Bits::new(&[0x03, 0x02, 0x00, 0x00][..]))).unwrap_err();
+        assert_eq!(err.to_string(), "invalid compressed data--invalid distance too far back");
+    }
+}
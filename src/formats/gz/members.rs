@@ -0,0 +1,231 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read, Write};
+use flate2::Crc;
+use flate2::bufread::DeflateDecoder;
+use super::{GzFile, HeaderError};
+use crate::formats::codec::Tested;
+
+/// One member of a gzip file, as `Members` found it
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// where the member starts in the file
+    pub offset: u64,
+    pub header: GzFile,
+    /// the size of the whole member, header and trailer included
+    pub compressed_size: u64,
+    /// the CRC32 of the member's data, as checked against its trailer
+    pub crc32: u32,
+    /// the size of the member's data, which its trailer only records modulo 2^32
+    pub size: u64
+}
+
+impl Member {
+    /// The bytes of the member around its compressed data: its header and trailer
+    pub fn header_size (&self) -> u64 {
+        (self.header.header_len() + super::TRAILER_LEN) as u64
+    }
+}
+
+/// Walks the members of a gzip file one after another, decompressing each to check it
+/// against its trailer. As in gunzip, zero padding or anything else that isn't a member after
+/// the first ends the walk; `trailing_garbage` tells which it was.
+///
+/// ```
+/// let mut file = rstzip::compress(&b"first"[..], Vec::new(), 6).unwrap();
+/// file.extend(rstzip::compress(&b"and second"[..], Vec::new(), 6).unwrap());
+///
+/// let sizes: Vec<u64> = rstzip::Members::new(&file[..]).map(|member| member.unwrap().size).collect();
+/// assert_eq!(sizes, vec![5, 10]);
+/// ```
+pub struct Members<R: BufRead, W: Write = io::Sink> {
+    input: Counted<R>,
+    /// where the data of the members goes
+    output: W,
+    /// the CRC32 of the data of every member so far
+    crc: Crc,
+    trailing_garbage: bool,
+    done: bool
+}
+
+impl<R: BufRead> Members<R> {
+    /// Walks the gzip file `input`, which must be at its start
+    pub fn new (input: R) -> Self {
+        Members::with_output(input, io::sink())
+    }
+}
+
+impl<R: BufRead, W: Write> Members<R, W> {
+    /// Walks the gzip file `input`, which must be at its start, writing the data of each member
+    /// to `output` as it is decompressed
+    pub fn with_output (input: R, output: W) -> Self {
+        Members { input: Counted { inner: input, count: 0 }, output, crc: Crc::new(), trailing_garbage: false,
+            done: false }
+    }
+
+    /// The CRC32 of all the data of the members walked so far, as if it had been one member
+    pub fn crc32 (&self) -> u32 {
+        self.crc.sum()
+    }
+
+    /// Whether data other than zero padding followed the last member. Only known once the
+    /// walk is over.
+    pub fn trailing_garbage (&self) -> bool {
+        self.trailing_garbage
+    }
+
+    /// Walks the remaining members, returning what was found in the whole walk
+    pub(crate) fn tested (mut self) -> io::Result<Tested> {
+        let mut tested = Tested::default();
+        while let Some(member) = self.member()? {
+            tested.members += 1;
+            tested.size += member.size;
+        }
+        tested.trailing_garbage = self.trailing_garbage;
+        Ok(tested)
+    }
+
+    fn member (&mut self) -> io::Result<Option<Member>> {
+        let offset = self.input.count;
+        if offset > 0 {
+            let next = self.input.fill_buf()?;
+            if next.is_empty() {
+                return Ok(None);
+            }
+            if next[0] != 0x1f {
+                self.trailing_garbage = !super::only_zeros(&mut self.input)?;
+                return Ok(None);
+            }
+        }
+        let header = match GzFile::read_header(&mut self.input) {
+            Ok(header) => header,
+            Err(ref e) if offset > 0 && HeaderError::of(e) == Some(&HeaderError::NotGzip) => {
+                self.trailing_garbage = true;
+                return Ok(None);
+            },
+            Err(e) => return Err(e)
+        };
+        let mut sums = Sums { member: Crc::new(), total: &mut self.crc, output: &mut self.output, failed: false };
+        let mut body = DeflateDecoder::new(&mut self.input);
+        let size = io::copy(&mut body, &mut sums).map_err(|e| match e.kind() {
+            // errors writing the data are the output's own
+            _ if sums.failed => e,
+            ErrorKind::UnexpectedEof => e,
+            _ => Error::new(ErrorKind::InvalidData, "invalid compressed data--format violated")
+        })?;
+        let crc32 = sums.member.sum();
+        let mut trailer = [0u8; 8];
+        self.input.read_exact(&mut trailer)?;
+        if u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != crc32 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--crc error"));
+        }
+        // the trailer only records the size modulo 2^32
+        if u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]) != size as u32 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--length error"));
+        }
+        Ok(Some(Member { offset, header, compressed_size: self.input.count - offset, crc32, size }))
+    }
+}
+
+impl<R: BufRead, W: Write> Iterator for Members<R, W> {
+    type Item = io::Result<Member>;
+
+    /// The next member, or the error that ends the walk
+    fn next (&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let member = self.member().transpose();
+        self.done = !matches!(member, Some(Ok(_)));
+        member
+    }
+}
+
+/// Counts the bytes consumed from `inner`
+struct Counted<R> {
+    inner: R,
+    count: u64
+}
+
+impl<R: BufRead> Read for Counted<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf (&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume (&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.count += amt as u64;
+    }
+}
+
+/// Sums the data written to it into the CRC of one member and that of the whole file, and
+/// passes it on to `output`
+struct Sums<'a, W> {
+    member: Crc,
+    total: &'a mut Crc,
+    output: &'a mut W,
+    /// whether writing to `output` failed
+    failed: bool
+}
+
+impl<W: Write> Write for Sums<'_, W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.output.write(buf).inspect_err(|_| self.failed = true)?;
+        self.member.update(&buf[..n]);
+        self.total.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::util::WorkData;
+
+    #[test]
+    fn walk () {
+        let named = |name: &str, data: &[u8]| {
+            let wdata = WorkData { orig_name: Some(String::from(name)), mtime: Some(crate::util::Timespec(1_000_000, 0)),
+                ofname: format!("{}.gz", name) };
+            GzFile::compress(data, Vec::new(), Some(wdata), &Options::default()).unwrap()
+        };
+        let first = named("one.log", b"first member\n");
+        let second = named("two.log", &[b'x'; 100_000]);
+        let file = [&first[..], &second[..], &[0u8; 10][..]].concat();
+
+        let mut members = Members::new(&file[..]);
+        let found: Vec<Member> = (&mut members).collect::<io::Result<_>>().unwrap();
+        assert!(!members.trailing_garbage());
+        assert_eq!(found.iter().map(|m| (m.offset, m.compressed_size, m.size)).collect::<Vec<_>>(),
+            vec![(0, first.len() as u64, 13), (first.len() as u64, second.len() as u64, 100_000)]);
+        assert_eq!(found[1].header.name(), Some("two.log"));
+        assert_eq!(found[1].header.mtime(), 1_000_000);
+        let mut whole = Crc::new();
+        whole.update(b"first member\n");
+        whole.update(&[b'x'; 100_000]);
+        assert_eq!(members.crc32(), whole.sum());
+
+        let garbage = [&first[..], b"junk"].concat();
+        let mut members = Members::new(&garbage[..]);
+        assert_eq!((&mut members).count(), 1);
+        assert!(members.trailing_garbage());
+
+        let mut corrupt = file.clone();
+        corrupt[first.len() - 8] ^= 1;
+        let results: Vec<io::Result<Member>> = Members::new(&corrupt[..]).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap_err().to_string(), "invalid compressed data--crc error");
+        assert!(Members::new(&b"not gzip"[..]).next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,507 @@
+use std::io::{self, BufRead, BufWriter, Error, ErrorKind, Write};
+use crate::formats::codec::{Codec, Header, Tested};
+
+const MAGIC: [u8; 2] = [0x1f, 0xa0];
+/// log2 of the window size
+const DICBIT: u32 = 13;
+const DICSIZ: usize = 1 << DICBIT;
+/// the shortest match
+const THRESHOLD: usize = 3;
+/// symbols of the character and length code: every byte, then every match length up to 256
+const NC: usize = 255 + 256 + 2 - THRESHOLD;
+/// symbols of the position code, one per bit length of a distance
+const NP: usize = DICBIT as usize + 1;
+/// symbols of the code the character and length code lengths are sent with
+const NT: usize = 16 + 3;
+const CBIT: u32 = 9;
+const PBIT: u32 = 4;
+const TBIT: u32 = 5;
+
+/// The LZH format SCO `compress -H` writes: LZ77 over an 8 KiB window with Huffman codes sent
+/// block by block, as in LHarc's -lh5- method. It records neither names, times, sizes nor a
+/// check value, and can only be read.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Lzh;
+
+impl Codec for Lzh {
+    fn name (&self) -> &'static str {
+        "lzh"
+    }
+
+    fn detect (&self, magic: &[u8]) -> bool {
+        magic.starts_with(&MAGIC)
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let mut raw = vec![0u8; MAGIC.len()];
+        input.read_exact(&mut raw)?;
+        if raw[..] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not in lzh format"));
+        }
+        Ok(Header { raw, ..Header::default() })
+    }
+
+    fn decode (&self, _header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        let mut out = BufWriter::new(output);
+        let size = unlzh(input, &mut out)?;
+        out.flush()?;
+        Ok(Tested { members: 1, size, ..Tested::default() })
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        self.decode(header, input, &mut io::sink())
+    }
+}
+
+/// Reads bits most significant first. Past the end of the input it yields zeros, as gzip
+/// does, since the last block size may be left to them.
+struct Bits<'a> {
+    input: &'a mut dyn BufRead,
+    byte: u8,
+    left: u32,
+    /// how many of the bits read lay past the end of the input
+    past_end: u32
+}
+
+impl Bits<'_> {
+    fn bit (&mut self) -> io::Result<usize> {
+        if self.left == 0 {
+            self.byte = match self.input.fill_buf()?.first() {
+                Some(&byte) => {
+                    self.input.consume(1);
+                    byte
+                },
+                None => {
+                    self.past_end += 8;
+                    0
+                }
+            };
+            self.left = 8;
+        }
+        self.left -= 1;
+        Ok(usize::from(self.byte >> self.left & 1))
+    }
+
+    fn bits (&mut self, n: u32) -> io::Result<usize> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = value << 1 | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    /// Fails if data was read past the end of the input
+    fn check (&self) -> io::Result<()> {
+        if self.past_end > 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "unexpected end of file"));
+        }
+        Ok(())
+    }
+}
+
+/// A canonical Huffman code: the codes of each length are consecutive, in symbol order
+enum Code {
+    /// a code with a single symbol, which takes no bits
+    Constant(usize),
+    /// how many codes there are of each length from 1 to 16, and the symbols by code
+    Canonical { counts: [usize; 17], symbols: Vec<usize> }
+}
+
+impl Code {
+    fn new (lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0usize; 17];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        // the code must be complete, as gzip's make_table insists
+        let space: usize = (1..=16).map(|len| counts[len] << (16 - len)).sum();
+        if space != 1 << 16 {
+            return Err(corrupt());
+        }
+        let mut symbols = Vec::new();
+        for len in 1..=16 {
+            symbols.extend((0..lengths.len()).filter(|&symbol| usize::from(lengths[symbol]) == len));
+        }
+        Ok(Code::Canonical { counts, symbols })
+    }
+
+    fn decode (&self, bits: &mut Bits) -> io::Result<usize> {
+        let (counts, symbols) = match self {
+            Code::Constant(symbol) => return Ok(*symbol),
+            Code::Canonical { counts, symbols } => (counts, symbols)
+        };
+        // the first code of the current length, and the index of its symbol
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &counts[1..] {
+            code |= bits.bit()?;
+            if code < first + count {
+                return Ok(symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt())
+    }
+}
+
+/// Reads the lengths of the code the character and length code lengths are sent with, or of
+/// the position code. After the third length of the former, a run of zero lengths may follow.
+fn read_pt_code (bits: &mut Bits, symbols: usize, nbit: u32, special: Option<usize>) -> io::Result<Code> {
+    let n = bits.bits(nbit)?;
+    if n == 0 {
+        let symbol = bits.bits(nbit)?;
+        return if symbol < symbols { Ok(Code::Constant(symbol)) } else { Err(corrupt()) };
+    }
+    if n > symbols {
+        return Err(corrupt());
+    }
+    let mut lengths = vec![0u8; symbols];
+    let mut i = 0;
+    while i < n {
+        let mut len = bits.bits(3)?;
+        if len == 7 {
+            while bits.bit()? == 1 {
+                len += 1;
+                if len > 16 {
+                    return Err(corrupt());
+                }
+            }
+        }
+        lengths[i] = len as u8;
+        i += 1;
+        if Some(i) == special {
+            i += bits.bits(2)?;
+        }
+    }
+    Code::new(&lengths)
+}
+
+/// Reads the lengths of the character and length code, sent with the code `pt`
+fn read_c_code (bits: &mut Bits, pt: &Code) -> io::Result<Code> {
+    let n = bits.bits(CBIT)?;
+    if n == 0 {
+        let symbol = bits.bits(CBIT)?;
+        return if symbol < NC { Ok(Code::Constant(symbol)) } else { Err(corrupt()) };
+    }
+    if n > NC {
+        return Err(corrupt());
+    }
+    let mut lengths = vec![0u8; NC];
+    let mut i = 0;
+    while i < n {
+        match pt.decode(bits)? {
+            0 => i += 1,
+            1 => i += bits.bits(4)? + 3,
+            2 => i += bits.bits(CBIT)? + 20,
+            len => {
+                lengths[i] = (len - 2) as u8;
+                i += 1;
+            }
+        }
+    }
+    if i > n {
+        return Err(corrupt());
+    }
+    Code::new(&lengths)
+}
+
+/// Decompresses LZH data, returning the decompressed size
+fn unlzh (input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<u64> {
+    let mut bits = Bits { input, byte: 0, left: 0, past_end: 0 };
+    let mut window = vec![0u8; DICSIZ];
+    let mut size = 0u64;
+    loop {
+        let block_size = bits.bits(16)?;
+        if block_size == 0 {
+            return Ok(size);
+        }
+        let pt = read_pt_code(&mut bits, NT, TBIT, Some(3))?;
+        let c = read_c_code(&mut bits, &pt)?;
+        let p = read_pt_code(&mut bits, NP, PBIT, None)?;
+        bits.check()?;
+        for _ in 0..block_size {
+            let symbol = c.decode(&mut bits)?;
+            if symbol < 256 {
+                window[size as usize % DICSIZ] = symbol as u8;
+                output.write_all(&[symbol as u8])?;
+                size += 1;
+            }
+            else {
+                let len = symbol - 256 + THRESHOLD;
+                let distance = match p.decode(&mut bits)? {
+                    0 => 0,
+                    width => (1 << (width - 1)) + bits.bits(width as u32 - 1)?
+                } + 1;
+                for _ in 0..len {
+                    let byte = window[(size as usize).wrapping_sub(distance) % DICSIZ];
+                    window[size as usize % DICSIZ] = byte;
+                    output.write_all(&[byte])?;
+                    size += 1;
+                }
+            }
+            bits.check()?;
+        }
+    }
+}
+
+fn corrupt () -> Error {
+    Error::new(ErrorKind::InvalidData, "corrupt input")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Writes bits most significant first
+    #[derive(Default)]
+    struct BitWriter {
+        out: Vec<u8>,
+        acc: u64,
+        count: u32
+    }
+
+    impl BitWriter {
+        fn put (&mut self, value: usize, n: u32) {
+            self.acc = self.acc << n | value as u64;
+            self.count += n;
+            while self.count >= 8 {
+                self.count -= 8;
+                self.out.push((self.acc >> self.count) as u8);
+            }
+        }
+
+        fn finish (mut self) -> Vec<u8> {
+            if self.count > 0 {
+                self.out.push((self.acc << (8 - self.count)) as u8);
+            }
+            self.out
+        }
+    }
+
+    /// Complete code lengths for `used` out of `symbols` symbols, or `None` if only one is
+    /// used: the first get the same length, the rest one more
+    fn lengths (used: &BTreeSet<usize>, symbols: usize) -> Option<Vec<u8>> {
+        let m = used.len();
+        if m < 2 {
+            return None;
+        }
+        let bits = usize::BITS - 1 - m.leading_zeros();
+        let short = if m == 1 << bits { m } else { (2 << bits) - m };
+        let mut lengths = vec![0u8; symbols];
+        for (i, &symbol) in used.iter().enumerate() {
+            lengths[symbol] = if i < short { bits } else { bits + 1 } as u8;
+        }
+        Some(lengths)
+    }
+
+    /// The canonical code of each symbol, as (code, length)
+    fn codes (lengths: &[u8]) -> Vec<(usize, u32)> {
+        let mut counts = [0usize; 17];
+        for &len in lengths.iter().filter(|&&len| len > 0) {
+            counts[usize::from(len)] += 1;
+        }
+        let mut next = [0usize; 17];
+        for len in 2..=16 {
+            next[len] = (next[len - 1] + counts[len - 1]) << 1;
+        }
+        lengths.iter().map(|&len| {
+            let len = usize::from(len);
+            let code = next[len];
+            next[len] += 1;
+            (code, len as u32)
+        }).collect()
+    }
+
+    fn put_pt (w: &mut BitWriter, lengths: Option<&Vec<u8>>, constant: usize, nbit: u32, special: bool) {
+        let lengths = match lengths {
+            Some(lengths) => lengths,
+            None => {
+                w.put(0, nbit);
+                w.put(constant, nbit);
+                return;
+            }
+        };
+        let n = lengths.iter().rposition(|&len| len > 0).unwrap() + 1;
+        w.put(n, nbit);
+        for (i, &len) in lengths[..n].iter().enumerate() {
+            let len = usize::from(len);
+            if len < 7 {
+                w.put(len, 3);
+            }
+            else {
+                w.put(7, 3);
+                for _ in 7..len {
+                    w.put(1, 1);
+                }
+                w.put(0, 1);
+            }
+            if special && i == 2 {
+                w.put(0, 2);
+            }
+        }
+    }
+
+    /// Compresses `data` into a single block, matching greedily with a naive search
+    fn compress (data: &[u8]) -> Vec<u8> {
+        // (character or length symbol, distance)
+        let mut symbols = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let (mut best, mut distance) = (0, 0);
+            for d in 1..=i.min(DICSIZ) {
+                let len = (0..256.min(data.len() - i)).take_while(|&k| data[i + k] == data[i + k - d]).count();
+                if len > best {
+                    best = len;
+                    distance = d;
+                }
+            }
+            if best >= THRESHOLD {
+                symbols.push((best - THRESHOLD + 256, distance));
+                i += best;
+            }
+            else {
+                symbols.push((usize::from(data[i]), 0));
+                i += 1;
+            }
+        }
+
+        let mut w = BitWriter::default();
+        if symbols.is_empty() {
+            w.put(0, 16);
+            return w.finish();
+        }
+        let position = |distance: usize| (usize::BITS - (distance - 1).leading_zeros()) as usize;
+        let used_c: BTreeSet<usize> = symbols.iter().map(|&(c, _)| c).collect();
+        let used_p: BTreeSet<usize> = symbols.iter().filter(|&&(c, _)| c >= 256)
+            .map(|&(_, d)| position(d)).collect();
+        let c_lengths = lengths(&used_c, NC);
+        let p_lengths = lengths(&used_p, NP);
+
+        // the character and length code lengths, as symbols of the code they are sent with
+        let mut t_symbols = Vec::new();
+        if let Some(c_lengths) = &c_lengths {
+            let n = c_lengths.iter().rposition(|&len| len > 0).unwrap() + 1;
+            let mut i = 0;
+            while i < n {
+                let zeros = c_lengths[i..n].iter().take_while(|&&len| len == 0).count().min(531);
+                if zeros >= 20 {
+                    t_symbols.push((2, zeros - 20));
+                    i += zeros;
+                }
+                else if zeros >= 3 {
+                    t_symbols.push((1, zeros.min(18) - 3));
+                    i += zeros.min(18);
+                }
+                else if zeros > 0 {
+                    t_symbols.push((0, 0));
+                    i += 1;
+                }
+                else {
+                    t_symbols.push((usize::from(c_lengths[i]) + 2, 0));
+                    i += 1;
+                }
+            }
+        }
+        let used_t: BTreeSet<usize> = t_symbols.iter().map(|&(t, _)| t).collect();
+        let t_lengths = lengths(&used_t, NT);
+
+        w.put(symbols.len(), 16);
+        put_pt(&mut w, t_lengths.as_ref(), used_t.iter().next().cloned().unwrap_or(0), TBIT, true);
+        match &c_lengths {
+            Some(c_lengths) => {
+                let n = c_lengths.iter().rposition(|&len| len > 0).unwrap() + 1;
+                w.put(n, CBIT);
+                let t_codes = t_lengths.as_ref().map(|lengths| codes(lengths));
+                for &(t, extra) in &t_symbols {
+                    if let Some(t_codes) = &t_codes {
+                        w.put(t_codes[t].0, t_codes[t].1);
+                    }
+                    match t {
+                        1 => w.put(extra, 4),
+                        2 => w.put(extra, CBIT),
+                        _ => ()
+                    }
+                }
+            },
+            None => {
+                w.put(0, CBIT);
+                w.put(symbols[0].0, CBIT);
+            }
+        }
+        put_pt(&mut w, p_lengths.as_ref(), used_p.iter().next().cloned().unwrap_or(0), PBIT, false);
+
+        let c_codes = c_lengths.as_ref().map(|lengths| codes(lengths));
+        let p_codes = p_lengths.as_ref().map(|lengths| codes(lengths));
+        for &(c, distance) in &symbols {
+            if let Some(c_codes) = &c_codes {
+                w.put(c_codes[c].0, c_codes[c].1);
+            }
+            if c >= 256 {
+                let width = position(distance);
+                if let Some(p_codes) = &p_codes {
+                    w.put(p_codes[width].0, p_codes[width].1);
+                }
+                if width > 1 {
+                    w.put(distance - 1 - (1 << (width - 1)), width as u32 - 1);
+                }
+            }
+        }
+        w.put(0, 16);
+        w.finish()
+    }
+
+    fn decode (compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = compressed;
+        let mut out = Vec::new();
+        unlzh(&mut input, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn known_stream () {
+        // the alphabet eleven times over, in one block whose longest match is 256 bytes, as gzip's
+        // unlzh decodes it
+        let compressed = [0x1f, 0xa0, 0x00, 0x1c, 0x40, 0x04, 0x00, 0x25, 0xfe, 0x13, 0x6a, 0xbf, 0xff, 0xff, 0xff,
+            0xff, 0xfc, 0x72, 0xce, 0x7c, 0x14, 0x04, 0x8d, 0x09, 0x52, 0xd8, 0xd7, 0x3e, 0x11, 0x94, 0xe9, 0x5b,
+            0x5f, 0x19, 0xd6, 0xf9, 0xdf, 0xcf, 0xa4, 0x00, 0x00];
+        let mut input = &compressed[..];
+        let header = Lzh.read_header(&mut input).unwrap();
+        let mut out = Vec::new();
+        Lzh.decode(&header, &mut input, &mut out).unwrap();
+        let expected: Vec<u8> = (b'a'..=b'z').cycle().take(26 * 11).collect();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn round_trip () {
+        let text = b"she sells sea shells by the sea shore, the shells she sells are sea shells".to_vec();
+        let varied: Vec<u8> = (0..20_000u32).map(|i| (i * i % 251) as u8 ^ (i / 700) as u8).collect();
+        let spread: Vec<u8> = (0..=255u8).chain(0..=255u8).collect();
+        for data in &[text, varied, spread, b"a".to_vec(), b"aaaaaaaaaaaa".to_vec(), Vec::new()] {
+            assert_eq!(&decode(&compress(data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn bad_input () {
+        let compressed = compress(b"she sells sea shells by the sea shore");
+        assert_eq!(decode(&compressed[..compressed.len() - 6]).unwrap_err().to_string(),
+            "unexpected end of file");
+        // a character code of one symbol with one bit
+        let mut w = BitWriter::default();
+        w.put(1, 16);
+        put_pt(&mut w, None, 3, TBIT, true);
+        w.put(1, CBIT);
+        assert_eq!(decode(&w.finish()).unwrap_err().to_string(), "corrupt input");
+        // a character and length code of the one symbol past the longest match, 256 bytes
+        let mut w = BitWriter::default();
+        w.put(1, 16);
+        put_pt(&mut w, None, 3, TBIT, true);
+        w.put(0, CBIT);
+        w.put(510, CBIT);
+        put_pt(&mut w, None, 1, PBIT, false);
+        assert_eq!(decode(&w.finish()).unwrap_err().to_string(), "corrupt input");
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Error, ErrorKind, Write};
+use crate::formats::codec::{Codec, Header, Tested};
+use crate::formats::list::ListEntry;
+use crate::options::Options;
+
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+const HEADER_LEN: usize = 3;
+/// flag byte: the number of bits codes grow to
+const BIT_MASK: u8 = 0x1f;
+/// flag byte: code 256 clears the dictionary
+const BLOCK_MODE: u8 = 0x80;
+const INIT_BITS: u32 = 9;
+const MAX_BITS: u32 = 16;
+const CLEAR: usize = 256;
+
+/// The LZW format of Unix `compress` (.Z files). It records neither names, times nor a check
+/// value, and can only be read.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Lzw;
+
+impl Codec for Lzw {
+    fn name (&self) -> &'static str {
+        "compress"
+    }
+
+    fn detect (&self, magic: &[u8]) -> bool {
+        magic.starts_with(&MAGIC)
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let mut raw = vec![0u8; HEADER_LEN];
+        input.read_exact(&mut raw)?;
+        if raw[..2] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not in compress format"));
+        }
+        Ok(Header { raw, ..Header::default() })
+    }
+
+    fn decode (&self, header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        let mut out = BufWriter::new(output);
+        let size = unlzw(header.raw[2], input, &mut out)?;
+        out.flush()?;
+        Ok(Tested { members: 1, size, ..Tested::default() })
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        self.decode(header, input, &mut io::sink())
+    }
+
+    /// Describes `file` with its size unknown, as gzip does, since it is only recorded by
+    /// decompressing the whole file
+    fn list (&self, file: &mut File, _opt: &Options) -> io::Result<Vec<ListEntry>> {
+        let compressed_size = file.metadata()?.len();
+        self.read_header(&mut io::BufReader::new(file))?;
+        Ok(vec![ListEntry {
+            method: "compr",
+            crc32: None,
+            mtime: None,
+            compressed_size,
+            uncompressed_size: None,
+            header_size: HEADER_LEN as u64,
+            stored_name: None,
+            subfields: Vec::new(),
+            comment: None,
+            members: Vec::new(),
+            trailing_garbage: false,
+            path: None
+        }])
+    }
+}
+
+/// Reads codes of a growing width, least significant bit first
+struct Codes<'a> {
+    input: &'a mut dyn BufRead,
+    bits: u32,
+    count: u32,
+    /// the width of the codes being read
+    width: u32,
+    /// codes read at this width. Widths change, and the dictionary is cleared, only at the end
+    /// of a group of eight codes, whose unused rest is skipped.
+    read: u32
+}
+
+impl Codes<'_> {
+    /// The next code, or `None` at the end of the input
+    fn next (&mut self) -> io::Result<Option<usize>> {
+        while self.count < self.width {
+            let byte = match self.input.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => return Ok(None)
+            };
+            self.input.consume(1);
+            self.bits |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let code = self.bits & ((1 << self.width) - 1);
+        self.bits >>= self.width;
+        self.count -= self.width;
+        self.read += 1;
+        Ok(Some(code as usize))
+    }
+
+    /// Skips to the end of the current group of codes and carries on with codes `width` bits wide
+    fn set_width (&mut self, width: u32) -> io::Result<()> {
+        while !self.read.is_multiple_of(8) {
+            if self.next()?.is_none() {
+                break;
+            }
+        }
+        self.width = width;
+        self.read = 0;
+        Ok(())
+    }
+}
+
+/// Decompresses LZW data whose header flag byte is `flags`, returning the decompressed size
+fn unlzw (flags: u8, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<u64> {
+    let max_bits = u32::from(flags & BIT_MASK);
+    if !(INIT_BITS..=MAX_BITS).contains(&max_bits) {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("compressed with {} bits, can only handle {} bits", max_bits, MAX_BITS)));
+    }
+    let block_mode = flags & BLOCK_MODE != 0;
+    let first = if block_mode { CLEAR + 1 } else { CLEAR };
+    let max_code = 1usize << max_bits;
+    let mut prefix = vec![0u16; max_code];
+    let mut suffix: Vec<u8> = (0..max_code).map(|code| code as u8).collect();
+    let mut stack = Vec::new();
+    let mut codes = Codes { input, bits: 0, count: 0, width: INIT_BITS, read: 0 };
+
+    let mut size = 0u64;
+    let mut next = first;
+    let mut previous = match codes.next()? {
+        None => return Ok(0),
+        Some(code) if code > 255 => return Err(corrupt()),
+        Some(code) => code
+    };
+    let mut last_char = previous as u8;
+    output.write_all(&[last_char])?;
+    size += 1;
+    loop {
+        // compress only stops growing codes once they have grown to the maximum, so 9 bit
+        // streams switch to 10 bit codes when the dictionary fills
+        if next >= 1 << codes.width && (codes.width < max_bits || codes.width == INIT_BITS) {
+            codes.set_width(codes.width + 1)?;
+        }
+        let code = match codes.next()? {
+            Some(code) => code,
+            None => break
+        };
+        if code == CLEAR && block_mode {
+            codes.set_width(INIT_BITS)?;
+            // the entry after a clear code is thrown away, as compress does
+            next = first - 1;
+            continue;
+        }
+        let mut current = code;
+        stack.clear();
+        if current >= next {
+            // the code being defined, which repeats the previous string and its first byte
+            if current > next {
+                return Err(corrupt());
+            }
+            stack.push(last_char);
+            current = previous;
+        }
+        while current >= CLEAR {
+            stack.push(suffix[current]);
+            current = usize::from(prefix[current]);
+        }
+        last_char = suffix[current];
+        stack.push(last_char);
+        stack.reverse();
+        output.write_all(&stack)?;
+        size += stack.len() as u64;
+        if next < max_code {
+            prefix[next] = previous as u16;
+            suffix[next] = last_char;
+            next += 1;
+        }
+        previous = code;
+    }
+    Ok(size)
+}
+
+fn corrupt () -> Error {
+    Error::new(ErrorKind::InvalidData, "corrupt input")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A minimal LZW compressor with compress's conventions, clearing the dictionary after
+    /// every `clear_every` codes if that isn't 0
+    fn compress (data: &[u8], max_bits: u32, clear_every: usize) -> Vec<u8> {
+        let mut dict: HashMap<Vec<u8>, usize> = (0..=255u8).map(|b| (vec![b], usize::from(b))).collect();
+        let mut codes = Vec::new();
+        let mut current = Vec::new();
+        for &byte in data {
+            let mut longer = current.clone();
+            longer.push(byte);
+            if dict.contains_key(&longer) {
+                current = longer;
+                continue;
+            }
+            codes.push(dict[&current]);
+            if clear_every > 0 && codes.iter().rev().take_while(|&&code| code != CLEAR).count() == clear_every {
+                codes.push(CLEAR);
+                dict.retain(|string, _| string.len() == 1);
+            }
+            else if dict.len() + 1 < 1 << max_bits {
+                dict.insert(longer, dict.len() + 1);
+            }
+            current = vec![byte];
+        }
+        if !current.is_empty() {
+            codes.push(dict[&current]);
+        }
+
+        // codes grow as the decompressor, which learns each entry one code late, needs them to
+        let mut out = vec![0x1f, 0x9d, BLOCK_MODE | max_bits as u8];
+        let (mut bits, mut count, mut width, mut group) = (0u64, 0u32, INIT_BITS, 0u32);
+        // codes since the start or the last clear code
+        let mut i = 0;
+        let mut write = |code: usize, width: u32, out: &mut Vec<u8>| {
+            bits |= (code as u64) << count;
+            count += width;
+            while count >= 8 {
+                out.push(bits as u8);
+                bits >>= 8;
+                count -= 8;
+            }
+        };
+        for &code in &codes {
+            let next = (CLEAR + i).min(1 << max_bits);
+            if i > 0 && next >= 1 << width && (width < max_bits || width == INIT_BITS) {
+                for _ in (group % 8..8).filter(|_| !group.is_multiple_of(8)) {
+                    write(0, width, &mut out);
+                }
+                width += 1;
+                group = 0;
+            }
+            write(code, width, &mut out);
+            group += 1;
+            i += 1;
+            if code == CLEAR {
+                for _ in (group % 8..8).filter(|_| !group.is_multiple_of(8)) {
+                    write(0, width, &mut out);
+                }
+                width = INIT_BITS;
+                group = 0;
+                i = 0;
+            }
+        }
+        let (bits, count) = (bits, count);
+        if count > 0 {
+            out.push(bits as u8);
+        }
+        out
+    }
+
+    fn decode (compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = compressed;
+        let header = Lzw.read_header(&mut input)?;
+        let mut out = Vec::new();
+        Lzw.decode(&header, &mut input, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn known_stream () {
+        // TOBEORNOTTOBEORTOBEORNOT, as `compress` writes it
+        let compressed = [0x1f, 0x9d, 0x90, 0x54, 0x9e, 0x08, 0x29, 0xf2, 0x44, 0x8a, 0x93, 0x27, 0x54, 0x02,
+            0x0e, 0x2c, 0xa8, 0x90, 0xa0, 0x41, 0x84];
+        assert_eq!(decode(&compressed).unwrap(), b"TOBEORNOTTOBEORTOBEORNOT");
+    }
+
+    #[test]
+    fn round_trip () {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+        for &max_bits in &[9, 12, 16] {
+            for &clear_every in &[0, 300, 5000] {
+                assert_eq!(decode(&compress(&data, max_bits, clear_every)).unwrap(), data,
+                    "{} bits, clearing every {} codes", max_bits, clear_every);
+            }
+        }
+        assert_eq!(decode(&compress(b"", 16, 0)).unwrap(), b"");
+    }
+
+    #[test]
+    fn bad_input () {
+        assert_eq!(decode(&[0x1f, 0x9d, 0x91, 0]).unwrap_err().to_string(),
+            "compressed with 17 bits, can only handle 16 bits");
+        // the first code must be a literal byte
+        assert_eq!(decode(&[0x1f, 0x9d, 0x90, 0xff, 0x01]).unwrap_err().to_string(), "corrupt input");
+    }
+}
@@ -1,82 +1,71 @@
-use crate::Opt;
-use crate::formats::list::List;
-use std::convert::{TryFrom, TryInto};
-use gz::GzFile;
-// use pkzip::ZipFile;
-use crate::util::WrappedFile;
-use std::io::{Read, ErrorKind};
+use crate::formats::list::ListEntry;
+use crate::options::Options;
+use std::fs::File;
+use std::io::{Chain, Cursor, ErrorKind, Read, Seek, SeekFrom};
 
+pub(crate) mod archive;
+pub mod bgzf;
+pub mod codec;
 pub mod gz;
 pub mod list;
-pub mod zip;
-// pub mod pkzip;
+pub mod lzh;
+pub mod lzw;
+pub mod pack;
+pub mod pkzip;
+pub(crate) mod tar;
+pub mod zlib;
 
-pub fn parse_list (mut wf: WrappedFile) -> Box<dyn List> {
-    let mut buf: Vec<u8> = Vec::new();
-    wf.file.read_to_end(&mut buf).unwrap();
-    let buf_len: usize = buf.len();
-    let magic_portion = buf[0..4].to_vec();
-    let path = wf.path;
-    // ... here read the file into the buf
-    if GzFile::is_magic_num(&magic_portion) {
-        if let Ok(f) = TryFrom::try_from(buf) {
-            let mut f: GzFile = f;
-            f.path = Some(path.to_path_buf());
-            return Box::new(f);
-        }
-    }
-    // else if ZipFile::is_magic_num(&magic_portion){
-    //     if let Ok(f) = TryFrom::try_from(buf.as_slice()) {
-    //         let f: ZipFile = f;
-    //         return Box::new(f);
-    //     }
-    // }
-    let f = UnknownFile::from(buf_len);
-    return Box::new(f);
-}
-
-pub struct UnknownFile {
-    compressed_size: u32
-}
-
-impl From<usize> for UnknownFile {
-    fn from (len: usize) -> Self {
-        UnknownFile { compressed_size: match len.try_into() {
-            Ok(n) => n,
-            Err(_) => u32::max_value()
-        }}
-    }
-}
+/// How many bytes at the start of a stream codecs are given to recognize it by
+pub const MAGIC_LEN: usize = 4;
 
-impl List for UnknownFile {
-    fn header_size (&self) -> f64 {
-        return 0.0;
-    }
+/// A stream whose first bytes were read to identify its format, then put back
+pub(crate) type Sniffed<R> = Chain<Cursor<Vec<u8>>, R>;
 
-    fn list (&self, opt: &Opt) -> (f64, f64) {
-        if opt.verbose > 0 {
-            print!("{:<8}{:<12}{:<8}{:<8}", "????", "????????", "????", "??:??");
+/// Reads the first `MAGIC_LEN` bytes of `input`, or all of it if it is shorter. They are
+/// replayed ahead of the rest of `input` by the returned reader, so it still yields the whole
+/// stream.
+pub(crate) fn sniff<R: Read> (mut input: R) -> std::io::Result<(Vec<u8>, Sniffed<R>)> {
+    let mut magic = [0u8; MAGIC_LEN];
+    let mut len = 0;
+    while len < MAGIC_LEN {
+        match input.read(&mut magic[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
         }
-        println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t", self.compressed_size, "??", 0.0, "????????");
-        return (self.compressed_size.into(), self.compressed_size.into());
     }
+    let magic = magic[..len].to_vec();
+    Ok((magic.clone(), Cursor::new(magic).chain(input)))
 }
 
-pub trait TryFromReadable<R>
-    where R: Read, Self: Sized {
-    fn try_from (buf: R) -> Result<Self, std::io::Error>;
+/// Describes `file` for `--list` with the codec of `opt` its magic number calls for, one entry
+/// per file an archive holds. A file in no format the codecs know is still described, with
+/// only its compressed size known.
+pub(crate) fn parse_list (file: &mut File, opt: &Options) -> std::io::Result<Vec<ListEntry>> {
+    let file_len: u64 = file.metadata()?.len();
+    let mut magic_portion: Vec<u8> = Vec::new();
+    file.take(MAGIC_LEN as u64).read_to_end(&mut magic_portion)?;
+    file.seek(SeekFrom::Start(0))?;
+    match opt.codecs.detect(&magic_portion) {
+        Some(codec) => codec.list(file, opt),
+        None => Ok(vec![unknown_entry(file_len)])
+    }
 }
 
-impl<'a, R: Read, U: TryFrom<Vec<u8>>> TryFromReadable<R> for U {
-    fn try_from (mut buf: R) -> Result<Self, std::io::Error> {
-        let mut v: Vec<u8> = Vec::new();
-        match buf.read_to_end(&mut v) {
-            Ok(_) => (),
-            Err(_) => return Err(std::io::Error::new(ErrorKind::InvalidData, ""))
-        }
-        return match Self::try_from(v){
-            Ok(s) => Ok(s),
-            Err(_) => return Err(std::io::Error::new(ErrorKind::InvalidData, ""))
-        }
+fn unknown_entry (compressed_size: u64) -> ListEntry {
+    ListEntry {
+        method: "????",
+        crc32: None,
+        mtime: None,
+        compressed_size,
+        uncompressed_size: None,
+        header_size: 0,
+        stored_name: None,
+        subfields: Vec::new(),
+        comment: None,
+        members: Vec::new(),
+        trailing_garbage: false,
+        path: None
     }
 }
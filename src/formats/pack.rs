@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Error, ErrorKind, Write};
+use crate::formats::codec::{Codec, Header, Tested};
+use crate::formats::list::ListEntry;
+use crate::options::Options;
+
+const MAGIC: [u8; 2] = [0x1f, 0x1e];
+/// the longest Huffman code pack writes
+const MAX_BITLEN: usize = 25;
+const LITERALS: usize = 256;
+
+/// The Huffman coding of the old Unix `pack` (.z files). The header holds the original size
+/// and the code tree; names and times are not recorded. It can only be read.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pack;
+
+impl Codec for Pack {
+    fn name (&self) -> &'static str {
+        "pack"
+    }
+
+    fn detect (&self, magic: &[u8]) -> bool {
+        magic.starts_with(&MAGIC)
+    }
+
+    /// Reads the magic number, the original size and the code tree
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let mut raw = vec![0u8; 7];
+        input.read_exact(&mut raw)?;
+        if raw[..2] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not in pack format"));
+        }
+        let max_len = usize::from(raw[6]);
+        if !(1..=MAX_BITLEN).contains(&max_len) {
+            return Err(corrupt("Huffman code bit length out of range"));
+        }
+        let mut leaves = vec![0u8; max_len];
+        input.read_exact(&mut leaves)?;
+        raw.extend_from_slice(&leaves);
+        // the count of the longest codes leaves out the end of block code and one more leaf
+        let literals = leaves.iter().map(|&n| usize::from(n)).sum::<usize>() + 1;
+        if literals > LITERALS {
+            return Err(corrupt("too many leaves in Huffman tree"));
+        }
+        let start = raw.len();
+        raw.resize(start + literals, 0);
+        input.read_exact(&mut raw[start..])?;
+        Ok(Header { raw, ..Header::default() })
+    }
+
+    fn decode (&self, header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        let tree = Tree::parse(&header.raw)?;
+        let mut out = BufWriter::new(output);
+        let mut bits = Bits { input, byte: 0, left: 0 };
+        let mut size = 0u64;
+        while let Some(byte) = tree.decode(&mut bits)? {
+            out.write_all(&[byte])?;
+            size += 1;
+        }
+        out.flush()?;
+        if size & 0xffff_ffff != u64::from(original_size(&header.raw)) {
+            return Err(corrupt("length error"));
+        }
+        Ok(Tested { members: 1, size, ..Tested::default() })
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        self.decode(header, input, &mut io::sink())
+    }
+
+    /// Describes `file` with the original size its header records
+    fn list (&self, file: &mut File, _opt: &Options) -> io::Result<Vec<ListEntry>> {
+        let compressed_size = file.metadata()?.len();
+        let header = self.read_header(&mut io::BufReader::new(file))?;
+        Ok(vec![ListEntry {
+            method: self.name(),
+            crc32: None,
+            mtime: None,
+            compressed_size,
+            uncompressed_size: Some(u64::from(original_size(&header.raw))),
+            header_size: header.raw.len() as u64,
+            stored_name: None,
+            subfields: Vec::new(),
+            comment: None,
+            members: Vec::new(),
+            trailing_garbage: false,
+            path: None
+        }])
+    }
+}
+
+fn original_size (raw: &[u8]) -> u32 {
+    u32::from_be_bytes([raw[2], raw[3], raw[4], raw[5]])
+}
+
+/// The code tree of a pack header. At each length the codes numbered below `parents` lead to
+/// longer codes and the rest are leaves, in the order their literals are stored; the end of
+/// block code is the last of the longest codes.
+struct Tree {
+    /// per code length, starting at 1
+    leaves: Vec<usize>,
+    parents: Vec<usize>,
+    /// where the literals of each length start in `literals`
+    base: Vec<usize>,
+    literals: Vec<u8>
+}
+
+impl Tree {
+    fn parse (raw: &[u8]) -> io::Result<Self> {
+        let max_len = usize::from(raw[6]);
+        let mut leaves: Vec<usize> = raw[7..7 + max_len].iter().map(|&n| usize::from(n)).collect();
+        let mut max_leaves = 1usize;
+        for (i, &n) in leaves.iter().enumerate() {
+            let last = usize::from(i + 1 == max_len);
+            if max_leaves < n + last {
+                return Err(corrupt("too many leaves in Huffman tree"));
+            }
+            max_leaves = (max_leaves - n + 1) * 2 - 1;
+        }
+        leaves[max_len - 1] += 2;
+        let mut base = Vec::with_capacity(max_len);
+        let mut parents = vec![0; max_len];
+        let mut start = 0;
+        for &n in &leaves {
+            base.push(start);
+            start += n;
+        }
+        let mut nodes = 0;
+        for len in (0..max_len).rev() {
+            nodes >>= 1;
+            parents[len] = nodes;
+            nodes += leaves[len];
+        }
+        if nodes >> 1 != 1 {
+            return Err(corrupt("too few leaves in Huffman tree"));
+        }
+        Ok(Tree { leaves, parents, base, literals: raw[7 + max_len..].to_vec() })
+    }
+
+    /// The next byte, or `None` at the end of block code
+    fn decode (&self, bits: &mut Bits) -> io::Result<Option<u8>> {
+        let mut code = 0;
+        for len in 0..self.leaves.len() {
+            code = code << 1 | bits.next()?;
+            if code >= self.parents[len] {
+                let leaf = code - self.parents[len];
+                if len + 1 == self.leaves.len() && leaf + 1 == self.leaves[len] {
+                    return Ok(None);
+                }
+                return Ok(Some(self.literals[self.base[len] + leaf]));
+            }
+        }
+        Err(corrupt("bad code"))
+    }
+}
+
+/// Reads bits most significant first
+struct Bits<'a> {
+    input: &'a mut dyn BufRead,
+    byte: u8,
+    left: u32
+}
+
+impl Bits<'_> {
+    fn next (&mut self) -> io::Result<usize> {
+        if self.left == 0 {
+            let mut byte = [0u8];
+            self.input.read_exact(&mut byte)?;
+            self.byte = byte[0];
+            self.left = 8;
+        }
+        self.left -= 1;
+        Ok(usize::from(self.byte >> self.left & 1))
+    }
+}
+
+fn corrupt (reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid compressed data -- {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Packs `data`, which must hold at least one byte, with the shortest codes for the
+    /// smallest bytes
+    fn pack (data: &[u8]) -> Vec<u8> {
+        let bytes: Vec<u8> = data.iter().cloned().collect::<BTreeSet<u8>>().into_iter().collect();
+        // a complete code: the first `short` symbols get `bits` bits and the rest one more,
+        // the end of block code last
+        let symbols = bytes.len() + 1;
+        let bits = (usize::BITS - 1 - symbols.leading_zeros()) as usize;
+        let short = if symbols == 1 << bits { symbols } else { (2 << bits) - symbols };
+        let max_len = if short == symbols { bits } else { bits + 1 };
+        let mut leaves = vec![0usize; max_len];
+        leaves[bits - 1] += short;
+        if max_len > bits {
+            leaves[max_len - 1] += symbols - short;
+        }
+        let mut parents = vec![0; max_len];
+        let mut nodes = 0;
+        for len in (0..max_len).rev() {
+            nodes >>= 1;
+            parents[len] = nodes;
+            nodes += leaves[len];
+        }
+        let code = |i: usize| if i < short {
+            (parents[bits - 1] + i, bits)
+        }
+        else {
+            (parents[max_len - 1] + i - short, max_len)
+        };
+
+        let mut out = vec![0x1f, 0x1e];
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.push(max_len as u8);
+        let mut counts = leaves.clone();
+        counts[max_len - 1] -= 2;
+        out.extend(counts.iter().map(|&n| n as u8));
+        out.extend_from_slice(&bytes);
+        let (mut acc, mut count) = (0u64, 0);
+        let codes = data.iter().map(|b| code(bytes.binary_search(b).unwrap())).chain(Some(code(bytes.len())));
+        for (value, len) in codes {
+            acc = acc << len | value as u64;
+            count += len;
+            while count >= 8 {
+                count -= 8;
+                out.push((acc >> count) as u8);
+            }
+        }
+        if count > 0 {
+            out.push((acc << (8 - count)) as u8);
+        }
+        out
+    }
+
+    fn decode (compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = compressed;
+        let header = Pack.read_header(&mut input)?;
+        let mut out = Vec::new();
+        Pack.decode(&header, &mut input, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn known_stream () {
+        // a stream in the layout `pack` writes, which gzip's unpack decodes to the same text
+        let packed = [0x1f, 0x1e, 0x00, 0x00, 0x00, 0x25, 0x04, 0x00, 0x00, 0x04, 0x06, 0x20, 0x61, 0x62, 0x65,
+            0x68, 0x6c, 0x6f, 0x72, 0x73, 0x74, 0x79, 0x40, 0xf1, 0x38, 0x8a, 0x44, 0xf6, 0x20, 0x71, 0x14, 0x99,
+            0xa2, 0x87, 0x89, 0xec, 0x40, 0x23, 0xee];
+        assert_eq!(decode(&packed).unwrap(), b"she sells sea shells by the sea shore");
+    }
+
+    #[test]
+    fn round_trip () {
+        let text = b"she sells sea shells by the sea shore".to_vec();
+        let all: Vec<u8> = (0..=255u8).cycle().take(3000).collect();
+        for data in &[text, all, b"ab".to_vec(), b"aaaa".to_vec()] {
+            assert_eq!(&decode(&pack(data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn bad_input () {
+        let mut packed = pack(b"she sells sea shells");
+        packed[5] += 1;
+        assert_eq!(decode(&packed).unwrap_err().to_string(), "invalid compressed data -- length error");
+        // three leaves for the two codes of one bit
+        assert_eq!(decode(&[0x1f, 0x1e, 0, 0, 0, 1, 1, 1, b'a', b'b']).unwrap_err().to_string(),
+            "invalid compressed data -- too many leaves in Huffman tree");
+        assert_eq!(decode(&[0x1f, 0x1e, 0, 0, 0, 1, 0]).unwrap_err().to_string(),
+            "invalid compressed data -- Huffman code bit length out of range");
+    }
+}
@@ -1,125 +1,593 @@
-extern crate zip;
-
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use flate2::CrcWriter;
+use flate2::bufread::DeflateDecoder;
+use deflate64::Deflate64Decoder;
+use crate::formats::codec::{Codec, Header, Tested};
+use crate::formats::archive::unix_mode;
+use crate::formats::list::ListEntry;
+use crate::options::Options;
 use crate::util::WorkData;
-use crate::Opt;
-use crate::formats::zip::Zip;
-use crate::formats::list::List;
-use std::io::{Read, Write};
-use std::io;
-use std::convert::{TryFrom, TryInto};
-use chrono::{NaiveDateTime, Datelike, Timelike};
-use zip::DateTime;
-
-pub struct ZipFile<'a> {
-    file: zip::read::ZipFile<'a>
-}
-
-impl Zip for ZipFile<'_> {
-    fn decompress (mut self) -> Result<Vec<u8>, std::io::Error> {
-        let mut v: Vec<u8> = Vec::new();
-        self.file.read_to_end(&mut v);
-        Ok(v)
-    }
-
-    fn compress<R: Read> (mut input: R, wdata: Option<WorkData>, opt: &Opt) -> Result<Vec<u8>, io::Error> {
-        let mut inbuf: Vec<u8> = Vec::new();
-        input.read_to_end(&mut inbuf);
-        let mut name: std::string::String = String::from("thing");
-        let opts = match wdata {
-            Some(data) => {
-                if let Some(orig_name) = data.orig_name {
-                    name = orig_name;
-                }
-                match data.mtime {
-                    Some(time) => {
-                        let time = NaiveDateTime::from_timestamp(time.0, time.1.try_into().unwrap());
-                        let year: u16 = time.year().try_into().unwrap();
-                        let boundedYear: u16 = if year < 1980 {
-                            1980
-                        } else if year > 2107 {
-                            2107
-                        }
-                        else {
-                            year
-                        };
-                        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated)
-                            .last_modified_time(
-                                DateTime::from_date_and_time(
-                                    boundedYear,
-                                    time.month().try_into().unwrap(),
-                                    time.day().try_into().unwrap(),
-                                    time.hour().try_into().unwrap(),
-                                    time.minute().try_into().unwrap(),
-                                    time.second().try_into().unwrap()
-                                ).unwrap())
-                            .compression_method(zip::CompressionMethod::Deflated)
-                    },
-                    None => zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated)
-                }
+
+mod archive;
+pub use archive::ZipWriter;
+pub(crate) use archive::extract;
+
+const LOCAL_SIG: [u8; 4] = *b"PK\x03\x04";
+const CENTRAL_SIG: [u8; 4] = *b"PK\x01\x02";
+const END_SIG: [u8; 4] = *b"PK\x05\x06";
+const ZIP64_END_SIG: [u8; 4] = *b"PK\x06\x06";
+const ZIP64_LOCATOR_SIG: [u8; 4] = *b"PK\x06\x07";
+const DESCRIPTOR_SIG: [u8; 4] = *b"PK\x07\x08";
+const LOCAL_LEN: usize = 30;
+const CENTRAL_LEN: usize = 46;
+const END_LEN: usize = 22;
+const ZIP64_END_LEN: usize = 56;
+const ZIP64_LOCATOR_LEN: usize = 20;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const DEFLATE64: u16 = 9;
+/// general purpose flag: the entry is encrypted
+const ENCRYPTED: u16 = 0b0001;
+/// general purpose flag: the CRC and sizes follow the data, in a data descriptor
+const DESCRIPTOR: u16 = 0b1000;
+/// general purpose flag: the name is UTF-8
+const UTF8: u16 = 1 << 11;
+/// high byte of "version made by" for archives made on Unix, whose external attributes hold
+/// the mode in their upper 16 bits
+const UNIX: u16 = 3;
+/// ID of the extended timestamp extra field, which holds the mtime as Unix time
+const EXTENDED_TIME: u16 = 0x5455;
+/// ID of the ZIP64 extra field, which holds the 64 bit values of fields set to all ones
+const ZIP64: u16 = 0x0001;
+/// a 32 bit field whose real value is in the ZIP64 extra field
+const ZIP64_MARK: u32 = u32::MAX;
+
+/// The ZIP format. As a codec it is read the way gunzip reads it: the first entry of an archive
+/// is decompressed, and any further entries are ignored with a warning. Whole archives are
+/// written by `ZipWriter` and extracted by `extract`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pkzip;
+
+impl Codec for Pkzip {
+    fn name (&self) -> &'static str {
+        "zip"
+    }
+
+    fn detect (&self, magic: &[u8]) -> bool {
+        magic.starts_with(&LOCAL_SIG)
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let mut raw = vec![0u8; LOCAL_LEN];
+        input.read_exact(&mut raw)?;
+        if raw[..4] != LOCAL_SIG {
+            return Err(Error::new(ErrorKind::InvalidData, "not a valid zip file"));
+        }
+        let variable = usize::from(le16(&raw[26..])) + usize::from(le16(&raw[28..]));
+        raw.resize(LOCAL_LEN + variable, 0);
+        input.read_exact(&mut raw[LOCAL_LEN..])?;
+        let entry = LocalHeader::parse(&raw);
+        Ok(Header {
+            name: Some(entry.name),
+            mtime: entry.mtime,
+            raw,
+            ..Header::default()
+        })
+    }
+
+    fn decode (&self, header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        decode_entry(&LocalHeader::parse(&header.raw), input, output)
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        decode_entry(&LocalHeader::parse(&header.raw), input, &mut io::sink())
+    }
+
+    /// Writes an archive holding `input` as its only entry, named after the input file
+    fn encode (&self, input: &mut dyn Read, output: &mut dyn Write, wdata: Option<WorkData>,
+        opt: &Options) -> io::Result<()>
+    {
+        let (name, mtime, mode) = match wdata {
+            Some(wdata) => {
+                let mode = wdata.orig_name.as_ref().and_then(|name| fs::metadata(name).ok()).map(|meta| unix_mode(&meta));
+                let name = wdata.orig_name.filter(|_| opt.name)
+                    .and_then(|name| Path::new(&name).file_name().map(|name| name.to_string_lossy().into_owned()));
+                (name, wdata.mtime.filter(|_| opt.time).map(|t| t.0), mode)
             },
-            None => zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated)
+            None => (None, None, None)
         };
-        let buf: Vec<u8> = Vec::new();
-        let outbuf = std::io::Cursor::new(buf);
-        let mut zip = zip::ZipWriter::new(outbuf);
-        zip.start_file(name, opts)?;
-        zip.write(&inbuf);
-        match zip.finish() {
-            Ok(seekable_buf) => Ok(seekable_buf.into_inner()),
-            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "pkzip compression error"))
+        let mut zip = ZipWriter::new(output, opt.level);
+        zip.add_file(name.as_deref().unwrap_or("-"), mtime, mode.unwrap_or(0o644), input)?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Describes every entry, from the central directory at the end of the archive
+    fn list (&self, file: &mut File, _opt: &Options) -> io::Result<Vec<ListEntry>> {
+        Ok(directory(file)?.into_iter().map(|entry| ListEntry {
+            method: method_name(entry.method),
+            crc32: Some(entry.crc32),
+            mtime: entry.mtime,
+            compressed_size: entry.compressed_size + entry.header_len,
+            uncompressed_size: Some(entry.size),
+            header_size: entry.header_len,
+            stored_name: Some(entry.name),
+            subfields: Vec::new(),
+            comment: None,
+            members: Vec::new(),
+            trailing_garbage: false,
+            path: None
+        }).collect())
+    }
+}
+
+/// The fields of a local file header, or of a central directory record, that rstzip uses
+#[derive(Debug, Clone)]
+struct LocalHeader {
+    flags: u16,
+    method: u16,
+    mtime: Option<i64>,
+    crc32: u32,
+    compressed_size: u64,
+    size: u64,
+    name: String,
+    /// length of the local header, name and extra field included
+    header_len: u64,
+    /// Unix mode, only recorded in the central directory of archives made on Unix
+    mode: Option<u32>,
+    /// where the local header starts, only recorded in the central directory
+    offset: u64,
+    /// whether the local header has a ZIP64 extra field, which makes the sizes in a data
+    /// descriptor 64 bits long
+    zip64: bool
+}
+
+impl LocalHeader {
+    /// Parses a whole local file header, as checked by `read_header`
+    fn parse (raw: &[u8]) -> Self {
+        let name_end = LOCAL_LEN + usize::from(le16(&raw[26..]));
+        let extra = &raw[name_end..];
+        let zip64 = extra_field(extra, ZIP64);
+        let mut values = Zip64Values(zip64.unwrap_or(&[]));
+        LocalHeader {
+            flags: le16(&raw[6..]),
+            method: le16(&raw[8..]),
+            mtime: extended_time(extra).or_else(|| dos_time(le16(&raw[12..]), le16(&raw[10..]))),
+            crc32: le32(&raw[14..]),
+            // the ZIP64 field holds the size first
+            size: values.or(le32(&raw[22..])),
+            compressed_size: values.or(le32(&raw[18..])),
+            name: String::from_utf8_lossy(&raw[LOCAL_LEN..name_end]).into_owned(),
+            header_len: raw.len() as u64,
+            mode: None,
+            offset: 0,
+            zip64: zip64.is_some()
         }
     }
 
-    fn compress_into<R: Read> (input: R, wdata: Option<WorkData>, opt: &Opt) -> Result<Box<Self>, io::Error> {
-        let mut compressed = Self::compress (input, wdata, opt)?;
-        let mut slice = &compressed[..];
-        match zip::read::read_zipfile_from_stream(&mut slice) {
-            Ok(opt) => match opt {
-                Some(file) => Ok(Box::new(ZipFile {file})),
-                None => Err(io::Error::new(io::ErrorKind::InvalidData, "pkzip compression error"))
-            },
-            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "pkzip compression error"))
+    fn is_dir (&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// Decompresses the entry whose local header is `entry` from `input` into `output`, checking
+/// its CRC and size
+fn decode_entry (entry: &LocalHeader, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+    if entry.flags & ENCRYPTED != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "encrypted file -- use unzip"));
+    }
+    let mut out = CrcWriter::new(output);
+    let size = match entry.method {
+        DEFLATED => io::copy(&mut DeflateDecoder::new(&mut *input), &mut out).map_err(format_violated)?,
+        // the Deflate64 decoder may read a little past the end of the data, so it can only be
+        // given the data when its size is known, and not from the data descriptor after it
+        DEFLATE64 if entry.flags & DESCRIPTOR == 0 => {
+            let mut body = Deflate64Decoder::with_buffer((&mut *input).take(entry.compressed_size));
+            io::copy(&mut body, &mut out).map_err(format_violated)?
+        },
+        DEFLATE64 => return Err(Error::new(ErrorKind::InvalidData,
+            "first entry is Deflate64 with a data descriptor -- use unzip")),
+        STORED if entry.flags & DESCRIPTOR == 0 => {
+            let copied = io::copy(&mut (&mut *input).take(entry.compressed_size), &mut out)?;
+            if copied != entry.compressed_size {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            copied
+        },
+        _ => return Err(Error::new(ErrorKind::InvalidData, "first entry not deflated or stored -- use unzip"))
+    };
+    let (crc32, expected) = if entry.flags & DESCRIPTOR != 0 {
+        // the descriptor's signature is optional; its sizes are 64 bits long in ZIP64 entries
+        let mut word = [0u8; 4];
+        input.read_exact(&mut word)?;
+        if word == DESCRIPTOR_SIG {
+            input.read_exact(&mut word)?;
+        }
+        let mut sizes = [0u8; 16];
+        if entry.zip64 {
+            input.read_exact(&mut sizes)?;
+            (le32(&word), le64(&sizes[8..]))
+        }
+        else {
+            input.read_exact(&mut sizes[..8])?;
+            (le32(&word), le32(&sizes[4..]).into())
         }
     }
+    else {
+        (entry.crc32, entry.size)
+    };
+    if crc32 != out.crc().sum() {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--crc error"));
+    }
+    if size != expected {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--length error"));
+    }
+    Ok(Tested {
+        members: 1,
+        size,
+        more_entries: input.fill_buf()?.starts_with(&LOCAL_SIG),
+        ..Tested::default()
+    })
 }
 
-impl <'a> TryFrom<&'a [u8]> for ZipFile<'a> {
-    type Error = std::io::Error;
-    fn try_from (buf: &'a [u8]) -> Result<Self, Self::Error> {
-        match zip::read::read_zipfile_from_stream(&buf) {
-            Ok(opt_zfile) => match opt_zfile {
-                Some(zfile) => Ok(ZipFile {file: zfile}),
-                None => Err(Self::Error::new(io::ErrorKind::InvalidData, "could not read zip file from stream"))
-            },
-            Err(_) => Err(Self::Error::new(io::ErrorKind::InvalidData, "could not read zip file from stream"))
+fn format_violated (e: Error) -> Error {
+    match e.kind() {
+        ErrorKind::UnexpectedEof => e,
+        _ => Error::new(ErrorKind::InvalidData, "invalid compressed data--format violated")
+    }
+}
+
+/// Reads the central directory of the archive `file`: one record per entry, in the order they
+/// are stored
+fn directory (file: &mut File) -> io::Result<Vec<LocalHeader>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // the end record is followed by a comment of at most 65535 bytes
+    let tail_len = len.min((END_LEN + usize::from(u16::MAX)) as u64);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    file.read_exact(&mut tail)?;
+    let end = tail.len().checked_sub(END_LEN)
+        .and_then(|last| (0..=last).rev().find(|&i| tail[i..].starts_with(&END_SIG)))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "end of central directory not found"))?;
+    let mut count = u64::from(le16(&tail[end + 10..]));
+    let mut start = u64::from(le32(&tail[end + 16..]));
+
+    // a ZIP64 archive locates its ZIP64 end record just ahead of the end record
+    if let Some(locator) = end.checked_sub(ZIP64_LOCATOR_LEN).map(|i| &tail[i..end]) {
+        if locator.starts_with(&ZIP64_LOCATOR_SIG) {
+            let mut zip64_end = [0u8; ZIP64_END_LEN];
+            file.seek(SeekFrom::Start(le64(&locator[8..])))?;
+            file.read_exact(&mut zip64_end)?;
+            if zip64_end[..4] != ZIP64_END_SIG {
+                return Err(Error::new(ErrorKind::InvalidData, "bad zip64 end of central directory"));
+            }
+            count = le64(&zip64_end[32..]);
+            start = le64(&zip64_end[48..]);
         }
     }
+
+    let mut records = BufReader::new(&mut *file);
+    records.seek(SeekFrom::Start(start))?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut record = [0u8; CENTRAL_LEN];
+        records.read_exact(&mut record)?;
+        if record[..4] != CENTRAL_SIG {
+            return Err(Error::new(ErrorKind::InvalidData, "bad central directory"));
+        }
+        let mut name = vec![0u8; usize::from(le16(&record[28..]))];
+        records.read_exact(&mut name)?;
+        let mut extra = vec![0u8; usize::from(le16(&record[30..]))];
+        records.read_exact(&mut extra)?;
+        io::copy(&mut (&mut records).take(le16(&record[32..]).into()), &mut io::sink())?;
+        let external = le32(&record[38..]);
+        let zip64 = extra_field(&extra, ZIP64);
+        let mut values = Zip64Values(zip64.unwrap_or(&[]));
+        entries.push(LocalHeader {
+            flags: le16(&record[8..]),
+            method: le16(&record[10..]),
+            mtime: extended_time(&extra).or_else(|| dos_time(le16(&record[14..]), le16(&record[12..]))),
+            crc32: le32(&record[16..]),
+            // the ZIP64 field holds the values in this order, each only if its field is all ones
+            size: values.or(le32(&record[24..])),
+            compressed_size: values.or(le32(&record[20..])),
+            offset: values.or(le32(&record[42..])),
+            name: String::from_utf8_lossy(&name).into_owned(),
+            header_len: 0,
+            mode: Some(external >> 16).filter(|&mode| le16(&record[4..]) >> 8 == UNIX && mode != 0),
+            zip64: zip64.is_some()
+        });
+    }
+
+    // the local header's extra field may differ from the central one, so measure it there
+    for entry in &mut entries {
+        entry.header_len = local_header_len(file, entry.offset)?;
+    }
+    Ok(entries)
 }
 
-impl List for ZipFile<'_> {
-    fn list(&self, opt: &Opt) -> (f64, f64) {
-        let datetime = self.file.last_modified();
-        let datestring = Self::datestring_raw (datetime.month (), datetime.day ());
-        let timestring = Self::timestring_raw (datetime.hour (), datetime.minute ());
-        if opt.verbose > 0 {
-            print!("{:<8}{:<12x}{:<8}{:<8}", "defla", self.file.crc32(), datestring, timestring);
+/// The length of the local header at `offset`, name and extra field included. `file` is left
+/// positioned just past it, at the entry's data.
+fn local_header_len (file: &mut File, offset: u64) -> io::Result<u64> {
+    let mut local = [0u8; LOCAL_LEN];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut local)?;
+    if local[..4] != LOCAL_SIG {
+        return Err(Error::new(ErrorKind::InvalidData, "bad local header"));
+    }
+    let len = (LOCAL_LEN + usize::from(le16(&local[26..])) + usize::from(le16(&local[28..]))) as u64;
+    file.seek(SeekFrom::Start(offset + len))?;
+    Ok(len)
+}
+
+/// The name of a ZIP compression method, as shown in the method column of `--list`
+fn method_name (method: u16) -> &'static str {
+    match method {
+        STORED => "store",
+        DEFLATED => "defla",
+        DEFLATE64 => "def64",
+        _ => "????"
+    }
+}
+
+/// The data of the field with ID `id` in the extra field `extra`, if it has one
+fn extra_field (mut extra: &[u8], id: u16) -> Option<&[u8]> {
+    while extra.len() >= 4 {
+        let len = usize::from(le16(&extra[2..]));
+        let data = extra.get(4..4 + len)?;
+        if le16(extra) == id {
+            return Some(data);
         }
-        let compr_size: u32 = self.file.compressed_size().try_into().unwrap();
-        let uncompr_size: u32 = self.file.size().try_into().unwrap();
-        let ratio = Self::calculate_ratio (compr_size.try_into().unwrap(),
-            Some(uncompr_size.try_into().unwrap()), self.header_size());
-        println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t", self.file.compressed_size(),
-            self.file.size(), ratio, self.file.name());
-        (compr_size.try_into().unwrap(), uncompr_size.try_into().unwrap())
+        extra = &extra[4 + len..];
     }
+    None
+}
 
-    fn header_size(&self) -> f64 {
-        return 30.0;
+/// The mtime held in an extended timestamp extra field, if `extra` has one
+fn extended_time (extra: &[u8]) -> Option<i64> {
+    // the first byte flags which times follow, the mtime coming first
+    match extra_field(extra, EXTENDED_TIME)? {
+        &[flags, a, b, c, d, ..] if flags & 1 != 0 => Some(i32::from_le_bytes([a, b, c, d]).into()),
+        _ => None
     }
 }
 
-impl ZipFile<'_> {
-    pub fn is_magic_num (bytes: &[u8]) -> bool {
-        return (bytes[0] == 0x4b) && (bytes[1] == 0x50) && (bytes[2] == 0x03) && (bytes[3] == 0x04)
+/// The 64 bit values of a ZIP64 extra field, taken in turn by the fields set to all ones
+struct Zip64Values<'a> (&'a [u8]);
+
+impl Zip64Values<'_> {
+    /// The value of a 32 bit field: its own, or the next ZIP64 value if it is all ones
+    fn or (&mut self, field: u32) -> u64 {
+        if field != ZIP64_MARK || self.0.len() < 8 {
+            return field.into();
+        }
+        let value = le64(self.0);
+        self.0 = &self.0[8..];
+        value
+    }
+}
+
+/// Converts an MS-DOS date and time, which are in local time, to seconds since the epoch
+fn dos_time (date: u16, time: u16) -> Option<i64> {
+    let year = 1980 + i32::from(date >> 9);
+    let month = u32::from((date >> 5) & 0x0f);
+    let day = u32::from(date & 0x1f);
+    let hour = u32::from(time >> 11);
+    let minute = u32::from((time >> 5) & 0x3f);
+    let second = u32::from(time & 0x1f) * 2;
+    Local.with_ymd_and_hms(year, month, day, hour, minute, second).earliest().map(|t| t.timestamp())
+}
+
+/// Converts seconds since the epoch to an MS-DOS date and time, clamped to the years MS-DOS
+/// can represent, 1980 through 2107. Seconds are rounded down to an even number.
+fn to_dos_time (mtime: i64) -> (u16, u16) {
+    let t = match Local.timestamp_opt(mtime, 0).single() {
+        Some(t) if t.year() < 1980 => return (0x21, 0),
+        Some(t) if t.year() > 2107 => return (0xff9f, 0xbf7d),
+        Some(t) => t,
+        None => return (0x21, 0)
+    };
+    let date = ((t.year() - 1980) as u16) << 9 | (t.month() as u16) << 5 | t.day() as u16;
+    let time = (t.hour() as u16) << 11 | (t.minute() as u16) << 5 | (t.second() / 2) as u16;
+    (date, time)
+}
+
+fn le16 (bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32 (bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64 (bytes: &[u8]) -> u64 {
+    let mut le = [0u8; 8];
+    le.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(le)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+
+    /// An archive holding `entries`, deflated, with or without data descriptors
+    fn archive (entries: &[(&str, &[u8])], descriptor: bool) -> Vec<u8> {
+        let deflated: Vec<_> = entries.iter().map(|&(name, data)| {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            (name, data, DEFLATED, encoder.finish().unwrap())
+        }).collect();
+        packed(&deflated, descriptor)
+    }
+
+    /// An archive holding `entries`: their names, their data, and the method and bytes they are
+    /// compressed with
+    fn packed (entries: &[(&str, &[u8], u16, Vec<u8>)], descriptor: bool) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data, method, deflated) in entries {
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            let mut sizes = Vec::new();
+            sizes.extend_from_slice(&crc.sum().to_le_bytes());
+            sizes.extend_from_slice(&(deflated.len() as u32).to_le_bytes());
+            sizes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            let flags: u16 = if descriptor { DESCRIPTOR } else { 0 };
+            // 2019-05-17 13:42:18
+            let fixed = [&20u16.to_le_bytes()[..], &flags.to_le_bytes(), &method.to_le_bytes(),
+                &0x6d49u16.to_le_bytes(), &0x4eb1u16.to_le_bytes()].concat();
+
+            directory.extend_from_slice(&CENTRAL_SIG);
+            directory.extend_from_slice(&20u16.to_le_bytes());
+            directory.extend_from_slice(&fixed);
+            directory.extend_from_slice(&sizes);
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&(zip.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+
+            zip.extend_from_slice(&LOCAL_SIG);
+            zip.extend_from_slice(&fixed);
+            zip.extend_from_slice(if descriptor { &[0; 12] } else { &sizes[..] });
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend_from_slice(&[0, 0]);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(deflated);
+            if descriptor {
+                zip.extend_from_slice(&DESCRIPTOR_SIG);
+                zip.extend_from_slice(&sizes);
+            }
+        }
+        let offset = zip.len() as u32;
+        zip.extend_from_slice(&directory);
+        zip.extend_from_slice(&END_SIG);
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&offset.to_le_bytes());
+        zip.extend_from_slice(&[0, 0]);
+        zip
+    }
+
+    fn decode (zip: &[u8]) -> io::Result<(Header, Vec<u8>, Tested)> {
+        let mut input = zip;
+        let header = Pkzip.read_header(&mut input)?;
+        let mut out = Vec::new();
+        let tested = Pkzip.decode(&header, &mut input, &mut out)?;
+        Ok((header, out, tested))
+    }
+
+    #[test]
+    fn first_entry_only () {
+        for &descriptor in &[false, true] {
+            let zip = archive(&[("dir/first.txt", b"the first entry"), ("second.txt", b"the second")], descriptor);
+            assert!(Pkzip.detect(&zip));
+            let (header, out, tested) = decode(&zip).unwrap();
+            assert_eq!(header.name.as_deref(), Some("dir/first.txt"));
+            assert_eq!(header.mtime, Local.with_ymd_and_hms(2019, 5, 17, 13, 42, 18).earliest().map(|t| t.timestamp()));
+            assert_eq!(out, b"the first entry");
+            assert!(tested.more_entries);
+            let (_, _, tested) = decode(&archive(&[("only", b"one")], descriptor)).unwrap();
+            assert!(!tested.more_entries);
+        }
+        let mut corrupt = archive(&[("only", b"one entry")], false);
+        corrupt[14] ^= 1;
+        assert_eq!(decode(&corrupt).unwrap_err().to_string(), "invalid compressed data--crc error");
+    }
+
+    #[test]
+    fn list_entries () {
+        let zip = archive(&[("first.txt", &[b'a'; 1000][..]), ("second.txt", b"the second")], false);
+        let path = std::env::temp_dir().join(format!("rstzip-pkzip-{}", std::process::id()));
+        std::fs::write(&path, &zip).unwrap();
+        let entries = Pkzip.list(&mut File::open(&path).unwrap(), &Options::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].stored_name.as_deref(), Some("second.txt"));
+        let entry = &entries[0];
+        assert_eq!(entry.method, "defla");
+        assert_eq!(entry.uncompressed_size, Some(1000));
+        assert_eq!(entry.stored_name.as_deref(), Some("first.txt"));
+        assert_eq!(entry.header_size, (LOCAL_LEN + "first.txt".len()) as u64);
+    }
+
+    #[test]
+    fn deflate64 () {
+        // fixed Huffman codes for the alphabet, then a match of 39974 bytes at distance 26,
+        // then "!" and a match of 40000 bytes at distance 40001: the length code 285 with 16
+        // extra bits and the distance code 30 only Deflate64 has. unzip gives the same data.
+        let body = [0x4b, 0x4c, 0x4a, 0x4e, 0x49, 0x4d, 0x4b, 0xcf, 0xc8, 0xcc, 0xca, 0xce, 0xc9, 0xcd, 0xcb, 0x2f,
+            0x28, 0x2c, 0x2a, 0x2e, 0x29, 0x2d, 0x2b, 0xaf, 0xa8, 0xac, 0x1a, 0x1d, 0xe1, 0x94, 0x51, 0x1c, 0xed, 0xe1,
+            0x7c, 0x40, 0x1c, 0x00];
+        let alphabet: Vec<u8> = (b'a'..=b'z').cycle().take(40_000).collect();
+        let data = [&alphabet[..], b"!", &alphabet[..]].concat();
+        let entries = [("d64.txt", &data[..], DEFLATE64, body.to_vec())];
+        let (_, out, tested) = decode(&packed(&entries, false)).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(tested.size, 80_001);
+        // without its size, the data can't be told apart from the descriptor after it
+        assert_eq!(decode(&packed(&entries, true)).unwrap_err().to_string(),
+            "first entry is Deflate64 with a data descriptor -- use unzip");
+        let mut corrupt = packed(&entries, false);
+        corrupt[LOCAL_LEN + "d64.txt".len() + 30] ^= 0xff;
+        assert!(decode(&corrupt).is_err());
+    }
+
+    #[test]
+    fn bad_input () {
+        // an end record cut short after its signature
+        let path = std::env::temp_dir().join(format!("rstzip-truncated-{}", std::process::id()));
+        std::fs::write(&path, b"PK\x05\x06abcdef").unwrap();
+        let err = directory(&mut File::open(&path).unwrap()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.to_string(), "end of central directory not found");
+    }
+
+    #[test]
+    fn zip64_sizes () {
+        let size: u64 = 5 << 30;
+        let compressed: u64 = (4 << 30) + 7;
+        let zip64 = [&ZIP64.to_le_bytes()[..], &16u16.to_le_bytes(), &size.to_le_bytes(), &compressed.to_le_bytes()].concat();
+        let fields = [&[0u8; 4][..], &0x6d49u16.to_le_bytes(), &0x4eb1u16.to_le_bytes(), &[0; 4], &[0xff; 8],
+            &4u16.to_le_bytes(), &(zip64.len() as u16).to_le_bytes()].concat();
+        let mut zip = [&LOCAL_SIG[..], &45u16.to_le_bytes(), &fields, b"huge", &zip64].concat();
+        let start = zip.len() as u64;
+        let directory = [&CENTRAL_SIG[..], &45u16.to_le_bytes(), &45u16.to_le_bytes(), &fields, &[0; 10],
+            &0u32.to_le_bytes(), b"huge", &zip64].concat();
+        zip.extend_from_slice(&directory);
+        let zip64_end = zip.len() as u64;
+        zip.extend_from_slice(&ZIP64_END_SIG);
+        zip.extend_from_slice(&44u64.to_le_bytes());
+        zip.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        zip.extend_from_slice(&1u64.to_le_bytes());
+        zip.extend_from_slice(&1u64.to_le_bytes());
+        zip.extend_from_slice(&(directory.len() as u64).to_le_bytes());
+        zip.extend_from_slice(&start.to_le_bytes());
+        zip.extend_from_slice(&ZIP64_LOCATOR_SIG);
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&zip64_end.to_le_bytes());
+        zip.extend_from_slice(&1u32.to_le_bytes());
+        zip.extend_from_slice(&END_SIG);
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&[0xff; 4]);
+        zip.extend_from_slice(&[0xff; 8]);
+        zip.extend_from_slice(&[0, 0]);
+
+        let path = std::env::temp_dir().join(format!("rstzip-zip64-{}", std::process::id()));
+        std::fs::write(&path, &zip).unwrap();
+        let entries = Pkzip.list(&mut File::open(&path).unwrap(), &Options::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].uncompressed_size, Some(size));
+        assert_eq!(entries[0].compressed_size, compressed + (LOCAL_LEN + 4 + zip64.len()) as u64);
+        let header = LocalHeader::parse(&zip[..LOCAL_LEN + 4 + zip64.len()]);
+        assert_eq!((header.size, header.compressed_size, header.zip64), (size, compressed, true));
     }
 }
@@ -0,0 +1,310 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use flate2::{Compression, CrcWriter};
+use flate2::write::DeflateEncoder;
+use crate::formats::archive::{check_no_links, restore, safe_path};
+use crate::formats::codec::Tested;
+use crate::options::Options;
+use super::*;
+
+/// "version needed to extract": 2.0, for deflate and directories
+const VERSION: u16 = 20;
+/// file type bits of a Unix mode
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+/// the MS-DOS directory attribute, in the low byte of the external attributes
+const DOS_DIR: u32 = 0x10;
+
+/// Writes a ZIP archive to a stream, one entry at a time. The CRC and sizes of each file
+/// follow its data in a data descriptor, so the output is never sought back into and can go
+/// to a pipe. The central directory is kept in memory until `finish`.
+///
+/// ```
+/// let mut zip = rstzip::formats::pkzip::ZipWriter::new(Vec::new(), 6);
+/// zip.add_dir("docs", Some(1_500_000_000), 0o755).unwrap();
+/// zip.add_file("docs/readme.txt", Some(1_500_000_000), 0o644, &mut &b"read me"[..]).unwrap();
+/// let archive = zip.finish().unwrap();
+/// assert!(archive.starts_with(b"PK\x03\x04"));
+/// ```
+pub struct ZipWriter<W: Write> {
+    output: W,
+    level: Compression,
+    /// bytes written so far, i.e. the offset of the next local header
+    offset: u64,
+    directory: Vec<u8>,
+    entries: usize
+}
+
+/// The fields local headers and central directory records share
+struct Fields {
+    flags: u16,
+    method: u16,
+    date: u16,
+    time: u16,
+    name: Vec<u8>,
+    extra: Vec<u8>
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// A writer deflating at `level`, 1 (fastest) through 9 (best)
+    pub fn new (output: W, level: u32) -> Self {
+        ZipWriter { output, level: Compression::new(level.min(9)), offset: 0, directory: Vec::new(), entries: 0 }
+    }
+
+    /// Adds a regular file holding all of `input`. `name` is its path in the archive, with `/`
+    /// separating directories; `mode` holds its permission bits.
+    pub fn add_file<R: Read + ?Sized> (&mut self, name: &str, mtime: Option<i64>, mode: u32, input: &mut R)
+        -> io::Result<()>
+    {
+        let fields = Fields::new(name, mtime, DEFLATED, DESCRIPTOR)?;
+        let offset = self.offset;
+        self.write_local(&fields, 0, 0, 0)?;
+
+        let mut counted = Counted { inner: &mut self.output, count: 0 };
+        let mut encoder = CrcWriter::new(DeflateEncoder::new(&mut counted, self.level));
+        let size = io::copy(input, &mut encoder)?;
+        let crc32 = encoder.crc().sum();
+        encoder.into_inner().finish()?;
+        let compressed_size = counted.count;
+        let (compressed_size, size) = match (u32::try_from(compressed_size), u32::try_from(size)) {
+            (Ok(compressed_size), Ok(size)) => (compressed_size, size),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{} is too large for a zip archive", name)))
+        };
+
+        let mut descriptor = DESCRIPTOR_SIG.to_vec();
+        descriptor.extend_from_slice(&crc32.to_le_bytes());
+        descriptor.extend_from_slice(&compressed_size.to_le_bytes());
+        descriptor.extend_from_slice(&size.to_le_bytes());
+        self.output.write_all(&descriptor)?;
+        self.offset += u64::from(compressed_size) + descriptor.len() as u64;
+        self.add_record(&fields, crc32, compressed_size, size, S_IFREG | mode & !S_IFMT, offset)
+    }
+
+    /// Adds a directory. A `/` is appended to `name` if it doesn't end in one.
+    pub fn add_dir (&mut self, name: &str, mtime: Option<i64>, mode: u32) -> io::Result<()> {
+        let name = if name.ends_with('/') { name.to_string() } else { format!("{}/", name) };
+        let fields = Fields::new(&name, mtime, STORED, 0)?;
+        let offset = self.offset;
+        self.write_local(&fields, 0, 0, 0)?;
+        self.add_record(&fields, 0, 0, 0, S_IFDIR | mode & !S_IFMT, offset)
+    }
+
+    /// Writes the central directory, completing the archive, and returns the output
+    pub fn finish (mut self) -> io::Result<W> {
+        let (entries, size, start) = match (u16::try_from(self.entries), u32::try_from(self.directory.len()),
+            u32::try_from(self.offset))
+        {
+            (Ok(entries), Ok(size), Ok(start)) => (entries, size, start),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "too many entries for a zip archive"))
+        };
+        self.output.write_all(&self.directory)?;
+        let mut end = END_SIG.to_vec();
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&start.to_le_bytes());
+        end.extend_from_slice(&[0, 0]);
+        self.output.write_all(&end)?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    fn write_local (&mut self, fields: &Fields, crc32: u32, compressed_size: u32, size: u32) -> io::Result<()> {
+        let mut local = LOCAL_SIG.to_vec();
+        local.extend_from_slice(&VERSION.to_le_bytes());
+        fields.write_common(&mut local, crc32, compressed_size, size);
+        local.extend_from_slice(&fields.name);
+        local.extend_from_slice(&fields.extra);
+        self.output.write_all(&local)?;
+        self.offset += local.len() as u64;
+        Ok(())
+    }
+
+    fn add_record (&mut self, fields: &Fields, crc32: u32, compressed_size: u32, size: u32, mode: u32,
+        offset: u64) -> io::Result<()>
+    {
+        let offset = u32::try_from(offset).map_err(|_| Error::new(ErrorKind::InvalidInput,
+            "archive too large for the zip format"))?;
+        let dos_attributes = if mode & S_IFMT == S_IFDIR { DOS_DIR } else { 0 };
+        let record = &mut self.directory;
+        record.extend_from_slice(&CENTRAL_SIG);
+        record.extend_from_slice(&(UNIX << 8 | VERSION).to_le_bytes());
+        record.extend_from_slice(&VERSION.to_le_bytes());
+        fields.write_common(record, crc32, compressed_size, size);
+        // no comment, disk 0, no internal attributes
+        record.extend_from_slice(&[0; 6]);
+        record.extend_from_slice(&(mode << 16 | dos_attributes).to_le_bytes());
+        record.extend_from_slice(&offset.to_le_bytes());
+        record.extend_from_slice(&fields.name);
+        record.extend_from_slice(&fields.extra);
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+impl Fields {
+    fn new (name: &str, mtime: Option<i64>, method: u16, flags: u16) -> io::Result<Self> {
+        if name.len() > usize::from(u16::MAX) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}: name too long for a zip archive", name)));
+        }
+        let mtime = mtime.unwrap_or_else(|| SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64).unwrap_or(0));
+        let (date, time) = to_dos_time(mtime);
+        // MS-DOS times are local and only good to two seconds, so the exact time is kept too
+        let extra = match i32::try_from(mtime) {
+            Ok(mtime) => [&EXTENDED_TIME.to_le_bytes()[..], &5u16.to_le_bytes(), &[1], &mtime.to_le_bytes()].concat(),
+            Err(_) => Vec::new()
+        };
+        let flags = if name.is_ascii() { flags } else { flags | UTF8 };
+        Ok(Fields { flags, method, date, time, name: name.as_bytes().to_vec(), extra })
+    }
+
+    /// Writes the fields from the flags to the extra field length, which local headers and
+    /// central directory records lay out alike
+    fn write_common (&self, out: &mut Vec<u8>, crc32: u32, compressed_size: u32, size: u32) {
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&self.method.to_le_bytes());
+        out.extend_from_slice(&self.time.to_le_bytes());
+        out.extend_from_slice(&self.date.to_le_bytes());
+        out.extend_from_slice(&crc32.to_le_bytes());
+        out.extend_from_slice(&compressed_size.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(self.extra.len() as u16).to_le_bytes());
+    }
+}
+
+/// Counts the bytes written through it
+struct Counted<W> {
+    inner: W,
+    count: u64
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Extracts every entry of the archive `file` below `dest`, restoring modes and mtimes as
+/// unzip does. With `opt.stdout` the contents of the files are written to stdout one after
+/// another instead.
+///
+/// Nothing is extracted from an archive holding an absolute name or one that climbs out of
+/// `dest` with `..`, and no entry is written through a symbolic link. Existing files are only
+/// replaced with `opt.force`.
+pub(crate) fn extract (file: &mut File, dest: &Path, opt: &Options) -> io::Result<Tested> {
+    let entries = directory(file)?;
+    let mut paths = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        paths.push(safe_path(&entry.name).ok_or_else(|| Error::new(ErrorKind::InvalidData,
+            format!("{}: unsafe path in archive, nothing extracted", entry.name)))?);
+    }
+
+    let mut tested = Tested::default();
+    let mut dirs = Vec::new();
+    let stdout = io::stdout();
+    for (entry, path) in entries.iter().zip(paths) {
+        // the central directory has the CRC and sizes, so data descriptors can be ignored
+        let entry = LocalHeader { flags: entry.flags & !DESCRIPTOR, ..entry.clone() };
+        if opt.stdout {
+            if !entry.is_dir() {
+                local_header_len(file, entry.offset)?;
+                let mut out = stdout.lock();
+                tested.size += decode_entry(&entry, &mut BufReader::new(&mut *file), &mut out)?.size;
+                out.flush()?;
+            }
+        }
+        else if entry.is_dir() {
+            let path = dest.join(path);
+            check_no_links(dest, &path)?;
+            fs::create_dir_all(&path)?;
+            dirs.push((path, entry));
+        }
+        else {
+            let path = dest.join(path);
+            check_no_links(dest, &path)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if !opt.force && fs::symlink_metadata(&path).is_ok() {
+                return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists; not overwritten",
+                    path.display())));
+            }
+            local_header_len(file, entry.offset)?;
+            let mut out = BufWriter::new(File::create(&path)?);
+            let res = decode_entry(&entry, &mut BufReader::new(&mut *file), &mut out)
+                .and_then(|t| out.flush().map(|_| t));
+            match res {
+                Ok(t) => tested.size += t.size,
+                Err(e) => {
+                    drop(out);
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+            }
+            restore(&path, entry.mode, entry.mtime)?;
+        }
+        tested.members += 1;
+    }
+    // extracting into a directory changes its mtime, so directories are done last, deepest first
+    for (path, entry) in dirs.iter().rev() {
+        restore(path, entry.mode, entry.mtime)?;
+    }
+    Ok(tested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Mode;
+
+    #[test]
+    fn write_and_extract () {
+        let mut zip = ZipWriter::new(Vec::new(), 6);
+        zip.add_dir("tree", Some(1_000_000_000), 0o750).unwrap();
+        zip.add_file("tree/a.txt", Some(1_234_567_891), 0o640, &mut &[b'a'; 5000][..]).unwrap();
+        zip.add_file("tree/sub/é.txt", None, 0o600, &mut &b""[..]).unwrap();
+        let archive = zip.finish().unwrap();
+
+        let dest = std::env::temp_dir().join(format!("rstzip-archive-{}", std::process::id()));
+        fs::create_dir_all(&dest).unwrap();
+        let path = dest.join("tree.zip");
+        fs::write(&path, &archive).unwrap();
+
+        let entries = directory(&mut File::open(&path).unwrap()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["tree/", "tree/a.txt", "tree/sub/é.txt"]);
+        assert_eq!(entries[1].mtime, Some(1_234_567_891));
+        assert_eq!(entries[1].mode, Some(0o100640));
+        assert_eq!(entries[2].flags & UTF8, UTF8);
+
+        let opt = Options::new(Mode::Decompress);
+        let tested = extract(&mut File::open(&path).unwrap(), &dest, &opt).unwrap();
+        assert_eq!((tested.members, tested.size), (3, 5000));
+        assert_eq!(fs::read(dest.join("tree/a.txt")).unwrap(), vec![b'a'; 5000]);
+        assert!(dest.join("tree/sub/é.txt").exists());
+        let mtime = fs::metadata(dest.join("tree/a.txt")).unwrap().modified().unwrap();
+        assert_eq!(mtime.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(), 1_234_567_891);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(dest.join("tree")).unwrap().permissions().mode() & 0o777, 0o750);
+        }
+        // existing files are kept
+        let err = extract(&mut File::open(&path).unwrap(), &dest, &opt).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        fs::remove_dir_all(&dest).unwrap();
+    }
+}
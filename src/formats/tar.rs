@@ -0,0 +1,622 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use crate::formats::archive::{check_no_links, restore, safe_path, unix_mode};
+use crate::formats::codec::Tested;
+use crate::formats::gz::HeaderError;
+use crate::constants;
+use crate::options::Options;
+
+const BLOCK: usize = 512;
+/// the largest value of an 11 digit octal field, such as the size
+const MAX_OCTAL: u64 = 0o77777777777;
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+
+const REGULAR: u8 = b'0';
+/// the type flag of regular files in old archives
+const OLD_REGULAR: u8 = b'\0';
+const CONTIGUOUS: u8 = b'7';
+const DIRECTORY: u8 = b'5';
+/// a pax extended header, whose records apply to the next entry
+const PAX: u8 = b'x';
+/// a pax global header, whose records apply to every entry that follows
+const PAX_GLOBAL: u8 = b'g';
+/// GNU tar's header for a name too long for the next entry's header
+const GNU_LONG_NAME: u8 = b'L';
+
+/// Reads as an uncompressed tar archive of `entries`, each a file or directory and its name in
+/// the archive, so that any codec can compress the archive as it is made. Headers are ustar,
+/// with a pax extended header before any entry whose name or size ustar can't hold.
+pub(crate) struct TarStream<'a> {
+    entries: std::slice::Iter<'a, (PathBuf, String)>,
+    /// whether to record modification times
+    time: bool,
+    /// headers and padding not read yet
+    pending: Cursor<Vec<u8>>,
+    /// the file being archived, limited to the size recorded for it
+    file: Option<(&'a Path, u64, io::Take<BufReader<File>>)>,
+    finished: bool
+}
+
+impl<'a> TarStream<'a> {
+    pub(crate) fn new (entries: &'a [(PathBuf, String)], time: bool) -> Self {
+        TarStream { entries: entries.iter(), time, pending: Cursor::new(Vec::new()), file: None, finished: false }
+    }
+
+    /// Lays out the headers of the entry `path`, named `name`, opening it if it is a file
+    fn start (&mut self, path: &'a Path, name: &str) -> io::Result<()> {
+        let meta = fs::metadata(path)?;
+        let mtime = if self.time {
+            meta.modified().ok().map(|m| crate::util::Timespec::from(m).0).unwrap_or(0)
+        }
+        else {
+            0
+        };
+        let (typeflag, size) = if meta.is_dir() { (DIRECTORY, 0) } else { (REGULAR, meta.len()) };
+        let (uid, gid) = owner(&meta);
+        let entry = Entry { name: name.to_string(), size, mode: unix_mode(&meta) & 0o7777, mtime: mtime.max(0) as u64,
+            uid, gid, typeflag };
+        self.pending = Cursor::new(entry.headers());
+        if typeflag == REGULAR {
+            self.file = Some((path, size, BufReader::new(File::open(path)?).take(size)));
+        }
+        Ok(())
+    }
+}
+
+impl Read for TarStream<'_> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.pending.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            if let Some((path, size, file)) = &mut self.file {
+                let n = file.read(buf).map_err(|e| named(path, e))?;
+                if n > 0 {
+                    return Ok(n);
+                }
+                if file.limit() > 0 {
+                    return Err(named(path, Error::new(ErrorKind::UnexpectedEof, "file shrank while being archived")));
+                }
+                self.pending = Cursor::new(vec![0; padding(*size)]);
+                self.file = None;
+                continue;
+            }
+            match self.entries.next() {
+                Some((path, name)) => self.start(path, name).map_err(|e| named(path, e))?,
+                None if !self.finished => {
+                    // the end of the archive is marked by two zero blocks
+                    self.pending = Cursor::new(vec![0; 2 * BLOCK]);
+                    self.finished = true;
+                },
+                None => return Ok(0)
+            }
+        }
+    }
+}
+
+/// Names the file an error is about, as it isn't the operand the error is reported for
+fn named (path: &Path, e: Error) -> Error {
+    Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+#[cfg(unix)]
+fn owner (meta: &fs::Metadata) -> (u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.uid(), meta.gid())
+}
+
+#[cfg(not(unix))]
+fn owner (_meta: &fs::Metadata) -> (u32, u32) {
+    (0, 0)
+}
+
+/// The zero bytes that bring data of `size` bytes to a whole number of blocks
+fn padding (size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+/// The header fields of one archive entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    size: u64,
+    /// the permission bits
+    mode: u32,
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+    typeflag: u8
+}
+
+impl Entry {
+    /// The ustar header of the entry, after a pax extended header if ustar can't hold it
+    fn headers (&self) -> Vec<u8> {
+        let mut records = Vec::new();
+        let split = split_name(&self.name);
+        if split.is_none() {
+            records.extend(pax_record("path", &self.name));
+        }
+        if self.size > MAX_OCTAL {
+            records.extend(pax_record("size", &self.size.to_string()));
+        }
+        if self.mtime > MAX_OCTAL {
+            records.extend(pax_record("mtime", &self.mtime.to_string()));
+        }
+        let mut out = Vec::new();
+        if !records.is_empty() {
+            let base = self.name.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+            let pax = Entry { name: truncate(&format!("PaxHeaders/{}", base), NAME_LEN).to_string(),
+                size: records.len() as u64, mode: 0o644, typeflag: PAX, ..self.clone() };
+            out.extend(pax.ustar(None));
+            let padding = padding(records.len() as u64);
+            out.extend(records);
+            out.extend(vec![0; padding]);
+        }
+        out.extend(self.ustar(split));
+        out
+    }
+
+    /// The ustar header block, with the name split into `prefix` and name if given, else cut
+    /// down to what fits
+    fn ustar (&self, split: Option<(&str, &str)>) -> Vec<u8> {
+        let mut block = vec![0u8; BLOCK];
+        let (prefix, name) = split.unwrap_or(("", truncate(&self.name, NAME_LEN)));
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut block[100..108], u64::from(self.mode));
+        octal(&mut block[108..116], u64::from(self.uid).min(0o7777777));
+        octal(&mut block[116..124], u64::from(self.gid).min(0o7777777));
+        octal(&mut block[124..136], self.size.min(MAX_OCTAL));
+        octal(&mut block[136..148], self.mtime.min(MAX_OCTAL));
+        block[156] = self.typeflag;
+        block[257..265].copy_from_slice(b"ustar\x0000");
+        block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        // the checksum is summed with its own field taken as spaces
+        block[148..156].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+        block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        block
+    }
+}
+
+/// Writes `value` into `field` in octal, zero padded and NUL terminated
+fn octal (field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// `name` split at a `/` into a ustar prefix and name, or kept whole if it fits, or `None` if
+/// it can't be fitted
+fn split_name (name: &str) -> Option<(&str, &str)> {
+    if name.len() <= NAME_LEN {
+        return Some(("", name));
+    }
+    // the slash at the end of a directory name can't be split at
+    name[..name.len() - 1].match_indices('/').map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= PREFIX_LEN && rest.len() <= NAME_LEN)
+}
+
+/// The longest start of `s` that fits in `len` bytes
+fn truncate (s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// A pax record, "LEN KEY=VALUE\n", where LEN counts the whole record including itself
+fn pax_record (key: &str, value: &str) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, value).into_bytes()
+}
+
+/// What to do with the entries of an archive as they are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// only check that every entry is safe to extract
+    Check,
+    Extract,
+    /// write the contents of the files to stdout, one after the other
+    Stdout
+}
+
+/// Where the data of the current entry goes
+enum Sink {
+    Discard,
+    File(PathBuf, BufWriter<File>),
+    Stdout,
+    /// the data of a pax or GNU header, kept for the entry it describes
+    Keep(Vec<u8>)
+}
+
+/// Reads a tar archive written into it, acting on each entry as its data arrives, so that a
+/// codec can decompress straight into it
+struct Extractor<'a> {
+    dest: &'a Path,
+    action: Action,
+    opt: &'a Options,
+    /// the header being read, or a zero block
+    header: Vec<u8>,
+    /// data bytes of the current entry left, and the padding after them
+    left: u64,
+    padding: usize,
+    sink: Sink,
+    /// the type flag, mode and mtime of the current entry
+    current: Option<(u8, Option<u32>, Option<i64>)>,
+    /// what a pax or GNU header said of the next entry
+    next_name: Option<String>,
+    next_size: Option<u64>,
+    next_mtime: Option<i64>,
+    /// directories extracted, whose metadata is restored once everything is in them
+    dirs: Vec<(PathBuf, Option<u32>, Option<i64>)>,
+    ended: bool,
+    tested: Tested
+}
+
+impl<'a> Extractor<'a> {
+    fn new (dest: &'a Path, action: Action, opt: &'a Options) -> Self {
+        Extractor { dest, action, opt, header: Vec::with_capacity(BLOCK), left: 0, padding: 0,
+            sink: Sink::Discard, current: None, next_name: None, next_size: None, next_mtime: None,
+            dirs: Vec::new(), ended: false, tested: Tested::default() }
+    }
+
+    /// Acts on the header block just read
+    fn header (&mut self) -> io::Result<()> {
+        let block = std::mem::take(&mut self.header);
+        if block.iter().all(|&b| b == 0) {
+            self.ended = true;
+            return Ok(());
+        }
+        let stored = parse_octal(&block[148..156]).ok_or_else(|| corrupt("bad header checksum"))?;
+        let mut spaced = block.clone();
+        spaced[148..156].copy_from_slice(b"        ");
+        if stored != spaced.iter().map(|&b| u64::from(b)).sum::<u64>() {
+            return Err(corrupt("bad header checksum"));
+        }
+        let typeflag = block[156];
+        let size = match self.next_size.take() {
+            Some(size) => size,
+            None => parse_number(&block[124..136]).ok_or_else(|| corrupt("bad entry size"))?
+        };
+        let name = match self.next_name.take() {
+            Some(name) => name,
+            None => {
+                let name = cstr(&block[..NAME_LEN]);
+                let prefix = if &block[257..262] == b"ustar" { cstr(&block[345..345 + PREFIX_LEN]) } else { String::new() };
+                if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+            }
+        };
+        let mode = parse_octal(&block[100..108]).map(|mode| mode as u32);
+        let mtime = self.next_mtime.take().or_else(|| parse_number(&block[136..148]).map(|t| t as i64));
+        self.left = size;
+        self.padding = padding(size);
+        self.current = Some((typeflag, mode, mtime));
+        self.sink = match typeflag {
+            PAX | GNU_LONG_NAME => Sink::Keep(Vec::new()),
+            PAX_GLOBAL => Sink::Discard,
+            REGULAR | OLD_REGULAR | CONTIGUOUS | DIRECTORY => self.entry(&name, typeflag == DIRECTORY || name.ends_with('/'),
+                mode, mtime)?,
+            _ => {
+                // links and special files could point extraction anywhere; they are left out
+                if self.action == Action::Extract && !self.opt.quiet {
+                    self.opt.emit(format_args!("{}: {}: not a regular file or directory -- skipped",
+                        constants::PROGRAM_NAME, name));
+                }
+                Sink::Discard
+            }
+        };
+        self.data_done()
+    }
+
+    /// Checks, and unless only checking, creates the file or directory `name`
+    fn entry (&mut self, name: &str, dir: bool, mode: Option<u32>, mtime: Option<i64>) -> io::Result<Sink> {
+        // archives made of a directory's contents start with "./", which is where they go
+        if dir && Path::new(name).components().all(|c| c == Component::CurDir) {
+            return Ok(Sink::Discard);
+        }
+        let path = safe_path(name).ok_or_else(|| Error::new(ErrorKind::InvalidData,
+            format!("{}: unsafe path in archive, nothing extracted", name)))?;
+        self.tested.members += 1;
+        match self.action {
+            Action::Check => Ok(Sink::Discard),
+            Action::Stdout => Ok(if dir { Sink::Discard } else { Sink::Stdout }),
+            Action::Extract => {
+                let path = self.dest.join(path);
+                check_no_links(self.dest, &path)?;
+                if dir {
+                    fs::create_dir_all(&path)?;
+                    self.dirs.push((path, mode, mtime));
+                    return Ok(Sink::Discard);
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if !self.opt.force && fs::symlink_metadata(&path).is_ok() {
+                    return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists; not overwritten",
+                        path.display())));
+                }
+                let out = BufWriter::new(File::create(&path)?);
+                Ok(Sink::File(path, out))
+            }
+        }
+    }
+
+    /// Finishes the current entry once all its data has been read
+    fn data_done (&mut self) -> io::Result<()> {
+        if self.left > 0 {
+            return Ok(());
+        }
+        let (typeflag, mode, mtime) = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(())
+        };
+        match std::mem::replace(&mut self.sink, Sink::Discard) {
+            Sink::File(path, mut out) => {
+                out.flush()?;
+                drop(out);
+                restore(&path, mode, mtime)?;
+            },
+            Sink::Keep(data) if typeflag == GNU_LONG_NAME => self.next_name = Some(cstr(&data)),
+            Sink::Keep(data) => self.pax(&data)?,
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// Takes what the records of a pax extended header say of the next entry
+    fn pax (&mut self, mut records: &[u8]) -> io::Result<()> {
+        while !records.is_empty() {
+            let space = records.iter().position(|&b| b == b' ').ok_or_else(|| corrupt("bad pax header"))?;
+            let len: usize = std::str::from_utf8(&records[..space]).ok().and_then(|len| len.parse().ok())
+                .filter(|&len| len > space + 1 && len <= records.len() && records[len - 1] == b'\n')
+                .ok_or_else(|| corrupt("bad pax header"))?;
+            let record = String::from_utf8_lossy(&records[space + 1..len - 1]).into_owned();
+            records = &records[len..];
+            let (key, value) = record.split_at(record.find('=').ok_or_else(|| corrupt("bad pax header"))?);
+            let value = &value[1..];
+            match key {
+                "path" => self.next_name = Some(value.to_string()),
+                "size" => self.next_size = Some(value.parse().map_err(|_| corrupt("bad pax header"))?),
+                // times may have a fraction of a second
+                "mtime" => self.next_mtime = value.split('.').next().and_then(|t| t.parse().ok()),
+                _ => ()
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the archive didn't stop in the middle of an entry, and restores the
+    /// metadata of the directories extracted, deepest first
+    fn finish (mut self) -> io::Result<Tested> {
+        if self.left > 0 || self.padding > 0 || !self.header.is_empty() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "unexpected end of tar archive"));
+        }
+        for (path, mode, mtime) in std::mem::take(&mut self.dirs).iter().rev() {
+            restore(path, *mode, *mtime)?;
+        }
+        Ok(self.tested)
+    }
+}
+
+impl Write for Extractor<'_> {
+    fn write (&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+        while !buf.is_empty() {
+            if self.ended {
+                // whatever follows the end of the archive is ignored, as tar does
+                break;
+            }
+            if self.left > 0 {
+                let n = buf.len().min(usize::try_from(self.left).unwrap_or(usize::MAX));
+                let data = &buf[..n];
+                // pax and long name records are metadata, not member data
+                match &mut self.sink {
+                    Sink::File(_, out) => out.write_all(data)?,
+                    Sink::Stdout => io::stdout().write_all(data)?,
+                    Sink::Keep(kept) => kept.extend_from_slice(data),
+                    Sink::Discard => ()
+                }
+                if !matches!(self.sink, Sink::Keep(_)) {
+                    self.tested.size += n as u64;
+                }
+                self.left -= n as u64;
+                buf = &buf[n..];
+                self.data_done()?;
+            }
+            else if self.padding > 0 {
+                let n = buf.len().min(self.padding);
+                self.padding -= n;
+                buf = &buf[n..];
+            }
+            else {
+                let n = buf.len().min(BLOCK - self.header.len());
+                self.header.extend_from_slice(&buf[..n]);
+                buf = &buf[n..];
+                if self.header.len() == BLOCK {
+                    self.header()?;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::File(_, out) => out.flush(),
+            Sink::Stdout => io::stdout().flush(),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Drop for Extractor<'_> {
+    /// Removes a file left half written by an archive that turned out to be broken
+    fn drop (&mut self) {
+        if let Sink::File(path, _) = std::mem::replace(&mut self.sink, Sink::Discard) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The text of a NUL padded field
+fn cstr (field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// An octal field, padded with spaces or NULs
+fn parse_octal (field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// A numeric field, either octal or, as GNU tar writes numbers too large for it, base 256
+/// with the high bit of the first byte set
+fn parse_number (field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 == 0 {
+        return parse_octal(field);
+    }
+    field[1..].iter().try_fold(u64::from(field[0] & 0x7f), |n, &b| n.checked_mul(256).map(|n| n | u64::from(b)))
+}
+
+fn corrupt (reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid tar archive -- {}", reason))
+}
+
+/// Extracts the compressed tar archive `file` into `dest`, in any format the codecs of `opt`
+/// recognize. Every entry is checked before anything is written, so an archive with an absolute
+/// or `..` name extracts nothing. With `opt.stdout` the contents of the files are written to
+/// stdout instead.
+pub(crate) fn extract (file: &mut File, dest: &Path, opt: &Options) -> io::Result<Tested> {
+    let passes: &[Action] = if opt.stdout { &[Action::Check, Action::Stdout] } else { &[Action::Check, Action::Extract] };
+    let mut tested = Tested::default();
+    for &action in passes {
+        file.seek(SeekFrom::Start(0))?;
+        let (codec, mut input) = opt.sniff(BufReader::new(&mut *file))?;
+        let codec = codec.ok_or_else(|| Error::from(HeaderError::NotGzip))?;
+        let header = codec.read_header(&mut input)?;
+        let mut extractor = Extractor::new(dest, action, opt);
+        let decoded = codec.decode(&header, &mut input, &mut extractor)?;
+        tested = Tested { trailing_garbage: decoded.trailing_garbage, ..extractor.finish()? };
+    }
+    if opt.stdout {
+        io::stdout().flush()?;
+    }
+    Ok(tested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Mode;
+
+    /// A tar archive of the entries, each a name and the file's contents, or `None` for a
+    /// directory
+    fn archive (entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (name, data) in entries {
+            let (typeflag, data) = match data {
+                Some(data) => (REGULAR, *data),
+                None => (DIRECTORY, &b""[..])
+            };
+            let entry = Entry { name: name.to_string(), size: data.len() as u64, mode: 0o640,
+                mtime: 1_234_567_891, uid: 0, gid: 0, typeflag };
+            out.extend(entry.headers());
+            out.extend_from_slice(data);
+            out.extend(vec![0; padding(data.len() as u64)]);
+        }
+        out.extend(vec![0; 2 * BLOCK]);
+        out
+    }
+
+    fn unpack (archive: &[u8], dest: &Path, action: Action) -> io::Result<Tested> {
+        let opt = Options::new(Mode::Decompress);
+        let mut extractor = Extractor::new(dest, action, &opt);
+        // small writes, as decompressed data arrives in pieces
+        for chunk in archive.chunks(100) {
+            extractor.write_all(chunk)?;
+        }
+        extractor.finish()
+    }
+
+    #[test]
+    fn headers () {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        assert_eq!(split_name(&long), Some((&long[..120], &long[121..])));
+        assert_eq!(split_name(&"n".repeat(300)), None);
+        assert_eq!(pax_record("path", "a"), b"9 path=a\n");
+        // the length counts its own digits
+        assert_eq!(pax_record("path", &"a".repeat(91)).len(), 101);
+
+        // a name ustar can't hold goes into a pax header, which is read back
+        let name = format!("{}/../../up", "n".repeat(300));
+        let tar = archive(&[(&name, Some(b"long"))]);
+        assert_eq!(tar[156], PAX);
+        let err = unpack(&tar, Path::new("nowhere"), Action::Check).unwrap_err();
+        assert_eq!(err.to_string(), format!("{}: unsafe path in archive, nothing extracted", name));
+    }
+
+    #[test]
+    fn write_and_extract () {
+        let src = std::env::temp_dir().join(format!("rstzip-tar-src-{}", std::process::id()));
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), vec![b'a'; 5000]).unwrap();
+        fs::write(src.join("sub/é.txt"), b"").unwrap();
+        let entries = vec![(src.clone(), String::from("tree/")), (src.join("a.txt"), String::from("tree/a.txt")),
+            (src.join("sub"), String::from("tree/sub/")), (src.join("sub/é.txt"), String::from("tree/sub/é.txt"))];
+        let mut tar = Vec::new();
+        TarStream::new(&entries, true).read_to_end(&mut tar).unwrap();
+        assert_eq!(tar.len() % BLOCK, 0);
+
+        let dest = std::env::temp_dir().join(format!("rstzip-tar-dest-{}", std::process::id()));
+        fs::create_dir_all(&dest).unwrap();
+        let tested = unpack(&tar, &dest, Action::Extract).unwrap();
+        assert_eq!((tested.members, tested.size), (4, 5000));
+        assert_eq!(fs::read(dest.join("tree/a.txt")).unwrap(), vec![b'a'; 5000]);
+        assert!(dest.join("tree/sub/é.txt").exists());
+        let modified = |path: &Path| crate::util::Timespec::from(fs::metadata(path).unwrap().modified().unwrap()).0;
+        assert_eq!(modified(&dest.join("tree/a.txt")), modified(&src.join("a.txt")));
+        // existing files are kept
+        assert_eq!(unpack(&tar, &dest, Action::Extract).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        fs::remove_dir_all(&src).unwrap();
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn unsafe_and_broken () {
+        let dest = Path::new("nowhere");
+        for name in &["/etc/passwd", "../up", "tree/../../up"] {
+            let err = unpack(&archive(&[("tree/", None), (name, Some(b"x"))]), dest, Action::Check).unwrap_err();
+            assert_eq!(err.to_string(), format!("{}: unsafe path in archive, nothing extracted", name));
+        }
+        let tar = archive(&[("./", None), ("./a", Some(b"x"))]);
+        assert_eq!(unpack(&tar, dest, Action::Check).unwrap().members, 1);
+        assert_eq!(unpack(&tar[..BLOCK + 10], dest, Action::Check).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        let mut bad = tar.clone();
+        bad[0] = b'x';
+        assert_eq!(unpack(&bad, dest, Action::Check).unwrap_err().to_string(),
+            "invalid tar archive -- bad header checksum");
+        // pax records with no body, or not ending in a newline
+        for records in &[&b"2 "[..], b"9 path=ab", b"3 x\n"] {
+            let pax = Entry { name: String::from("PaxHeaders/a"), size: records.len() as u64, mode: 0o644,
+                mtime: 0, uid: 0, gid: 0, typeflag: PAX };
+            let mut tar = pax.ustar(None);
+            tar.extend_from_slice(records);
+            tar.extend(vec![0; padding(records.len() as u64)]);
+            tar.extend(archive(&[("a", Some(b"x"))]));
+            assert_eq!(unpack(&tar, dest, Action::Check).unwrap_err().to_string(),
+                "invalid tar archive -- bad pax header");
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::io::{self, BufRead, BufWriter, Error, ErrorKind, Read, Write};
+use flate2::Compression;
+use flate2::bufread::DeflateDecoder;
+use flate2::write::{DeflateEncoder, ZlibEncoder};
+use crate::formats::codec::{Codec, Header, Tested};
+use crate::formats::gz::only_zeros;
+use crate::options::Options;
+use crate::util::WorkData;
+
+const HEADER_LEN: usize = 2;
+const DEFLATED: u8 = 8;
+/// the largest window, as log2 of its size minus 8
+const MAX_CINFO: u8 = 7;
+/// flag byte: a preset dictionary must be given to decompress
+const FDICT: u8 = 0x20;
+
+/// The zlib format (RFC 1950): a two byte header, deflated data and the Adler-32 of the data.
+/// It records neither names nor times.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Zlib;
+
+impl Codec for Zlib {
+    fn name (&self) -> &'static str {
+        "zlib"
+    }
+
+    /// Deflate with a valid window size and header check, which only 1 in 31 byte pairs with
+    /// the right first byte pass. Streams needing a preset dictionary are left out: the flag
+    /// is set in most text, and they couldn't be decompressed anyway.
+    fn detect (&self, magic: &[u8]) -> bool {
+        match magic {
+            [cmf, flg, ..] => valid_header(*cmf, *flg) && flg & FDICT == 0,
+            _ => false
+        }
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let mut raw = vec![0u8; HEADER_LEN];
+        input.read_exact(&mut raw)?;
+        if !valid_header(raw[0], raw[1]) {
+            return Err(Error::new(ErrorKind::InvalidData, "not in zlib format"));
+        }
+        if raw[1] & FDICT != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "preset dictionaries are not supported"));
+        }
+        Ok(Header { raw, ..Header::default() })
+    }
+
+    fn decode (&self, _header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        let mut out = AdlerWriter { inner: BufWriter::new(output), adler: Adler32::new(), size: 0 };
+        inflate(input, &mut out)?;
+        out.flush()?;
+        let mut trailer = [0u8; 4];
+        input.read_exact(&mut trailer)?;
+        if u32::from_be_bytes(trailer) != out.adler.sum() {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid compressed data--adler32 error"));
+        }
+        Ok(Tested { members: 1, size: out.size, trailing_garbage: !only_zeros(input)?, ..Tested::default() })
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        self.decode(header, input, &mut io::sink())
+    }
+
+    fn encode (&self, input: &mut dyn Read, output: &mut dyn Write, _wdata: Option<WorkData>,
+        opt: &Options) -> io::Result<()>
+    {
+        let mut encoder = ZlibEncoder::new(output, Compression::new(opt.level.min(9)));
+        io::copy(input, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+/// Whether `cmf` and `flg` name deflate with a valid window size and pass the header check
+fn valid_header (cmf: u8, flg: u8) -> bool {
+    cmf & 0x0f == DEFLATED && cmf >> 4 <= MAX_CINFO && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0
+}
+
+/// Bare deflate data (RFC 1951), with no header or check value. Having no magic number, it
+/// is never detected: it is only read when asked for by name.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Raw;
+
+impl Codec for Raw {
+    fn name (&self) -> &'static str {
+        "raw"
+    }
+
+    fn detect (&self, _magic: &[u8]) -> bool {
+        false
+    }
+
+    fn read_header (&self, _input: &mut dyn BufRead) -> io::Result<Header> {
+        Ok(Header::default())
+    }
+
+    fn decode (&self, _header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        let mut out = BufWriter::new(output);
+        let size = inflate(input, &mut out)?;
+        out.flush()?;
+        Ok(Tested { members: 1, size, trailing_garbage: !only_zeros(input)?, ..Tested::default() })
+    }
+
+    fn test (&self, header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        self.decode(header, input, &mut io::sink())
+    }
+
+    fn encode (&self, input: &mut dyn Read, output: &mut dyn Write, _wdata: Option<WorkData>,
+        opt: &Options) -> io::Result<()>
+    {
+        let mut encoder = DeflateEncoder::new(output, Compression::new(opt.level.min(9)));
+        io::copy(input, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+/// Inflates one deflate stream from `input` into `output`, leaving `input` just past its end.
+/// Returns the decompressed size.
+fn inflate (input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<u64> {
+    let mut body = DeflateDecoder::new(input);
+    io::copy(&mut body, output).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => e,
+        _ => Error::new(ErrorKind::InvalidData, "invalid compressed data--format violated")
+    })
+}
+
+/// The Adler-32 checksum zlib streams end with
+#[derive(Debug, Clone, Copy)]
+struct Adler32 {
+    a: u32,
+    b: u32
+}
+
+impl Adler32 {
+    const MOD: u32 = 65521;
+    /// how many bytes can be summed before `b` could overflow
+    const NMAX: usize = 5552;
+
+    fn new () -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    fn update (&mut self, data: &[u8]) {
+        for chunk in data.chunks(Self::NMAX) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= Self::MOD;
+            self.b %= Self::MOD;
+        }
+    }
+
+    fn sum (&self) -> u32 {
+        self.b << 16 | self.a
+    }
+}
+
+/// Passes data through to `inner`, summing it
+struct AdlerWriter<W: Write> {
+    inner: W,
+    adler: Adler32,
+    size: u64
+}
+
+impl<W: Write> Write for AdlerWriter<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.adler.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Mode;
+
+    fn encode (codec: &dyn Codec, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        codec.encode(&mut &data[..], &mut out, None, &Options::new(Mode::Compress)).unwrap();
+        out
+    }
+
+    fn decode (codec: &dyn Codec, compressed: &[u8]) -> io::Result<(Vec<u8>, Tested)> {
+        let mut input = compressed;
+        let header = codec.read_header(&mut input)?;
+        let mut out = Vec::new();
+        let tested = codec.decode(&header, &mut input, &mut out)?;
+        Ok((out, tested))
+    }
+
+    #[test]
+    fn adler32 () {
+        let mut adler = Adler32::new();
+        adler.update(b"Wikipedia");
+        assert_eq!(adler.sum(), 0x11e6_0398);
+        let mut adler = Adler32::new();
+        adler.update(&[0xff; 100_000]);
+        assert_eq!(adler.sum(), 0x149a_302c);
+    }
+
+    #[test]
+    fn round_trip () {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8 ^ (i / 4000) as u8).collect();
+        for codec in &[&Zlib as &dyn Codec, &Raw] {
+            let compressed = encode(*codec, &data);
+            assert_eq!(decode(*codec, &compressed).unwrap(), (data.clone(),
+                Tested { members: 1, size: data.len() as u64, ..Tested::default() }));
+        }
+        assert!(Zlib.detect(&encode(&Zlib, &data)));
+        assert!(!Zlib.detect(&[0x1f, 0x8b, 8, 0]));
+        assert!(!Zlib.detect(b"text"));
+        // text that passes the header check, but sets FDICT or a window too large
+        assert!(!Zlib.detect(b"80 percent"));
+        assert!(!Zlib.detect(&[0x88, 0x1c]));
+        assert_eq!(decode(&Zlib, b"80 percent").unwrap_err().to_string(), "preset dictionaries are not supported");
+    }
+
+    #[test]
+    fn bad_check () {
+        let mut compressed = encode(&Zlib, b"hello zlib");
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert_eq!(decode(&Zlib, &compressed).unwrap_err().to_string(), "invalid compressed data--adler32 error");
+        compressed[last] ^= 1;
+        compressed.extend_from_slice(b"junk");
+        assert!(decode(&Zlib, &compressed).unwrap().1.trailing_garbage);
+    }
+}
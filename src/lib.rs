@@ -0,0 +1,98 @@
+//! GNU gzip ported to Rust.
+//!
+//! This crate is the engine behind the `rstzip` command line tool, which is a thin client of
+//! the [`cli`] module. Data always moves from a reader to a writer through bounded buffers, so
+//! memory use does not depend on the size of the input.
+//!
+//! ```
+//! let compressed = rstzip::compress(&b"hello, world"[..], Vec::new(), 6).unwrap();
+//!
+//! let mut input = &compressed[..];
+//! let header = rstzip::GzFile::read_header(&mut input).unwrap();
+//! assert_eq!(header.name(), None);
+//!
+//! let mut output = Vec::new();
+//! rstzip::decompress(&compressed[..], &mut output).unwrap();
+//! assert_eq!(output, b"hello, world");
+//! ```
+
+extern crate libc;
+extern crate structopt;
+
+pub mod cli;
+pub mod formats;
+mod constants;
+mod error;
+mod treat;
+mod util;
+mod zip;
+mod list;
+mod options;
+mod parallel;
+mod rsyncable;
+
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use flate2::Compression;
+
+pub use error::{Error, Warning};
+pub use formats::codec::{Codec, Header, Registry, Tested};
+pub use formats::gz::{GzFile, GzIndex, IndexedGz, Member, Members, Subfield};
+pub use formats::list::ListEntry;
+pub use options::{Mode, Options, Sink};
+pub use util::{Timespec, WorkData};
+
+/// Compresses all of `input` into `output` as a single gzip member, without storing a name or
+/// timestamp. `level` ranges from 1 (fastest) to 9 (best); higher values are treated as 9.
+/// Returns `output` once the trailer has been written.
+pub fn compress<R: Read, W: Write> (input: R, output: W, level: u32) -> std::io::Result<W> {
+    GzFile::encode(input, output, None, None, Compression::new(level.min(9)))
+}
+
+/// Decompresses a gzip stream from `input` into `output`. Concatenated members are decompressed
+/// one after another, as gunzip does. Returns the number of bytes written.
+pub fn decompress<R: Read, W: Write> (input: R, output: &mut W) -> std::io::Result<u64> {
+    let mut input = BufReader::new(input);
+    let gz = GzFile::read_header(&mut input)?;
+    gz.decompress(input, output)
+}
+
+/// Describes the compressed file at `path` the way `rstzip --list` does. Files in a format
+/// that isn't recognized are still described, with only their compressed size known. Only the
+/// first entry of an archive is described; `list_entries` describes them all.
+pub fn list<P: AsRef<Path>> (path: P) -> std::io::Result<ListEntry> {
+    list_entries(path)?.into_iter().next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "empty archive"))
+}
+
+/// Describes every entry of the compressed file at `path`: one for a gzip file, one per file
+/// for an archive
+pub fn list_entries<P: AsRef<Path>> (path: P) -> std::io::Result<Vec<ListEntry>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let mut entries = formats::parse_list(&mut file, &Options::default())?;
+    for entry in &mut entries {
+        entry.path = Some(path.to_path_buf());
+    }
+    Ok(entries)
+}
+
+/// Compresses, decompresses, tests or lists `files` according to `options.mode`, as the command
+/// line tool does. A path of `-` stands for standard input, which is also used when `files` is
+/// empty. Each problem is reported through the options' sink as it is met; the worst one is
+/// returned, so that `Error::exit_code` gives gzip's exit status.
+pub fn run (files: Vec<PathBuf>, options: &Options) -> Result<(), Error> {
+    let files = if files.is_empty() {
+        vec![PathBuf::from("-")]
+    }
+    else {
+        files
+    };
+    if options.mode == Mode::List {
+        list::do_list(files, options)
+    }
+    else {
+        treat::files(files, options)
+    }
+}
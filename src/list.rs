@@ -1,13 +1,23 @@
-use crate::util::WrappedFile;
-use crate::{Opt, constants};
-use std::path::PathBuf;
+use crate::constants;
+use crate::error::{Error, Warning};
+use crate::options::Options;
+use crate::treat::Batch;
+use std::path::{Path, PathBuf};
+use std::io::{self, ErrorKind};
+use chrono::{DateTime, Datelike, Timelike};
+use chrono::offset::{Local, TimeZone};
 use crate::util;
 use crate::formats::parse_list;
-use crate::formats::gz::GzFile;
-use crate::formats::list::List;
+use crate::formats::gz::Member;
+use crate::formats::list::{calculate_ratio, ListEntry};
 
-pub fn do_list (files: Vec<PathBuf>, opt: &Opt) -> Result<(), i8> {
-    if files.len() > 0 {
+const HEADER_SIZE: f64 = 18.0;
+
+/// The header size and the compressed and uncompressed sizes of one listed entry
+type Listed = (f64, (f64, f64));
+
+pub fn do_list (files: Vec<PathBuf>, opt: &Options) -> Result<(), Error> {
+    if !files.is_empty() {
         if opt.verbose > 0 && !opt.quiet {
             print!("{:<8}{:<12}{:<8}{:<8}", "method", "crc", "date", "time");
         }
@@ -15,34 +25,180 @@ pub fn do_list (files: Vec<PathBuf>, opt: &Opt) -> Result<(), i8> {
             println!("{:<8}\t{:<8}\t{:>8}\t{:<8}", "compressed", "uncompressed", "ratio", "uncompressed_name");
         }
     }
+    let mut batch = Batch::new(opt);
     let mut total_compressed_bytes = 0.0;
     let mut total_uncompressed_bytes = 0.0;
-    // I know that reading the file is potentially slow
-    // TODO: Look for a faster solution. The use of libc::lseek is hindered by abstracting away ifd to
-    // this point, so reintroducing it here would be an incongruity.
-    let num_files = files.len();
-    let mut header_size: f64 = 18.0;
-    let mut i = 1.0;
+    let mut num_entries = 0;
+    let mut total_header_size = 0.0;
     for filepath in files {
-        let file = match util::file_open(&filepath) {
-            Ok(f) => f,
-            Err(_) => return Err(constants::ERROR)
+        let listed = match list_file(&filepath, opt, &mut batch) {
+            Ok(listed) => listed,
+            Err(err) => {
+                if batch.failed(err) {
+                    continue;
+                }
+                break;
+            }
         };
-        let wfile = WrappedFile { path: filepath.as_path(), file: &file};
-        let compr_file: Box<dyn List> = parse_list(wfile);
-        header_size = (header_size + compr_file.header_size())/i;
-        i += 1.0;
-        let (compressed_bytes, uncompressed_bytes) = compr_file.list(opt);
-        total_compressed_bytes += compressed_bytes;
-        total_uncompressed_bytes += uncompressed_bytes;
-    }
-    if num_files > 1 {
-        let total_ratio = GzFile::calculate_ratio(total_compressed_bytes, Some(total_uncompressed_bytes), header_size);
+        for (entry_header_size, (compressed_bytes, uncompressed_bytes)) in listed {
+            total_header_size += entry_header_size;
+            num_entries += 1;
+            total_compressed_bytes += compressed_bytes;
+            total_uncompressed_bytes += uncompressed_bytes;
+        }
+    }
+    if num_entries > 1 {
+        let total_ratio = calculate_ratio(total_compressed_bytes, total_uncompressed_bytes, total_header_size);
         if opt.verbose > 0 {
             print!("{:>36}", " ");
         }
         println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t",
             total_compressed_bytes, total_uncompressed_bytes, total_ratio, "(totals)");
     }
-    Ok(())
+    batch.finish()
+}
+
+/// Lists one file, one line per entry, returning the header size and the compressed and
+/// uncompressed sizes of each entry. Trailing garbage found is reported to `batch` as a warning.
+fn list_file (filepath: &Path, opt: &Options, batch: &mut Batch) -> Result<Vec<Listed>, Error> {
+    let mut file = util::file_open(filepath)?;
+    let entries = parse_list(&mut file, opt).map_err(|e| Error::file(filepath, e))?;
+    let archive = entries.len() > 1;
+    let mut listed = Vec::with_capacity(entries.len());
+    for mut entry in entries {
+        entry.path = Some(filepath.to_path_buf());
+        // the entries of an archive can only be told apart by their stored names
+        let name = if archive {
+            entry.stored_name.clone().unwrap_or_else(|| String::from("????"))
+        }
+        else {
+            get_filename_str(&entry.stored_name, &entry.path, opt)
+        };
+        let sizes = print_entry(&entry, &name, opt).map_err(|e| Error::file(filepath, e))?;
+        listed.push((entry.header_size as f64, sizes));
+        if entry.trailing_garbage {
+            batch.failed(Error::warning(filepath, Warning::TrailingGarbage));
+        }
+    }
+    Ok(listed)
+}
+
+/// Prints one line of the listing, for an entry whose uncompressed name is `name`, returning the
+/// compressed and uncompressed sizes to add to the totals
+fn print_entry (entry: &ListEntry, name: &str, opt: &Options) -> std::io::Result<(f64, f64)> {
+    let uncompressed_size = match entry.uncompressed_size {
+        Some(size) => size,
+        None => {
+            // a format that doesn't record the size, or one we don't understand
+            if opt.verbose > 0 {
+                print!("{:<8}{:<12}{:<8}{:<8}", entry.method, "????????", "????", "??:??");
+            }
+            println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t", entry.compressed_size, "??", 0.0, name);
+            return Ok((entry.compressed_size as f64, entry.compressed_size as f64));
+        }
+    };
+    if opt.verbose > 0 {
+        let crc = entry.crc32.map(|crc| format!("{:x}", crc)).unwrap_or_else(|| String::from("????????"));
+        let (date, time) = when(entry.modified_on());
+        print!("{:<8}{:<12}{:<8}{:<8}", entry.method, crc, date, time)
+    }
+    let header_size = entry.header_size as f64;
+    let compressed_size: f64 = bytes_bound_check(entry.compressed_size as f64, opt, header_size)?;
+    // small files and the directories of archives legitimately hold less than a header's worth
+    let uncompressed = uncompressed_size as f64;
+    println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t", compressed_size, uncompressed_size,
+        calculate_ratio(compressed_size, uncompressed, header_size), name);
+    if opt.verbose > 0 && entry.members.len() > 1 {
+        for member in &entry.members {
+            print_member(member);
+        }
+    }
+    if opt.verbose > 0 {
+        for subfield in &entry.subfields {
+            println!("{:>36}extra {}", " ", subfield);
+        }
+        if let Some(comment) = &entry.comment {
+            println!("{:>36}comment: {}", " ", comment);
+        }
+    }
+    Ok((compressed_size, uncompressed))
+}
+
+/// Prints the line `--list --verbose` shows for one member of a gzip file holding several,
+/// below the line for the whole file. The method column gives its offset in the file.
+fn print_member (member: &Member) {
+    let (date, time) = when(Local.timestamp_opt(i64::from(member.header.mtime()), 0).single());
+    print!("{:<8}{:<12x}{:<8}{:<8}", format!("  @{}", member.offset), member.crc32, date, time);
+    println!("{:<8}\t{:<8}\t{:>8.1}%\t{:<8}\t", member.compressed_size, member.size,
+        calculate_ratio(member.compressed_size as f64, member.size as f64, member.header_size() as f64),
+        member.header.name().unwrap_or("????"));
+}
+
+/// The date and time columns for a modification time, if one is known
+fn when (dt: Option<DateTime<Local>>) -> (String, String) {
+    match dt {
+        Some(dt) => (datestring(&dt), timestring(&dt)),
+        None => (String::from("????"), String::from("??:??"))
+    }
+}
+
+fn month(m: u32) -> &'static str {
+    match m {
+        1 => "Jan",
+        2 => "Feb",
+        3 => "Mar",
+        4 => "Apr",
+        5 => "May",
+        6 => "Jun",
+        7 => "Jul",
+        8 => "Aug",
+        9 => "Sep",
+        10 => "Oct",
+        11 => "Nov",
+        12 => "Dec",
+        _ => "???"
+    }
+}
+
+// check that the compressed file holds at least a header. If it doesn't and we aren't forcing,
+// return an error after emitting error msg
+fn bytes_bound_check (num_bytes: f64, opt: &Options, header_size: f64) -> std::io::Result<f64> {
+    if num_bytes < HEADER_SIZE {
+        if opt.verbose > 1 {
+            opt.emit(format_args!("{}: internal error: do_list: the number of bytes in a file\
+                cannot be less than the header size", constants::PROGRAM_NAME));
+        }
+        if opt.force {
+            return Ok(header_size)
+        }
+        return Err(io::Error::new(ErrorKind::InvalidData,
+            "number of bytes in compressed file is less than header size"))
+    }
+    Ok(num_bytes)
+}
+
+fn datestring (dt: &DateTime<Local>) -> String {
+    format!("{} {}", month(dt.month()), dt.day())
+}
+
+fn timestring (dt: &DateTime<Local>) -> String {
+    format!("{}:{:02}", dt.hour(), dt.minute())
+}
+
+fn get_filename_str (stored_filename: &Option<String>, path: &Option<PathBuf>, opt: &Options) -> String {
+    if opt.name {
+        match stored_filename {
+            Some(s) => String::from(s),
+            None => String::from("????")
+        }
+    }
+    else if let Some(p) = path {
+        match util::make_ofname(p, opt) {
+            Ok(Some(filename)) => filename.to_string_lossy().into_owned(),
+            _ => String::from("????")
+        }
+    }
+    else {
+        String::from("????")
+    }
 }
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+use crate::constants;
+use crate::error::Error;
+use crate::formats::codec::{Codec, Registry};
+use crate::formats::pkzip::Pkzip;
+use crate::formats::Sniffed;
+use crate::formats::gz::Subfield;
+
+/// What rstzip does with each input
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Compress,
+    Decompress,
+    /// decompress without writing any output, only checking integrity
+    Test,
+    /// describe compressed files instead of decompressing them
+    List,
+    /// decompress without writing any output, saving an index for random access next to
+    /// each gzip file
+    Index
+}
+
+/// Receives every diagnostic line rstzip emits while it works. The command line tool prints
+/// them to stderr; embedding code can collect or discard them instead.
+pub type Sink = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// How files are compressed and decompressed. This is the library's equivalent of the command
+/// line flags: `cli::Opt` only translates arguments into it.
+#[derive(Clone)]
+pub struct Options {
+    pub mode: Mode,
+    /// the codecs compressed data is recognized with
+    pub codecs: Registry,
+    /// name of the codec to compress with. With `zip`, a directory is packed into one archive
+    /// and archives are extracted whole.
+    pub format: String,
+    /// with `recursive`, pack each directory into one tar archive compressed in `format`, and
+    /// extract such archives when decompressing
+    pub tar: bool,
+    /// with the `bgzf` format, write a `.gzi` index of the blocks next to each compressed file
+    pub gzi: bool,
+    /// decompress only this many bytes, starting at this offset of the data, jumping straight
+    /// to them with the `.gzi` index of a BGZF file or the index `Mode::Index` saves
+    pub range: Option<(u64, u64)>,
+    /// compression level, 1 (fastest) through 9 (best)
+    pub level: u32,
+    /// compress with this many threads, producing the same output for any count
+    pub threads: Option<usize>,
+    /// make the output friendlier to rsync
+    pub rsyncable: bool,
+    /// save the original file name when compressing, restore it when decompressing
+    pub name: bool,
+    /// save the modification time when compressing, restore it when decompressing
+    pub time: bool,
+    /// protect the header with a CRC16 (FHCRC) when compressing
+    pub header_crc: bool,
+    /// subfields to store in the extra field (FEXTRA) when compressing
+    pub extra: Vec<Subfield>,
+    /// comment to store in the header (FCOMMENT) when compressing
+    pub comment: Option<String>,
+    /// suffix of compressed files, without the leading dot
+    pub suffix: String,
+    /// write to standard output instead of to files, leaving the inputs in place
+    pub stdout: bool,
+    /// overwrite existing files and compress files that would otherwise be skipped
+    pub force: bool,
+    /// keep input files instead of deleting them
+    pub keep: bool,
+    /// descend into directories
+    pub recursive: bool,
+    /// how many directories deep `recursive` descends below each operand
+    pub max_depth: usize,
+    /// suppress warnings
+    pub quiet: bool,
+    /// how much extra detail to report; 0 reports nothing extra
+    pub verbose: u8,
+    sink: Sink
+}
+
+impl Options {
+    /// Options for `mode` with gzip's defaults: level 6, the `gz` suffix, and the name and
+    /// time saved when compressing but not restored when decompressing. Diagnostics are
+    /// written to stderr.
+    pub fn new (mode: Mode) -> Self {
+        let compressing = mode == Mode::Compress;
+        Options {
+            mode,
+            codecs: Registry::new(),
+            format: String::from(constants::DEFAULT_FORMAT),
+            tar: false,
+            gzi: false,
+            range: None,
+            level: constants::DEFAULT_LEVEL as u32,
+            threads: None,
+            rsyncable: false,
+            name: compressing,
+            time: compressing,
+            header_crc: false,
+            extra: Vec::new(),
+            comment: None,
+            suffix: String::from(constants::DEFAULT_SUFFIX),
+            stdout: false,
+            force: false,
+            keep: false,
+            recursive: false,
+            max_depth: constants::MAX_DEPTH,
+            quiet: false,
+            verbose: 0,
+            sink: Arc::new(|msg: &str| eprintln!("{}", msg))
+        }
+    }
+
+    /// Sends diagnostics to `sink` instead of stderr
+    pub fn with_sink<F> (mut self, sink: F) -> Self
+        where F: Fn(&str) + Send + Sync + 'static
+    {
+        self.sink = Arc::new(sink);
+        self
+    }
+
+    /// Whether inputs are compressed data, i.e. anything but `Mode::Compress`
+    pub fn decompressing (&self) -> bool {
+        self.mode != Mode::Compress
+    }
+
+    /// Whether output files are created (and inputs deleted) rather than writing to stdout or
+    /// writing nothing at all
+    pub fn writes_files (&self) -> bool {
+        !self.stdout && (self.mode == Mode::Compress || self.mode == Mode::Decompress)
+    }
+
+    /// Whether files are packed into and extracted from ZIP archives, rather than compressed
+    /// one by one
+    pub(crate) fn zip_archives (&self) -> bool {
+        self.format == Pkzip.name()
+    }
+
+    /// Identifies the format of `input` by its magic number, returning its codec and a reader
+    /// that still yields the whole stream. Data no codec recognizes is read with the codec
+    /// `format` names, if it isn't the default, since formats without a magic number such as
+    /// raw deflate can only be read when asked for.
+    pub(crate) fn sniff<R: Read> (&self, input: R) -> io::Result<(Option<&dyn Codec>, Sniffed<R>)> {
+        let (codec, input) = self.codecs.sniff(input)?;
+        let asked = Some(self.format.as_str()).filter(|&format| format != constants::DEFAULT_FORMAT)
+            .and_then(|format| self.codecs.get(format));
+        Ok((codec.or(asked), input))
+    }
+
+    /// Reports `err` through the sink, unless it is a warning and `quiet` is set
+    pub(crate) fn report (&self, err: &Error) {
+        if !(self.quiet && err.is_warning()) {
+            self.emit(format_args!("{}: {}", constants::PROGRAM_NAME, err));
+        }
+    }
+
+    /// Emits one diagnostic line
+    pub(crate) fn emit (&self, msg: fmt::Arguments) {
+        (self.sink)(&msg.to_string());
+    }
+}
+
+impl Default for Options {
+    fn default () -> Self {
+        Options::new(Mode::Compress)
+    }
+}
+
+impl fmt::Debug for Options {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("mode", &self.mode)
+            .field("codecs", &self.codecs)
+            .field("format", &self.format)
+            .field("tar", &self.tar)
+            .field("gzi", &self.gzi)
+            .field("range", &self.range)
+            .field("level", &self.level)
+            .field("threads", &self.threads)
+            .field("rsyncable", &self.rsyncable)
+            .field("name", &self.name)
+            .field("time", &self.time)
+            .field("header_crc", &self.header_crc)
+            .field("extra", &self.extra)
+            .field("comment", &self.comment)
+            .field("suffix", &self.suffix)
+            .field("stdout", &self.stdout)
+            .field("force", &self.force)
+            .field("keep", &self.keep)
+            .field("recursive", &self.recursive)
+            .field("max_depth", &self.max_depth)
+            .field("quiet", &self.quiet)
+            .field("verbose", &self.verbose)
+            .finish()
+    }
+}
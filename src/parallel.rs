@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::io::{Error, Read, Write};
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use crate::constants;
+
+/// A block of input waiting to be deflated, along with the tail of the block before it
+struct Job {
+    index: usize,
+    dict: Vec<u8>,
+    data: Vec<u8>,
+    last: bool
+}
+
+type Done = (usize, std::io::Result<(Vec<u8>, Crc)>);
+
+/// Deflates `input` pigz-style: the input is cut into blocks of `constants::BLOCK_SIZE` bytes
+/// that are compressed on `threads` workers, each primed with the last 32 KiB of the block
+/// before it. Every block but the last ends in a sync flush, so the blocks concatenate into a
+/// single raw deflate stream. Because a block's output depends only on its own data, its
+/// dictionary and the level, the stream is the same no matter how many threads are used.
+///
+/// Returns the CRC32 and length of the input, for the gzip trailer.
+pub fn deflate<R: Read, W: Write> (mut input: R, output: &mut W, level: Compression, threads: usize)
+    -> std::io::Result<Crc>
+{
+    let threads = threads.max(1);
+    let (job_tx, job_rx) = sync_channel::<Job>(threads * 2);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let (done_tx, done_rx) = channel::<Done>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let job_rx = Arc::clone(&job_rx);
+            let done_tx = done_tx.clone();
+            scope.spawn(move || worker(job_rx, done_tx, level));
+        }
+        drop(done_tx);
+
+        let mut writer = InOrder { output, next: 0, pending: BTreeMap::new(), crc: Crc::new() };
+        let mut index = 0;
+        let mut dict: Vec<u8> = Vec::new();
+        let mut block = read_block(&mut input)?;
+        loop {
+            let next = read_block(&mut input)?;
+            let last = next.is_empty();
+            let tail = block[block.len().saturating_sub(constants::WINDOW_SIZE)..].to_vec();
+            let job = Job { index, dict, data: block, last };
+            if job_tx.send(job).is_err() {
+                break;
+            }
+            index += 1;
+            dict = tail;
+            while let Ok(done) = done_rx.try_recv() {
+                writer.push(done)?;
+            }
+            if last {
+                break;
+            }
+            block = next;
+        }
+        drop(job_tx);
+        for done in done_rx.iter() {
+            writer.push(done)?;
+        }
+        if writer.next != index {
+            return Err(Error::other("parallel compression worker exited early"));
+        }
+        Ok(writer.crc)
+    })
+}
+
+/// Writes finished blocks in input order, holding back any that complete early
+struct InOrder<'a, W: Write> {
+    output: &'a mut W,
+    next: usize,
+    pending: BTreeMap<usize, (Vec<u8>, Crc)>,
+    crc: Crc
+}
+
+impl<W: Write> InOrder<'_, W> {
+    fn push (&mut self, (index, res): Done) -> std::io::Result<()> {
+        self.pending.insert(index, res?);
+        while let Some((data, crc)) = self.pending.remove(&self.next) {
+            self.output.write_all(&data)?;
+            self.crc.combine(&crc);
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+fn worker (jobs: Arc<Mutex<Receiver<Job>>>, done: std::sync::mpsc::Sender<Done>, level: Compression) {
+    loop {
+        let job = match jobs.lock() {
+            Ok(rx) => match rx.recv() {
+                Ok(job) => job,
+                Err(_) => return
+            },
+            Err(_) => return
+        };
+        let res = deflate_block(&job.dict, &job.data, job.last, level).map(|out| {
+            let mut crc = Crc::new();
+            crc.update(&job.data);
+            (out, crc)
+        });
+        if done.send((job.index, res)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Compresses one block as raw deflate. Non-final blocks are ended with a sync flush so that
+/// they finish on a byte boundary.
+fn deflate_block (dict: &[u8], data: &[u8], last: bool, level: Compression) -> std::io::Result<Vec<u8>> {
+    let mut c = Compress::new(level, false);
+    if !dict.is_empty() {
+        c.set_dictionary(dict).map_err(Error::other)?;
+    }
+    let flush = if last { FlushCompress::Finish } else { FlushCompress::Sync };
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() + data.len() / 1000 + 64);
+    loop {
+        if out.len() == out.capacity() {
+            out.reserve(constants::WINDOW_SIZE);
+        }
+        let consumed = c.total_in() as usize;
+        let status = c.compress_vec(&data[consumed..], &mut out, flush)
+            .map_err(Error::other)?;
+        let all_in = c.total_in() as usize == data.len();
+        match status {
+            Status::StreamEnd => return Ok(out),
+            // the flush is complete once zlib stops short of filling the output buffer
+            _ if !last && all_in && out.len() < out.capacity() => return Ok(out),
+            _ => continue
+        }
+    }
+}
+
+/// Reads up to `constants::BLOCK_SIZE` bytes, returning fewer only at the end of the input
+fn read_block<R: Read> (input: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut block: Vec<u8> = Vec::with_capacity(constants::BLOCK_SIZE);
+    input.take(constants::BLOCK_SIZE as u64).read_to_end(&mut block)?;
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::DeflateDecoder;
+
+    #[test]
+    fn identical_across_thread_counts () {
+        let data: Vec<u8> = (0..(constants::BLOCK_SIZE * 5 + 1234))
+            .map(|i| ((i * 7) % 253) as u8 ^ (i / 1000) as u8).collect();
+        let mut outputs: Vec<Vec<u8>> = Vec::new();
+        for threads in &[1, 2, 3, 8] {
+            let mut out: Vec<u8> = Vec::new();
+            let crc = deflate(&data[..], &mut out, Compression::new(6), *threads).unwrap();
+            assert_eq!(crc.amount() as usize, data.len());
+            let mut expected = Crc::new();
+            expected.update(&data);
+            assert_eq!(crc.sum(), expected.sum());
+            outputs.push(out);
+        }
+        assert!(outputs.windows(2).all(|w| w[0] == w[1]));
+
+        let mut decoded: Vec<u8> = Vec::new();
+        DeflateDecoder::new(&outputs[0][..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn empty_input () {
+        let mut out: Vec<u8> = Vec::new();
+        let crc = deflate(&b""[..], &mut out, Compression::new(6), 4).unwrap();
+        assert_eq!(crc.amount(), 0);
+        let mut decoded: Vec<u8> = Vec::new();
+        DeflateDecoder::new(&out[..]).read_to_end(&mut decoded).unwrap();
+        assert!(decoded.is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::{Error, Read, Write};
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use crate::constants;
+
+/// The rolling sum GNU gzip uses to pick reset points: the sum of the last `RSYNC_WIN` bytes
+/// of input, which triggers whenever its low bits are all zero.
+struct RollingSum {
+    window: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    sum: u32
+}
+
+impl RollingSum {
+    fn new () -> Self {
+        RollingSum { window: vec![0; constants::RSYNC_WIN], pos: 0, filled: 0, sum: 0 }
+    }
+
+    /// Adds `byte` to the window and reports whether the compressor should be reset after it
+    fn roll (&mut self, byte: u8) -> bool {
+        self.sum = self.sum.wrapping_add(byte.into());
+        if self.filled >= constants::RSYNC_WIN {
+            self.sum = self.sum.wrapping_sub(self.window[self.pos].into());
+        }
+        else {
+            self.filled += 1;
+        }
+        self.window[self.pos] = byte;
+        self.pos = (self.pos + 1) % constants::RSYNC_WIN;
+        self.filled >= constants::RSYNC_WIN && self.sum & (constants::RSYNC_WIN as u32 - 1) == 0
+    }
+}
+
+/// Deflates `input` the way `gzip --rsyncable` does: whenever the rolling sum of the input
+/// triggers, the compressor is fully flushed, which ends the current block on a byte boundary
+/// and forgets all history. Reset points depend only on nearby content, so an edit to the
+/// input only changes the compressed output up to the next reset point after it.
+///
+/// Returns the CRC32 and length of the input, for the gzip trailer.
+pub fn deflate<R: Read, W: Write> (mut input: R, output: &mut W, level: Compression) -> std::io::Result<Crc> {
+    let mut c = Compress::new(level, false);
+    let mut crc = Crc::new();
+    let mut rolling = RollingSum::new();
+    let mut inbuf: Vec<u8> = vec![0; constants::BLOCK_SIZE];
+    let mut outbuf: Vec<u8> = Vec::with_capacity(constants::BLOCK_SIZE);
+    loop {
+        let n = input.read(&mut inbuf)?;
+        if n == 0 {
+            break;
+        }
+        let data = &inbuf[..n];
+        crc.update(data);
+        let mut start = 0;
+        for (i, &byte) in data.iter().enumerate() {
+            if rolling.roll(byte) {
+                feed(&mut c, &data[start..=i], FlushCompress::Full, &mut outbuf, output)?;
+                start = i + 1;
+            }
+        }
+        feed(&mut c, &data[start..], FlushCompress::None, &mut outbuf, output)?;
+    }
+    feed(&mut c, &[], FlushCompress::Finish, &mut outbuf, output)?;
+    Ok(crc)
+}
+
+/// Passes all of `data` through the compressor with the given flush, writing whatever it
+/// produces to `output`
+fn feed<W: Write> (c: &mut Compress, data: &[u8], flush: FlushCompress, outbuf: &mut Vec<u8>,
+    output: &mut W) -> std::io::Result<()>
+{
+    let start_in = c.total_in();
+    loop {
+        outbuf.clear();
+        let consumed = (c.total_in() - start_in) as usize;
+        let status = c.compress_vec(&data[consumed..], outbuf, flush).map_err(Error::other)?;
+        output.write_all(outbuf)?;
+        let all_in = (c.total_in() - start_in) as usize == data.len();
+        match status {
+            Status::StreamEnd => return Ok(()),
+            // zlib is done once it stops short of filling the output buffer
+            _ if all_in && flush != FlushCompress::Finish && outbuf.len() < outbuf.capacity() => return Ok(()),
+            _ => continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::DeflateDecoder;
+
+    #[test]
+    fn round_trip () {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8 % 16 + b'a').collect();
+        let mut out: Vec<u8> = Vec::new();
+        let crc = deflate(&data[..], &mut out, Compression::new(6)).unwrap();
+        assert_eq!(crc.amount() as usize, data.len());
+        let mut decoded: Vec<u8> = Vec::new();
+        DeflateDecoder::new(&out[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -1,314 +1,735 @@
 use crate::util::{WrappedFile, WorkData};
 use std::path::{PathBuf, Path};
-use std::fs::{File, ReadDir, remove_file, read_dir, metadata};
-use std::io::{Read, Write, ErrorKind};
+use std::fs::{self, File, remove_file, read_dir, metadata};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write, ErrorKind};
 use std::time::SystemTime;
-use std::process::exit;
-use crate::{Opt, EXIT_CODE, warn, util, constants};
-use crate::formats::gz::GzFile;
-use crate::formats::zip::{Test, Zip};
-use crate::formats::TryFromReadable;
+use std::convert::TryFrom;
+use crate::{util, constants};
+use crate::error::{Error, Warning};
+use crate::options::{Mode, Options};
+use crate::formats::gz::{self, GzFile, GzIndex, HeaderError, IndexedGz};
+use crate::formats::Sniffed;
+use crate::formats::codec::{Codec, Tested};
+use crate::formats::zlib::Zlib;
+use crate::formats::archive;
+use crate::formats::bgzf;
+use crate::formats::pkzip::{self, ZipWriter};
+use crate::formats::tar::{self, TarStream};
+
+use flate2::bufread::MultiGzDecoder;
 
 extern crate atty;
 
-pub fn files (files: Vec<PathBuf>, opt: &mut Opt) -> Result<(), i8> {
-    let mut exit_code: Result<(), i8> = Ok(());
+/// Reports the errors met while processing a batch of files as they happen, and remembers the
+/// worst of them for the exit status
+pub(crate) struct Batch<'a> {
+    opt: &'a Options,
+    worst: Option<Error>
+}
+
+impl<'a> Batch<'a> {
+    pub(crate) fn new (opt: &'a Options) -> Self {
+        Batch { opt, worst: None }
+    }
+
+    /// Reports `err`, returning whether the remaining files should still be processed. Like
+    /// gzip, only a fatal error stops the batch; a file that fails is reported and skipped.
+    pub(crate) fn failed (&mut self, err: Error) -> bool {
+        self.opt.report(&err);
+        let carry_on = !err.is_fatal();
+        self.worst = Some(match self.worst.take() {
+            Some(worst) => worst.worst(err),
+            None => err
+        });
+        carry_on
+    }
+
+    pub(crate) fn finish (self) -> Result<(), Error> {
+        match self.worst {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+}
+
+pub fn files (files: Vec<PathBuf>, opt: &Options) -> Result<(), Error> {
+    let mut batch = Batch::new(opt);
+    let mut ancestors: Vec<PathBuf> = Vec::new();
     for file in files {
-        match self::file (file, opt) {
-            Ok(()) => continue,
-            Err(i) => {
-                if i == constants::ERROR {
-                    return Err(constants::ERROR);
-                }
-                else{
-                    exit_code = Err(i);
-                }
-            }
+        if !visit(file, &mut batch, &mut ancestors) {
+            break;
+        }
+    }
+    batch.finish()
+}
+
+/// Processes one operand, or one entry of a directory being walked, returning whether the
+/// remaining files should still be processed
+fn visit (filepath: PathBuf, batch: &mut Batch, ancestors: &mut Vec<PathBuf>) -> bool {
+    let opt = batch.opt;
+    let res = if check_for_stdin(&filepath) {
+        self::stdin(opt)
+    }
+    else if metadata(&filepath).map(|m| m.is_dir()).unwrap_or(false) {
+        if !opt.recursive {
+            Err(Error::warning(filepath, Warning::Directory))
+        }
+        else if (opt.zip_archives() || opt.tar) && opt.mode == Mode::Compress {
+            return self::archive(filepath, batch, ancestors);
+        }
+        else {
+            return self::dir(filepath, batch, ancestors);
         }
     }
-    exit_code
+    else {
+        self::file(&filepath, opt)
+    };
+    match res {
+        Ok(()) => true,
+        Err(err) => batch.failed(err)
+    }
 }
 
-pub fn stdin (opt: &mut Opt) -> Result<(), i8>{
-    check_for_tty(opt);
+pub fn stdin (opt: &Options) -> Result<(), Error> {
+    check_for_tty(opt)?;
+    if opt.mode == Mode::Test {
+        return test(std::io::stdin(), Path::new("stdin"), opt);
+    }
+    if opt.mode == Mode::Decompress && opt.zip_archives() {
+        // the central directory at the end of an archive is needed before anything is extracted
+        return Err(Error::file("stdin", io::Error::new(ErrorKind::Unsupported,
+            "zip archives can only be extracted from files")));
+    }
+    if opt.range.is_some() {
+        return Err(Error::file("stdin", io::Error::new(ErrorKind::Unsupported,
+            "ranges can only be read from files")));
+    }
+    if opt.mode == Mode::Index {
+        // the index is saved next to the file, and only useful for seeking in it
+        return Err(Error::file("stdin", io::Error::new(ErrorKind::Unsupported,
+            "only files can be indexed")));
+    }
+    if opt.mode == Mode::Decompress && opt.tar {
+        // every entry is checked before any is extracted, which takes reading the input twice
+        return Err(Error::file("stdin", io::Error::new(ErrorKind::Unsupported,
+            "tar archives can only be extracted from files")));
+    }
 
     let work_data = WorkData {
         mtime: None,
         orig_name: None,
         ofname: String::from("stdout")
     };
-    if let Err(_) = work (std::io::stdin(), work_data, opt) {
-        return Err(constants::ERROR);
+    match work (std::io::stdin(), work_data, opt).map_err(|e| Error::file("stdin", e))? {
+        Some(warning) => Err(Error::warning("stdin", warning)),
+        None => Ok(())
     }
-    return Ok(());
 }
 
-fn check_for_tty (opt: &Opt) {
-    let isatty = if opt.decompress {
+fn check_for_tty (opt: &Options) -> Result<(), Error> {
+    let isatty = if opt.decompressing() {
         atty::is(atty::Stream::Stdin)
     }
     else {
         atty::is(atty::Stream::Stdout)
     };
-    if !opt.force && !opt.list && isatty {
-        if !opt.quiet {
-            errors::tty_err_msg(opt.decompress);
+    if !opt.force && opt.mode != Mode::List && isatty {
+        let (readwrite, de) = if opt.decompressing() {
+            ("read from", "de")
         }
-        exit (constants::ERROR.into());
+        else {
+            ("written to", "")
+        };
+        return Err(Error::fatal(format!("compressed data not {1} a terminal. \
+            Use -f to force {2}compression.\n\
+            For help, type: {0} -h", constants::PROGRAM_NAME, readwrite, de)));
     }
+    Ok(())
 }
 
-fn file (filepath: PathBuf, opt: &mut Opt) -> Result<(), i8> {
-    let fstr = match filepath.to_str() {
-        Some(s) => s,
-        None => {
-            let msg = "file does not have valid unicode name";
-            eprintln!("{}: {}", constants::PROGRAM_NAME, msg);
-            return Err(constants::ERROR);
-        }
+fn file (filepath: &Path, opt: &Options) -> Result<(), Error> {
+    let fstr = filepath.to_str().ok_or_else(|| Error::file(filepath,
+        io::Error::new(ErrorKind::InvalidInput, "file does not have valid unicode name")))?;
+    let f = util::file_open (filepath)?;
+    let stat = f.metadata().map_err(|e| Error::file(filepath, e))?;
+    let wrapped_file = util::WrappedFile {path: filepath, file: &f};
+    util::check_file_modes(&wrapped_file, opt)?;
+
+    let ofname = match util::make_ofname(filepath, opt)? {
+        Some(ofname) => ofname,
+        None => return Ok(())
     };
-    if check_for_stdin(fstr) {
-        let cflag = opt.stdout;
-        let exit = self::stdin(opt);
-        opt.stdout = cflag;
-        return exit;
-    }
-    else{
-        let fpath: &Path = filepath.as_path();
-        let f = match util::file_open (&filepath) {
-            Ok(f) => f,
-            Err(_) => return Err(constants::ERROR)
-        };
-        let stat = match f.metadata() {
-            Ok(m) => m,
-            // TODO: should this actually return? Check gzip's behavior
-            Err(_) => return Err(constants::ERROR)
-        };
-        if stat.is_dir() {
-            let dir: ReadDir = match read_dir(fpath) {
-                Ok(it) => it,
-                Err(_) => {
-                    if opt.verbose > 1 {
-                        eprintln!("{}: {}: internal error while reading directory",
-                            constants::PROGRAM_NAME, fstr);
-                    }
-                    return Err(constants::ERROR);
-                }
-            };
-            let wrapped_dir = util::WrappedDir {path: fpath, dir: dir};
-            self::try_dir(wrapped_dir, opt)?;
-        }
-        else {
-            let wrapped_file = util::WrappedFile {path: fpath, file: &f};
-            // if this fails, we must have something that isn't a regular file
-            match util::check_file_modes(&wrapped_file, opt) {
-                Ok(b) => if !b {
-                    if opt.verbose > 1 {
-                        eprintln!("{}: {}: not a regular file", constants::PROGRAM_NAME, fstr);
-                    }
-                    return Err(constants::ERROR);
-                },
-                Err(_) => {
-                    if opt.verbose > 1 {
-                        eprintln!("{}: {}: unable to access file modes", constants::PROGRAM_NAME, fstr);
-                    }
+    if opt.mode == Mode::Test {
+        return test(&f, filepath, opt);
+    }
+    if opt.mode == Mode::Index {
+        return index(&f, filepath, opt);
+    }
+    if let Some((offset, len)) = opt.range {
+        return range(f, filepath, offset, len);
+    }
+    if opt.mode == Mode::Decompress && (opt.zip_archives() || opt.tar) {
+        return extract(f, filepath, opt);
+    }
+    let ofname_str = ofname.to_string_lossy().into_owned();
 
-                }
-            }
+    let mtime = util::get_input_time(&stat).map_err(|e| Error::file(filepath, e))?;
 
-            let ofname = match util::make_ofname(&filepath, opt) {
-                Ok(boxed_path_buf) => boxed_path_buf,
-                Err(_) => return Err(constants::ERROR)
-            };
+    let work_data = WorkData::new (Some(String::from(fstr)), Some(mtime), ofname_str.clone(), opt);
 
-            let ofname_str: &str = (*ofname).to_str().unwrap();
+    let (written, warning) = if file_would_replace(&ofname_str) && !opt.force && opt.writes_files() {
+        overwrite_prompt(&wrapped_file, work_data, opt)
+    }
+    else {
+        work(wrapped_file.file, work_data, opt).map(|warning| (true, warning))
+    }.map_err(|e| Error::file(filepath, e))?;
 
-            let mtime = match util::get_input_time(&stat) {
-                Ok(mtime) => mtime,
-                Err(_) => return Err(constants::ERROR)
-            };
+    // delete the file if necessary
+    if written && opt.writes_files() && !opt.keep {
+        remove_file(filepath).map_err(|e| Error::file(filepath, e))?;
+    }
+    match warning {
+        Some(warning) => Err(Error::warning(filepath, warning)),
+        None => Ok(())
+    }
+}
 
-            let work_data = WorkData::new (Some(String::from(fstr)), Some(mtime), String::from(ofname_str), opt);
+/// Extracts the ZIP archive, or with `--tar` the compressed tar archive, `f`, read from
+/// `filepath`, next to it, then deletes it as gzip deletes the files it decompresses
+fn extract (mut f: File, filepath: &Path, opt: &Options) -> Result<(), Error> {
+    let dest = match filepath.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
+    };
+    let tested = if opt.tar {
+        tar::extract(&mut f, dest, opt)
+    }
+    else {
+        pkzip::extract(&mut f, dest, opt)
+    }.map_err(|e| Error::file(filepath, e))?;
+    if opt.writes_files() && !opt.keep {
+        remove_file(filepath).map_err(|e| Error::file(filepath, e))?;
+    }
+    match tested.warning() {
+        Some(warning) => Err(Error::warning(filepath, warning)),
+        None => Ok(())
+    }
+}
 
-            if file_would_replace(ofname_str) && !opt.force && !opt.stdout {
-                if let Err(_) = overwrite_prompt(&wrapped_file, work_data, opt) {
-                    return Err(constants::ERROR);
-                }
-            }
-            else {
-                if let Err(_) = work(wrapped_file.file, work_data, opt) {
-                    return Err(constants::ERROR);
+/// Writes `len` bytes starting at `offset` of the data of the gzip file `f` to stdout. A BGZF
+/// file is read from the block its `.gzi` index names, or found by walking the blocks if it
+/// has none or it is out of date; any other gzip file from the access point its `--index` index names, or from its
+/// start if it has none.
+fn range (mut f: File, filepath: &Path, offset: u64, len: u64) -> Result<(), Error> {
+    let res = (|| -> io::Result<()> {
+        let bgzf = GzFile::read_header(&mut f)?.is_bgzf();
+        let stdout = std::io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        if bgzf {
+            // an index older than the file, or naming blocks past its end, was made for
+            // another version of it
+            let meta = f.metadata()?;
+            let saved = match File::open(bgzf::index_path(filepath)) {
+                Ok(gzi) if gzi.metadata()?.modified()? >= meta.modified()? => {
+                    Some(bgzf::Index::read(BufReader::new(gzi))?).filter(|index| index.describes(meta.len()))
+                },
+                Ok(_) => None,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e)
+            };
+            let index = match saved {
+                Some(index) => index,
+                None => bgzf::Index::scan(&mut f)?
+            };
+            bgzf::read_range(&mut f, &index, offset, len, &mut out)?;
+            return out.flush();
+        }
+        let index = match File::open(gz::index_path(filepath)) {
+            Ok(saved) => Some(GzIndex::read(BufReader::new(saved))?),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e)
+        };
+        match index {
+            Some(index) => {
+                if !index.describes(f.metadata()?.len()) {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "index is out of date; rebuild it with --index"));
                 }
+                let mut data = IndexedGz::new(f, index);
+                data.seek(SeekFrom::Start(offset))?;
+                io::copy(&mut data.take(len), &mut out)?;
+            },
+            None => {
+                f.seek(SeekFrom::Start(0))?;
+                let mut data = MultiGzDecoder::new(BufReader::new(f));
+                io::copy(&mut (&mut data).take(offset), &mut io::sink())?;
+                io::copy(&mut data.take(len), &mut out)?;
             }
+        }
+        out.flush()
+    })();
+    res.map_err(|e| Error::file(filepath, e))
+}
 
-            // delete the file if necessary
-            if !opt.stdout && !opt.keep {
-                if let Err(e) = remove_file(fpath) {
-                    match e.kind() {
-                        ErrorKind::PermissionDenied => errors::permission_denied_err_msg(fstr, "delete"),
-                        _ => errors::file_delete_err_msg(fstr)
-                    }
-                }
-            }
-         }
-        Ok(())
+/// Decompresses the gzip file `f` once, checking it, and saves an index of it for `--range`
+/// next to it
+fn index (f: &File, filepath: &Path, opt: &Options) -> Result<(), Error> {
+    let index = GzIndex::build(f, GzIndex::DEFAULT_SPAN).map_err(|e| Error::file(filepath, e))?;
+    let saved = gz::index_path(filepath);
+    to_file(&saved, |out| index.write(out)).map_err(|e| Error::file(&saved, e))?;
+    if opt.verbose > 0 {
+        println!("{}:\t {} access points", filepath.display(), index.len());
     }
+    Ok(())
 }
 
-fn work<R: Read> (input: R, work_data: WorkData, opt: &mut Opt) -> std::io::Result<()> {
+/// Compresses or decompresses `input` as `opt` says, returning the warning decompression
+/// ended with, if any
+fn work<R: Read> (input: R, work_data: WorkData, opt: &Options) -> std::io::Result<Option<Warning>> {
+    let input = BufReader::new(input);
     let ofname_str = work_data.ofname.clone();
-    let mut name_from_compressed_file: Option<String> = None;
-    let mut mtime_from_compressed_file: Option<u32> = None;
-    if opt.test {
-        let gz: GzFile = TryFromReadable::try_from(input)?;
-        gz.test(opt);
-        return Ok(())
-    }
-    let output: Vec<u8> = if !opt.decompress {
-        if opt.no_name || work_data.mtime.is_none() || work_data.orig_name.is_none() {
-            GzFile::compress(input, None, opt)?
+    let to_stdout = opt.stdout || ofname_str == "stdout";
+    if opt.mode == Mode::Compress {
+        let wdata = if work_data.mtime.is_none() || work_data.orig_name.is_none() {
+            None
         }
         else {
-            GzFile::compress(input, Some(work_data), opt)?
+            Some(work_data)
+        };
+        let codec = opt.codecs.get(&opt.format).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput,
+            format!("unknown format {}", opt.format)))?;
+        let mut input = input;
+        if to_stdout {
+            let stdout = std::io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            codec.encode(&mut input, &mut out, wdata, opt)?;
+            out.flush()?;
+        }
+        else {
+            to_file(&ofname_str, |out| codec.encode(&mut input, out, wdata, opt))?;
         }
+        return Ok(None)
+    }
+
+    let (codec, mut input) = opt.sniff(input)?;
+    let codec = match codec {
+        // zlib has a two byte magic number some text passes for
+        Some(codec) if opt.force && to_stdout && codec.name() == Zlib.name() => {
+            return decode_or_pass(codec, input);
+        },
+        Some(codec) => codec,
+        None if opt.force && to_stdout => {
+            // like gzip -cf, pass data in no format we know through unchanged
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            std::io::copy(&mut input, &mut out)?;
+            out.flush()?;
+            return Ok(None);
+        },
+        None => return Err(unrecognized(&input))
+    };
+    let header = codec.read_header(&mut input)?;
+    if opt.verbose > 0 {
+        let path = work_data.orig_name.as_deref().unwrap_or("stdin");
+        for subfield in &header.subfields {
+            opt.emit(format_args!("{}: {}: extra field {}", constants::PROGRAM_NAME, path, subfield));
+        }
+    }
+    let mut name_from_compressed_file: Option<String> = None;
+    let mut mtime_from_compressed_file: Option<i64> = None;
+    if opt.name {
+        name_from_compressed_file = header.name.clone();
+        if name_from_compressed_file.is_none() && opt.verbose > 1 {
+            opt.emit(format_args!("{}: no name found within compressed file", constants::PROGRAM_NAME));
+        }
+    }
+    if opt.time {
+        mtime_from_compressed_file = header.mtime;
+    }
+
+    if to_stdout {
+        let stdout = std::io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let tested = codec.decode(&header, &mut input, &mut out)?;
+        out.flush()?;
+        Ok(tested.warning())
     }
     else {
-        let gz: GzFile = TryFromReadable::try_from(input)?;
-        if !opt.no_name {
-            name_from_compressed_file = gz.stored_filename.clone();
-            if name_from_compressed_file == None && opt.verbose > 1 {
-                eprintln!("{}: no name found within compressed file", constants::PROGRAM_NAME);
-            }
-            mtime_from_compressed_file = Some(gz.mtime);
+        // if we specified no_name on decompression, use the calculated output file name,
+        // otherwise use the one stored within the file, falling back to the calculated
+        // if necessary. Only the last component of a stored name is used, and the file is
+        // created next to the input, as gzip does.
+        // TODO: we may actually overwrite a file on decompression too! Need to fix this
+        let stored = name_from_compressed_file.as_deref().and_then(|name| Path::new(name).file_name());
+        let fname = match stored {
+            Some(name) => Path::new(&ofname_str).with_file_name(name),
+            None => PathBuf::from(ofname_str)
+        };
+        let mut tested = Tested::default();
+        to_file(&fname, |out| {
+            tested = codec.decode(&header, &mut input, out)?;
+            Ok(())
+        })?;
+        // modify the mtime as well if we have that
+        if let Some(mtime) = mtime_from_compressed_file.and_then(|t| u64::try_from(t).ok()) {
+            utime::set_file_times(fname, SystemTime::now().duration_since(
+                SystemTime::UNIX_EPOCH).unwrap().as_secs(), mtime)?;
         }
-        gz.decompress()?
+        Ok(tested.warning())
+    }
+}
+
+/// How much of the input and output of `decode_or_pass` is held back
+const PASSTHROUGH_WINDOW: usize = 64 * 1024;
+
+/// Decompresses `input` to stdout with `codec`, or copies it through unchanged, as `-cf` does
+/// for data in no format we know, if it turns out not to be in that format after all. Output
+/// is held back until `PASSTHROUGH_WINDOW` bytes have come out, so a stream that fails later
+/// than that is reported as corrupt.
+fn decode_or_pass<R: Read> (codec: &dyn Codec, input: R) -> io::Result<Option<Warning>> {
+    let mut recorder = Recorder { inner: input, seen: Vec::new(), full: false };
+    let stdout = io::stdout();
+    let mut out = HeldBack { inner: BufWriter::new(stdout.lock()), held: Vec::new(), released: false };
+    let res = {
+        let mut input = BufReader::new(&mut recorder);
+        codec.read_header(&mut input).and_then(|header| codec.decode(&header, &mut input, &mut out))
     };
+    if res.is_err() && !recorder.full && !out.released {
+        let mut out = out.inner;
+        out.write_all(&recorder.seen)?;
+        io::copy(&mut recorder.inner, &mut out)?;
+        out.flush()?;
+        return Ok(None);
+    }
+    let mut inner = out.inner;
+    inner.write_all(&out.held)?;
+    inner.flush()?;
+    res.map(|tested| tested.warning())
+}
+
+/// Keeps a copy of the first `PASSTHROUGH_WINDOW` bytes read from `inner`
+struct Recorder<R> {
+    inner: R,
+    seen: Vec<u8>,
+    /// whether more than `PASSTHROUGH_WINDOW` bytes were read, so `seen` was given up
+    full: bool
+}
 
-    if opt.stdout || ofname_str == "stdout" {
-        std::io::stdout().write_all(output.as_slice())?;
-        std::io::stdout().flush()?;
+impl<R: Read> Read for Recorder<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if !self.full {
+            if self.seen.len() + n > PASSTHROUGH_WINDOW {
+                self.full = true;
+                self.seen = Vec::new();
+            }
+            else {
+                self.seen.extend_from_slice(&buf[..n]);
+            }
+        }
+        Ok(n)
     }
-    else {
-        let mut fname = ofname_str.clone();
-        // if we are compressing or we specified no_name on decompression, calculate the output
-        // file name
-        let mut f = if opt.no_name || !opt.decompress {
-            File::create(ofname_str)?
+}
+
+/// Holds back the first `PASSTHROUGH_WINDOW` bytes written, then passes everything through
+/// to `inner`
+struct HeldBack<W: Write> {
+    inner: W,
+    held: Vec<u8>,
+    released: bool
+}
+
+impl<W: Write> Write for HeldBack<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.released {
+            return self.inner.write(buf);
         }
-        // otherwise use the one stored within the file, falling back to the calculated
-        // if necessary
-        else {
-            if let Some(name) = name_from_compressed_file {
-                let name = name.trim_end_matches(char::from(0));
-                fname = String::from(name);
-                match File::create(name) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        println!("{:?}", e);
-                        return Err(e);
-                    }
+        self.held.extend_from_slice(buf);
+        if self.held.len() > PASSTHROUGH_WINDOW {
+            self.released = true;
+            self.inner.write_all(&self.held)?;
+            self.held.clear();
+        }
+        Ok(buf.len())
+    }
+
+    /// Only flushes what was already passed through
+    fn flush (&mut self) -> io::Result<()> {
+        if self.released {
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the integrity of the compressed `input` without writing the data anywhere
+fn test<R: Read> (input: R, path: &Path, opt: &Options) -> Result<(), Error> {
+    let mut comment = None;
+    let res = opt.sniff(BufReader::new(input)).and_then(|(codec, mut input)| {
+        let codec = codec.ok_or_else(|| unrecognized(&input))?;
+        let header = codec.read_header(&mut input)?;
+        comment = header.comment.clone();
+        codec.test(&header, &mut input)
+    });
+    match res {
+        Ok(tested) => {
+            if opt.verbose > 0 {
+                println!("{}:\t OK", path.display());
+                if let Some(comment) = comment {
+                    println!("\t comment: {}", comment);
                 }
-                // TODO: we may actually overwrite a file on decompression too! Need to fix this
             }
-            else{
-                File::create(ofname_str)?
+            if let Some(warning) = tested.warning() {
+                return Err(Error::warning(path, warning));
             }
-        };
-        f.write_all(output.as_slice())?;
-        f.flush()?;
-        // modify the mtime as well if we have that
-        if !opt.no_name && opt.decompress {
-            if let Some(mtime) = mtime_from_compressed_file {
-                utime::set_file_times(fname, SystemTime::now().duration_since(
-                    SystemTime::UNIX_EPOCH).unwrap().as_secs(), mtime.into())?;
+            Ok(())
+        },
+        Err(e) => {
+            if opt.verbose > 0 {
+                println!("{}:\t FAILED", path.display());
             }
+            Err(Error::file(path, e))
         }
     }
-    Ok(())
+}
+
+/// The error for input in no format rstzip can read: "not in gzip format", as gzip words it,
+/// or "unexpected end of file" when there was no input at all
+fn unrecognized<R> (input: &Sniffed<R>) -> io::Error {
+    if input.get_ref().0.get_ref().is_empty() {
+        HeaderError::Truncated.into()
+    }
+    else {
+        HeaderError::NotGzip.into()
+    }
+}
+
+/// Creates `fname` and lets `write` stream into it. On failure the partially written output
+/// is removed, so a truncated file is never left behind.
+fn to_file<P, F> (fname: P, write: F) -> std::io::Result<()>
+    where P: AsRef<Path>, F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>
+{
+    let fname = fname.as_ref();
+    let mut out = BufWriter::new(File::create(fname)?);
+    let res = write(&mut out).and_then(|_| out.flush());
+    if res.is_err() {
+        let _ = remove_file(fname);
+    }
+    res
 }
 
 fn file_would_replace (file_name: &str) -> bool {
     let p: &Path = Path::new(file_name);
     match metadata(p) {
         Ok(_) => true,
-        Err(e) => if e.kind() == ErrorKind::AlreadyExists {true} else {false}
+        Err(e) => e.kind() == ErrorKind::AlreadyExists
     }
 }
 
-fn overwrite_prompt (wrapped_file: &WrappedFile, work_data: WorkData, opt: &mut Opt) -> std::io::Result<()> {
+/// Asks before replacing an existing output file, returning whether it was replaced and the
+/// warning the work ended with
+fn overwrite_prompt (wrapped_file: &WrappedFile, work_data: WorkData, opt: &Options)
+    -> std::io::Result<(bool, Option<Warning>)>
+{
     print!("{}: {} already exists; do you wish to overwrite (y or n)? ",
         constants::PROGRAM_NAME, &work_data.ofname);
     if util::yesno() {
-        work(wrapped_file.file, work_data, opt)
+        Ok((true, work(wrapped_file.file, work_data, opt)?))
     }
     else {
         println!("\tnot overwritten");
-        Ok(())
+        Ok((false, None))
     }
 }
 
-fn check_for_stdin (fstr: &str) -> bool {
-    if fstr == "-" {
-        return true;
-    }
-    return false;
+fn check_for_stdin (path: &Path) -> bool {
+    path == Path::new("-")
 }
 
-fn try_dir (dir: util::WrappedDir, opt: &mut Opt) -> Result<(), i8> {
-    if opt.recursive {
-        return self::dir(dir.dir, opt)
+/// Walks `path` depth first, in name order. `ancestors` holds the canonical paths of the
+/// directories entered on the way down, so that a symbolic link back up the tree isn't followed
+/// forever. Symbolic links inside the tree are only followed with `--force`. Returns whether the
+/// remaining files should still be processed.
+fn dir (path: PathBuf, batch: &mut Batch, ancestors: &mut Vec<PathBuf>) -> bool {
+    let opt = batch.opt;
+    if ancestors.len() >= opt.max_depth {
+        return batch.failed(Error::warning(path, Warning::TooDeep));
+    }
+    let canonical = match fs::canonicalize(&path) {
+        Ok(canonical) => canonical,
+        Err(e) => return batch.failed(Error::file(path, e))
+    };
+    if ancestors.contains(&canonical) {
+        return batch.failed(Error::warning(path, Warning::Loop));
     }
-    else{
-        let dir_name = dir.path.as_os_str().to_str().unwrap();
-        warn!("{}: {}: is a directory -- ignored", constants::PROGRAM_NAME, dir_name; constants::WARNING);
-        return Err(constants::WARNING)
+    let dir = match read_dir(&path) {
+        Ok(dir) => dir,
+        Err(e) => return batch.failed(Error::file(path, e))
+    };
+    let mut entries: Vec<PathBuf> = Vec::new();
+    for entry in dir {
+        match entry {
+            Ok(entry) => entries.push(entry.path()),
+            Err(e) => if !batch.failed(Error::file(&path, e)) {
+                return false;
+            }
+        }
+    }
+    entries.sort();
+
+    ancestors.push(canonical);
+    let mut carry_on = true;
+    for entry in entries {
+        let is_symlink = fs::symlink_metadata(&entry).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+        carry_on = if is_symlink && !opt.force {
+            batch.failed(Error::warning(entry, Warning::NotRegular))
+        }
+        else {
+            visit(entry, batch, ancestors)
+        };
+        if !carry_on {
+            break;
+        }
     }
+    ancestors.pop();
+    carry_on
 }
 
-fn dir (dir: ReadDir, opt: &mut Opt) -> Result<(), i8> {
-    let files: Vec<PathBuf> = dir.filter_map(|f|{
-        match f {
-            Ok(dir_entry) => {
-                match dir_entry.file_type() {
-                    Ok(t) => if t.is_file() {Some(dir_entry.path())} else {None},
-                    Err(_) => None
+/// Packs the directory `path` into one ZIP archive, or with `--tar` one tar archive compressed
+/// in `format`, named after it, next to it. The tree is walked as `dir` walks it, and the files
+/// in it are left in place. Returns whether the remaining files should still be processed.
+fn archive (path: PathBuf, batch: &mut Batch, ancestors: &mut Vec<PathBuf>) -> bool {
+    let opt = batch.opt;
+    let canonical = match fs::canonicalize(&path) {
+        Ok(canonical) => canonical,
+        Err(e) => return batch.failed(Error::file(path, e))
+    };
+    // "." and ".." are named after the directory they stand for
+    let (named, root) = match (path.file_name(), canonical.file_name()) {
+        (Some(name), _) => (path.clone(), name.to_string_lossy().into_owned()),
+        (None, Some(name)) => (canonical.clone(), name.to_string_lossy().into_owned()),
+        (None, None) => return batch.failed(Error::file(path, io::Error::new(ErrorKind::InvalidInput,
+            "cannot name an archive after the root directory")))
+    };
+    let mut entries = Vec::new();
+    if !collect(path.clone(), root.clone(), batch, ancestors, &mut entries) {
+        return false;
+    }
+    let ofname = if opt.tar {
+        named.with_file_name(format!("{}.tar.{}", root, opt.suffix))
+    }
+    else {
+        named.with_file_name(format!("{}.{}", root, opt.suffix))
+    };
+    let write = |out: &mut dyn Write| -> io::Result<()> {
+        if opt.tar {
+            let codec = opt.codecs.get(&opt.format).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput,
+                format!("unknown format {}", opt.format)))?;
+            return codec.encode(&mut TarStream::new(&entries, opt.time), out, None, opt);
+        }
+        let mut zip = ZipWriter::new(out, opt.level);
+        for (entry, name) in &entries {
+            // name the file in any error, as it isn't the operand the error is reported for
+            let add = |zip: &mut ZipWriter<&mut dyn Write>| -> io::Result<()> {
+                let meta = metadata(entry)?;
+                let mtime = meta.modified().ok().map(|m| util::Timespec::from(m).0).filter(|_| opt.time);
+                let mode = archive::unix_mode(&meta);
+                if meta.is_dir() {
+                    zip.add_dir(name, mtime, mode)
                 }
-            },
-            Err(_) => None
+                else {
+                    zip.add_file(name, mtime, mode, &mut BufReader::new(File::open(entry)?))
+                }
+            };
+            add(&mut zip).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", entry.display(), e)))?;
+        }
+        zip.finish()?;
+        Ok(())
+    };
+    let res = if opt.stdout {
+        let stdout = std::io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        write(&mut out).and_then(|_| out.flush())
+    }
+    else if metadata(&ofname).is_ok() && !opt.force {
+        print!("{}: {} already exists; do you wish to overwrite (y or n)? ",
+            constants::PROGRAM_NAME, ofname.display());
+        if util::yesno() {
+            to_file(&ofname, |out| write(out))
         }
-    }).collect();
-    return self::files(files, opt);
+        else {
+            println!("\tnot overwritten");
+            Ok(())
+        }
+    }
+    else {
+        to_file(&ofname, |out| write(out))
+    };
+    match res {
+        Ok(()) => true,
+        Err(e) => batch.failed(Error::file(path, e))
+    }
 }
 
-pub mod errors {
-    pub fn permission_denied_err_msg (fstr: &str, op: &str) {
-        eprintln!("{}: {}: permission denied on {}",
-                super::constants::PROGRAM_NAME, fstr, op);
+/// Walks the directory `path` as `dir` does, adding it and everything below it that can be
+/// archived to `entries`, each with its name in the archive. `name` is the name of `path`.
+fn collect (path: PathBuf, name: String, batch: &mut Batch, ancestors: &mut Vec<PathBuf>,
+    entries: &mut Vec<(PathBuf, String)>) -> bool
+{
+    let opt = batch.opt;
+    if ancestors.len() >= opt.max_depth {
+        return batch.failed(Error::warning(path, Warning::TooDeep));
     }
-
-    pub fn file_delete_err_msg (fstr: &str) {
-        eprintln!("{}: {}: unexpected error while deleting file.",
-            super::constants::PROGRAM_NAME, fstr);
+    let canonical = match fs::canonicalize(&path) {
+        Ok(canonical) => canonical,
+        Err(e) => return batch.failed(Error::file(path, e))
+    };
+    if ancestors.contains(&canonical) {
+        return batch.failed(Error::warning(path, Warning::Loop));
+    }
+    let dir = match read_dir(&path) {
+        Ok(dir) => dir,
+        Err(e) => return batch.failed(Error::file(path, e))
+    };
+    let mut children: Vec<PathBuf> = Vec::new();
+    for entry in dir {
+        match entry {
+            Ok(entry) => children.push(entry.path()),
+            Err(e) => if !batch.failed(Error::file(&path, e)) {
+                return false;
+            }
+        }
     }
+    children.sort();
+    entries.push((path, format!("{}/", name)));
 
-    pub fn tty_err_msg (decompress: bool) {
-        let readwrite = if decompress {
-            "read from"
+    ancestors.push(canonical);
+    let mut carry_on = true;
+    for child in children {
+        let child_name = format!("{}/{}", name, child.file_name().unwrap_or_default().to_string_lossy());
+        let is_symlink = fs::symlink_metadata(&child).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+        let meta = metadata(&child);
+        carry_on = if is_symlink && !opt.force {
+            batch.failed(Error::warning(child, Warning::NotRegular))
         }
-        else{
-            "written to"
-        };
-        let de = if decompress {
-            "de"
+        else if meta.as_ref().map(|m| m.is_dir()).unwrap_or(false) {
+            collect(child, child_name, batch, ancestors, entries)
+        }
+        else if meta.as_ref().map(|m| m.is_file()).unwrap_or(false) {
+            entries.push((child, child_name));
+            true
         }
         else {
-            ""
+            match meta {
+                Ok(_) => batch.failed(Error::warning(child, Warning::NotRegular)),
+                Err(e) => batch.failed(Error::file(child, e))
+            }
         };
-        eprintln!("{0}: compressed data not {1} a terminal. \
-		  Use -f to force {2}compression.\n\
-		  For help, type: {0} -h", super::constants::PROGRAM_NAME,
-            readwrite, de);
+        if !carry_on {
+            break;
+        }
     }
+    ancestors.pop();
+    carry_on
 }
 
 #[cfg(test)]
@@ -316,7 +737,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_check_for_stdin () {
-        assert!(check_for_stdin("-"));
-        assert_eq!(check_for_stdin("_"), false);
+        assert!(check_for_stdin(Path::new("-")));
+        assert!(!check_for_stdin(Path::new("_")));
     }
 }
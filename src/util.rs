@@ -1,16 +1,15 @@
 use std::fs::File;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::io::{ErrorKind, Write};
+use std::io::Write;
 use std::io;
-use crate::treat::errors;
 
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
-#[cfg(linux)]
-use std::os::linux::fs::MetadataExt;
 
-use crate::{EXIT_CODE, Opt, constants};
+use crate::constants;
+use crate::error::{Error, Warning};
+use crate::options::{Mode, Options};
 
 extern crate num;
 
@@ -19,27 +18,14 @@ use std::convert::TryInto;
 
 use num::Integer;
 
-/// Convenience struct created to encapsulate both the location of a directory
-/// as well as the directory itself
-pub struct WrappedDir<'a> {
-    pub path: &'a Path,
-    pub dir: fs::ReadDir
-}
-
 /// Convenience struct created to encapsulate both the location of a file
 /// as well as the file itself
-#[derive(Copy)]
+#[derive(Copy, Clone)]
 pub struct WrappedFile<'a, 'b> {
     pub path: &'a Path,
     pub file: &'b fs::File,
 }
 
-impl <'a, 'b> Clone for WrappedFile<'a, 'b> {
-    fn clone(&self) -> Self {
-        WrappedFile { path: self.path, file: self.file.clone()}
-    }
-}
-
 pub struct WorkData {
     pub orig_name: Option<String>,
     pub mtime: Option<Timespec>,
@@ -47,16 +33,15 @@ pub struct WorkData {
 }
 
 impl WorkData {
-    pub fn new (orig_name: Option<String>, time: Option<Timespec>, ofname: String, opt: &Opt) -> Self {
-        let mtime;
+    pub fn new (orig_name: Option<String>, time: Option<Timespec>, ofname: String, opt: &Options) -> Self {
         // we might be restoring mtime on gunzipping, but we won't be using the mtime from work
         // data, we'll be using the mtime, if any, stored in the compressed file
-        if opt.decompress {
-            mtime = None
+        let mtime = if opt.decompressing() {
+            None
         }
         else{
-            mtime = time
-        }
+            time
+        };
         WorkData {orig_name, mtime, ofname}
     }
 }
@@ -64,19 +49,19 @@ impl WorkData {
 /// Timespec is (i64, i32) because it must represent negative numbers on no timestamp being
 /// recoverable
 #[derive(Debug, Copy, Clone)]
-pub struct Timespec (pub i64, pub i32);
+pub struct Timespec (pub i64, #[allow(dead_code)] pub i32);
 
 impl From<Duration> for Timespec  {
     fn from (d: Duration) -> Self {
         let mut secs;
         let nsecs;
-        if d.as_secs() > i32::max_value().try_into().unwrap() {
-            secs = i64::max_value ();
-            nsecs = i32::max_value ();
+        if d.as_secs() > i32::MAX.try_into().unwrap() {
+            secs = i64::MAX;
+            nsecs = i32::MAX;
         }
         else{
             secs = d.as_secs().try_into().unwrap();
-            if d.subsec_nanos() > i32::max_value().try_into().unwrap() {
+            if d.subsec_nanos() > i32::MAX.try_into().unwrap() {
                 secs += 1;
                 nsecs = 0;
             }
@@ -92,22 +77,22 @@ impl From<u32> for Timespec {
     fn from (secs: u32) -> Self {
         let secs: i64 = secs.into();
         let nanosecs: i32 = 0;
-        return Timespec (secs, nanosecs);
+        Timespec (secs, nanosecs)
     }
 }
 
 impl TryInto<u32> for Timespec{
     type Error = std::num::TryFromIntError;
     fn try_into (self) -> Result<u32, Self::Error> {
-        return self.0.try_into();
+        self.0.try_into()
     }
 }
 
 impl From<SystemTime> for Timespec {
     fn from (s: SystemTime) -> Self {
         match s.duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(dur) => return Timespec::from(dur),
-            Err(_) => return Timespec (-1, -1)
+            Ok(dur) => Timespec::from(dur),
+            Err(_) => Timespec (-1, -1)
         }
     }
 }
@@ -115,181 +100,150 @@ impl From<SystemTime> for Timespec {
 static KNOWN_SUFFIXES: [&str; 8] = [constants::DEFAULT_SUFFIX, "z", "taz", "tgz", "-gz", "-z", "_z", "zip"];
 
 /// checks if anded together, the two types yield a non-zero value
-pub fn bit_set<T: Integer + std::ops::BitAnd + PartialEq> (b1: T, b2: T) -> bool
+pub fn bit_set<T> (b1: T, b2: T) -> bool
 where
-    T: std::ops::BitAnd<Output = T>
+    T: Integer + std::ops::BitAnd<Output = T>
 {
     let res: T = b1 & b2;
-    return res != num::zero();
-}
-
-// format of warn! = (eprintln stuff; exit_code)
-#[macro_export]
-macro_rules! warn {
-    ($($arg:expr),*; $exit_code:expr) => {{
-        unsafe {EXIT_CODE = $exit_code;}
-        eprintln!($($arg),*);
-    }}
+    res != num::zero()
 }
 
-pub fn check_file_modes (f: &WrappedFile, opt: &mut Opt) -> std::io::Result<bool> {
-    if !opt.stdout {
-        let stat = f.file.metadata()?;
-        let file_type = stat.file_type();
-        if !file_type.is_file() {
-            warn!("{}: {} is not a directory or a regular file - ignored",
-                constants::PROGRAM_NAME, f.path.to_str().unwrap(); constants::WARNING);
-            return Ok(false)
+/// Checks that `f` is a regular file that may be replaced by its compressed or decompressed
+/// version, returning the reason to leave it alone otherwise
+pub fn check_file_modes (f: &WrappedFile, opt: &Options) -> Result<(), Error> {
+    if opt.writes_files() {
+        let stat = f.file.metadata().map_err(|e| Error::file(f.path, e))?;
+        if !stat.file_type().is_file() {
+            return Err(Error::warning(f.path, Warning::NotRegular));
         }
-        let file_checks = (!cfg!(unix) && !cfg!(linux)) || file_checks(f, opt)?;
-        if file_checks {
-            return Ok(true);
+        if cfg!(unix) {
+            file_checks(f, &stat, opt)?;
         }
-        return Ok(false);
     }
-    Ok(true)
+    Ok(())
 }
 
 // TODO: Support linux
-fn file_checks (f: &WrappedFile, opt: &Opt) -> std::io::Result<bool> {
-    let meta = f.file.metadata()?;
+fn file_checks (f: &WrappedFile, meta: &fs::Metadata, opt: &Options) -> Result<(), Error> {
     let mode = meta.mode();
-
-    let path_str = f.path.to_str().unwrap();
-
     if bit_set (mode, constants::S_ISUID) {
-        warn!("{}: {} is set-user-ID on execution - ignored",
-            constants::PROGRAM_NAME, path_str; constants::WARNING);
-        return Ok(false);
+        return Err(Error::warning(f.path, Warning::SetUid));
     }
     if bit_set (mode, constants::S_ISGID) {
-        warn!("{}: {} is set-group-ID on execution - ignored",
-            constants::PROGRAM_NAME, path_str; constants::WARNING);
-        return Ok(false);
+        return Err(Error::warning(f.path, Warning::SetGid));
     }
     if !opt.force {
         if bit_set (mode, constants::S_ISVTX) {
-            warn!("{}: {} has the sticky bit set - file ignored",
-                constants::PROGRAM_NAME, path_str; constants::WARNING);
-            return Ok(false);
+            return Err(Error::warning(f.path, Warning::Sticky));
         }
         let nlinks = meta.nlink();
         if nlinks > 1 {
-            warn!("{}: {} has {} other links -- unchanged",
-                constants::PROGRAM_NAME, path_str, nlinks; constants::WARNING);
-            return Ok(false);
+            return Err(Error::warning(f.path, Warning::Links(nlinks - 1)));
         }
     }
-    Ok(true)
+    Ok(())
 }
 
 pub fn get_input_time (stat: &fs::Metadata) -> std::io::Result<Timespec> {
     if stat.file_type().is_file() {
-        return Ok(Timespec::from (stat.modified().unwrap()));
+        return Ok(Timespec::from (stat.modified()?));
     }
-    return Err(std::io::Error::new(std::io::ErrorKind::Other, "Input given is not a file"));
+    Err(std::io::Error::other("Input given is not a file"))
 }
 
 pub fn _get_input_size (stat: &fs::Metadata) -> u64 {
-    return stat.len();
+    stat.len()
 }
 
-// A result of Error indicates that the file must be skipped
-pub fn make_ofname (f: &PathBuf, opt: &Opt) -> Result<Box<PathBuf>, ()> {
-    match get_suffix (f, opt) {
-        Some(suffix) => {
-            let path_str = f.to_str().unwrap();
-            if opt.decompress {
-                // strip known extension off of end of file
-                let res: Vec<&str> = path_str.rsplit('.').skip(1).collect();
-                let res: Vec<&str> = res.iter().rev().map(|&x| x).collect();
-                let res: String = res.join(".");
-                Ok(Box::new(PathBuf::from(res)))
-            }
-            else {
-                // add known extension at end of file
-                let res = format!("{}.{}", path_str, suffix);
-                Ok(Box::new(PathBuf::from(res)))
-            }
-        },
-        None => Err(())
+/// The name of the file `f` compresses or decompresses to. `None` means the file must be
+/// skipped without comment.
+pub fn make_ofname (f: &Path, opt: &Options) -> Result<Option<PathBuf>, Error> {
+    let suffix = match get_suffix (f, opt) {
+        Ok(Some(suffix)) => suffix,
+        // skipped, or read anyway since no output file needs naming after it
+        Err(Error::Warning { warning: Warning::UnknownSuffix, .. }) | Ok(None)
+            if opt.decompressing() && reads_any_suffix(opt) => return Ok(Some(f.to_path_buf())),
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    let path_str = f.to_string_lossy();
+    if opt.decompressing() {
+        // strip known extension off of end of file
+        let res: Vec<&str> = path_str.rsplit('.').skip(1).collect();
+        let res: Vec<&str> = res.iter().rev().copied().collect();
+        let mut res: String = res.join(".");
+        // .tgz and .taz stand for .tar.gz and .tar.Z, as in gzip's make_ofname
+        if ["tgz", "taz"].contains(&suffix.to_lowercase().as_str()) {
+            res.push_str(".tar");
+        }
+        Ok(Some(PathBuf::from(res)))
+    }
+    else {
+        // add known extension at end of file
+        let res = format!("{}.{}", path_str, suffix);
+        Ok(Some(PathBuf::from(res)))
     }
 }
 
-/// Returns the very last portion of the filename (after the last '.'), or None if there was an error
-fn get_suffix (p: &PathBuf, opt: &Opt) -> Option<String> {
-    if opt.decompress {
+/// Returns the very last portion of the filename (after the last '.'), or the reason the file
+/// can't be processed
+fn get_suffix (p: &Path, opt: &Options) -> Result<Option<String>, Error> {
+    let extension = p.extension().map(|os_str| os_str.to_string_lossy());
+    if opt.decompressing() {
         // if the suffix given through the CLI is empty, this means that we MUST try to decompress
         // with whatever suffix is at the end of the file (if any).
-        match p.extension() {
-            Some(os_str) => {
-                let suffix = strip_leading_dot(os_str.to_str().unwrap());
-                if opt.suffix.is_empty () || suffix_known(suffix) {
-                    return Some(String::from(suffix));
+        match extension {
+            Some(ext) => {
+                let suffix = strip_leading_dot(&ext);
+                if opt.suffix.is_empty () || suffix_known(suffix) || suffix == opt.suffix {
+                    return Ok(Some(String::from(suffix)));
                 }
-                unknown_suffix_warning(get_file_name(p), opt);
-                return None;
+                skip(p, Warning::UnknownSuffix, opt)
             },
             None => {
                 if opt.suffix.is_empty () {
-                    return Some(String::from (""));
+                    return Ok(Some(String::from ("")));
                 }
-                unknown_suffix_warning(get_file_name(p), opt);
-                return None;
+                skip(p, Warning::UnknownSuffix, opt)
             }
         }
     }
     else {
-        let has_compression_suffix = match p.extension() {
-            Some(os_str) => {
-                let suffix = strip_leading_dot(os_str.to_str().unwrap());
-                suffix_known (suffix)
+        if let Some(ext) = extension {
+            if suffix_known(strip_leading_dot(&ext)) && !opt.force {
+                return skip(p, Warning::HasSuffix(ext.into_owned()), opt);
             }
-            None => false
-        };
-        if has_compression_suffix && !opt.force {
-            let suffix = p.extension().unwrap().to_str().unwrap();
-            let file_name = get_file_name(p);
-            eprintln!("{}: {} already has .{} suffix -- unchanged", constants::PROGRAM_NAME,
-                file_name, suffix);
-            return None;
         }
-        return Some(String::from(&opt.suffix));
+        Ok(Some(String::from(&opt.suffix)))
     }
 }
 
-fn suffix_known (suffix: &str) -> bool {
-    return KNOWN_SUFFIXES.contains (&suffix);
+/// Whether compressed data is read whatever its suffix, as gzip does when no output file is
+/// named after the input: with --stdout, and with --test and --list unless recursing
+fn reads_any_suffix (opt: &Options) -> bool {
+    match opt.mode {
+        Mode::Decompress => opt.stdout,
+        Mode::Test | Mode::List | Mode::Index => !opt.recursive,
+        Mode::Compress => false
+    }
 }
 
-pub fn file_open (fpath: &PathBuf) -> std::io::Result<File> {
-    let fstr = fpath.to_str().unwrap();
-    match File::open(fpath) {
-        Ok(file) => Ok(file),
-        Err(e) => {
-            match e.kind() {
-                ErrorKind::NotFound => eprintln!("{}: {}: No such file or directory", constants::PROGRAM_NAME, fstr),
-                ErrorKind::PermissionDenied => eprintln!("{}: {}: permission denied",
-                    constants::PROGRAM_NAME, fstr),
-                _ => errors::permission_denied_err_msg(fstr, "open")
-            }
-            return Err(e);
-        }
-    }
+/// Whether `suffix` is one gzip knows, in any case, so that compress's .Z is known too
+fn suffix_known (suffix: &str) -> bool {
+    KNOWN_SUFFIXES.contains (&suffix.to_lowercase().as_str())
 }
 
-fn get_file_name (p: &PathBuf) -> &str {
-    match p.file_name() {
-        Some(name) => name.to_str().unwrap(),
-        None => ".."
-    }
+pub fn file_open (fpath: &Path) -> Result<File, Error> {
+    File::open(fpath).map_err(|e| Error::file(fpath, e))
 }
 
-fn unknown_suffix_warning (filename: &str, opt: &Opt) {
-    if (opt.verbose > 0) || (!opt.recursive && !opt.quiet) {
-        eprintln!("{}: {}: unknown suffix -- ignored", constants::PROGRAM_NAME,
-            filename);
+/// Skips a file because of its suffix. When recursing this happens silently unless `--verbose`
+/// is given, as in gzip, since a tree usually holds plenty of files of the wrong kind.
+fn skip (p: &Path, warning: Warning, opt: &Options) -> Result<Option<String>, Error> {
+    if (opt.verbose > 0) || !opt.recursive {
+        return Err(Error::warning(p, warning));
     }
+    Ok(None)
 }
 
 pub fn strip_leading_dot (suff: &str) -> &str {
@@ -298,7 +252,7 @@ pub fn strip_leading_dot (suff: &str) -> &str {
             return &suff [1..];
         }
     }
-    return suff;
+    suff
 }
 
 pub fn yesno () -> bool {
@@ -307,7 +261,7 @@ pub fn yesno () -> bool {
     match io::stdin().read_line(&mut input) {
         Ok(_) => {
             match input.chars().next() {
-                Some(c) => (c == 'y' || c == 'Y'),
+                Some(c) => c == 'y' || c == 'Y' ,
                 None => false
             }
         },
@@ -315,44 +269,6 @@ pub fn yesno () -> bool {
     }
 }
 
-/// 'from' must store its bytes in little endian ordering for its representations
-/// to be correct
-pub fn shift_left (num_bytes: usize, from: &[u8]) -> u32 {
-    if num_bytes > from.len() {
-        eprintln!("shift_left: value discarded due to range mismatch");
-        return 0;
-    }
-    let mut res: u32 = 0;
-    for i in 0..num_bytes {
-        let byte: u32 = from[i].into();
-        res = res | (byte << (i * 8));
-    }
-    return res;
-}
-
-pub fn str_seek (start: usize, buf: &[u8]) -> Result<(&str, usize), ()> {
-    match buf.iter().skip(start).position(|&x| x == 0) {
-        Some(pos) => {
-            let slice: &[u8] = &buf[start..(start + pos+1)];
-            match std::str::from_utf8(slice) {
-                Ok(s) => Ok((s, pos+1)),
-                Err(_) => Err(())
-            }
-        },
-        None => Err(())
-    }
-}
-
-pub fn get_str_if_set  (hpos: &mut usize, buf_slice: & [u8], cond: bool) -> Option<String> {
-    if cond {
-        if let Ok((s, pos)) = str_seek(*hpos, buf_slice) {
-            *hpos += pos;
-            return Some(String::from(s));
-        }
-    }
-    return None;
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -377,5 +293,9 @@ mod tests {
         assert!(suffix_known("gz"));
         assert!(suffix_known("-z"));
         assert!(!suffix_known("tz"));
+        assert!(suffix_known("Z"));
+        // only stripped as the suffix of --format zlib or raw, never refused when compressing
+        assert!(!suffix_known("raw"));
+        assert!(!suffix_known("zlib"));
     }
 }
@@ -1,3 +1,6 @@
+// argument lists are passed as borrowed slices throughout
+#![allow(clippy::needless_borrows_for_generic_args, clippy::unnecessary_fallible_conversions)]
+
 use std::convert::TryInto;
 use std::io::Read;
 use std::io::Write;
@@ -6,7 +9,7 @@ use assert_cmd::prelude::*; // Add methods on commands
 use predicates::prelude::*; // Used for writing assertions
 use std::fs::{File, create_dir, remove_file};
 use remove_dir_all::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 // THESE TESTS ARE ONLY GUARANTEED TO WORK ON UNIX. THEY HAVE NOT BEEN PORTED TO WINDOWS.
 
@@ -242,7 +245,8 @@ fn list() -> Result<(), Box<dyn std::error::Error>> {
     let f1_compr_len = File::open("list1.gz")?.metadata()?.len();
     let f2_compr_len = File::open("list2.gz")?.metadata()?.len();
     let total_compr_len = format!("{}", f1_compr_len + f2_compr_len);
-    let header_size = 18.0;
+    // as in gzip, the ratio leaves out the header, stored name included, and the trailer
+    let header_size = 18.0 + "list1\0".len() as f64;
 
     let f1_compr_ratio = format!("{:.1}", ((to_float(f1_uncompr_len) - (to_float(f1_compr_len)-header_size))*100.0)/to_float(f1_uncompr_len));
 
@@ -368,3 +372,835 @@ fn test() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// A fresh directory for the files of the test `name`, under the system's temp dir
+fn scratch (name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("rstzip-{}-{}", name, std::process::id()));
+    if dir.exists() {
+        remove_dir_all(&dir)?;
+    }
+    create_dir(&dir)?;
+    Ok(dir)
+}
+
+/// rstzip, run in `dir`
+fn rstzip_in (dir: &Path) -> Result<Command, Box<dyn std::error::Error>> {
+    let mut rstzip = Command::main_binary()?;
+    rstzip.current_dir(dir);
+    Ok(rstzip)
+}
+
+#[test]
+fn parallel() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("parallel")?;
+    let data: Vec<u8> = (0..600_000u32).map(|i| ((i * 31) % 241) as u8 ^ (i >> 12) as u8).collect();
+    File::create(dir.join("parallel1"))?.write_all(&data)?;
+
+    let mut outputs: Vec<Vec<u8>> = Vec::new();
+    for threads in &["1", "4"] {
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["-j", threads, "-k", "-f", "--", "parallel1"]);
+        rstzip.assert().success();
+        let mut compressed: Vec<u8> = Vec::new();
+        File::open(dir.join("parallel1.gz"))?.read_to_end(&mut compressed)?;
+        outputs.push(compressed);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+
+    remove_file(dir.join("parallel1"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "parallel1.gz"]);
+    rstzip.assert().success();
+    let mut decompressed: Vec<u8> = Vec::new();
+    File::open(dir.join("parallel1"))?.read_to_end(&mut decompressed)?;
+    assert_eq!(decompressed, data);
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn rsyncable() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("rsyncable")?;
+    let words = ["alpha ", "bravo ", "charlie ", "delta ", "echo ", "foxtrot ", "golf ", "hotel\n"];
+    let mut seed: u32 = 12345;
+    let mut data: Vec<u8> = Vec::new();
+    while data.len() < 1_000_000 {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        data.extend_from_slice(words[(seed >> 16) as usize % words.len()].as_bytes());
+    }
+
+    let compress = |data: &[u8]| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        File::create(dir.join("rsyncable1"))?.write_all(data)?;
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["--rsyncable", "-n", "-c", "--", "rsyncable1"]);
+        let out = rstzip.output()?;
+        assert!(out.status.success());
+        Ok(out.stdout)
+    };
+    let before = compress(&data)?;
+    data[500_000] ^= 0x20;
+    let after = compress(&data)?;
+
+    // the output must still be standard gzip
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "rsyncable1.gz"]);
+    File::create(dir.join("rsyncable1.gz"))?.write_all(&after)?;
+    rstzip.assert().success().stdout(predicate::eq(data.as_slice()));
+
+    // the trailer's CRC always changes, so only compare the deflate bodies
+    let (before, after) = (&before[..before.len() - 8], &after[..after.len() - 8]);
+    let prefix = before.iter().zip(after.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = before.iter().rev().zip(after.iter().rev()).take_while(|(a, b)| a == b).count();
+    let changed = after.len().saturating_sub(prefix + suffix);
+    assert!(changed < after.len() / 20, "{} of {} bytes changed", changed, after.len());
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn bad_operands_dont_stop_the_batch () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("bad_operands_dont_stop_the_batch")?;
+    let msg = b"Mr. Watson, come here. I want to see you. Mr. Watson, come here.";
+    File::create(dir.join("batch1"))?.write_all(msg)?;
+    File::create(dir.join("batch3"))?.write_all(msg)?;
+
+    // compress: the missing file in the middle is reported, the files around it are compressed
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--", "batch1", "batch2", "batch3"]);
+    rstzip.assert()
+        .code(1)
+        .stderr(predicate::str::contains("batch2: No such file or directory"));
+    assert!(dir.join("batch1.gz").exists());
+    assert!(dir.join("batch3.gz").exists());
+
+    // test and list: same again, with a file that isn't compressed at all in the middle
+    File::create(dir.join("batch2.gz"))?.write_all(msg)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--verbose", "1", "--", "batch1.gz", "batch2.gz", "batch3.gz"]);
+    rstzip.assert()
+        .code(1)
+        .stdout(predicate::str::contains("OK").count(2))
+        .stderr(predicate::str::contains("batch2.gz"));
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--", "batch1.gz", "missing.gz", "batch3.gz"]);
+    rstzip.assert()
+        .code(1)
+        .stdout(predicate::str::contains("batch1").and(predicate::str::contains("batch3")))
+        .stderr(predicate::str::contains("missing.gz: No such file or directory"));
+
+    // decompress: the bad file is left alone, the good ones are decompressed
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "batch1.gz", "batch2.gz", "batch3.gz"]);
+    rstzip.assert()
+        .code(1)
+        .stderr(predicate::str::contains("batch2.gz"));
+    for name in &["batch1", "batch3"] {
+        let mut decompressed: Vec<u8> = Vec::new();
+        File::open(dir.join(name))?.read_to_end(&mut decompressed)?;
+        assert_eq!(&decompressed[..], &msg[..]);
+        assert!(!dir.join(format!("{}.gz", name)).exists());
+    }
+    assert!(dir.join("batch2.gz").exists());
+    assert!(!dir.join("batch2").exists());
+
+    // a warning alone gives status 2, but an error anywhere in the batch gives 1
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-k", "--", "batch2.gz", "batch1", "batch4"]);
+    rstzip.assert()
+        .code(1)
+        .stderr(predicate::str::contains("already has .gz suffix"));
+    assert!(dir.join("batch1.gz").exists());
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn recursive_walk () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("recursive_walk")?;
+    std::fs::create_dir_all(dir.join("walk1/a/b/c"))?;
+    File::create(dir.join("walk1/top"))?.write_all(b"at the top of the tree")?;
+    File::create(dir.join("walk1/a/b/c/bottom"))?.write_all(b"at the bottom of the tree")?;
+    File::create(dir.join("walk1/a/already.gz"))?.write_all(b"not compressed, but named as if it were")?;
+
+    // files that already have a compressed suffix are skipped silently while recursing
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-r", "--", "walk1"]);
+    rstzip.assert().success().stderr(predicate::str::is_empty());
+    assert!(dir.join("walk1/top.gz").exists());
+    assert!(dir.join("walk1/a/b/c/bottom.gz").exists());
+    assert!(dir.join("walk1/a/already.gz").exists());
+
+    // and so are files without one while decompressing
+    File::create(dir.join("walk1/a/b/plain"))?.write_all(b"never compressed")?;
+    remove_file(dir.join("walk1/a/already.gz"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dr", "--", "walk1"]);
+    rstzip.assert().success().stderr(predicate::str::is_empty());
+    assert!(dir.join("walk1/top").exists());
+    assert!(dir.join("walk1/a/b/c/bottom").exists());
+    assert!(dir.join("walk1/a/b/plain").exists());
+
+    // symbolic links are only followed with --force, and never round a loop
+    std::os::unix::fs::symlink("../..", dir.join("walk1/a/b/up"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-r", "--", "walk1"]);
+    rstzip.assert()
+        .code(2)
+        .stderr(predicate::str::contains("walk1/a/b/up is not a directory or a regular file - ignored"));
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dr", "-f", "--", "walk1"]);
+    rstzip.assert()
+        .code(2)
+        .stderr(predicate::str::contains("walk1/a/b/up: directory loop -- ignored"));
+    assert!(dir.join("walk1/a/b/c/bottom").exists());
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_checks_every_member () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("test_checks_every_member")?;
+    let msg: Vec<u8> = b"Ask not what your country can do for you. ".iter().cycle().take(50_000).copied().collect();
+    File::create(dir.join("members1"))?.write_all(&msg)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--", "members1"]);
+    rstzip.assert().success();
+    let mut member: Vec<u8> = Vec::new();
+    File::open(dir.join("members1.gz"))?.read_to_end(&mut member)?;
+
+    // nothing is written to stdout unless asked for, whatever the size of the data
+    let two_members = [&member[..], &member[..]].concat();
+    File::create(dir.join("members1.gz"))?.write_all(&two_members)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "members1.gz"]);
+    rstzip.assert().success().stdout(predicate::str::is_empty());
+
+    // a bad CRC in the second member is caught
+    let mut bad_crc = two_members.clone();
+    let crc_at = bad_crc.len() - 8;
+    bad_crc[crc_at] ^= 1;
+    File::create(dir.join("members2.gz"))?.write_all(&bad_crc)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--verbose", "1", "--", "members1.gz", "members2.gz"]);
+    rstzip.assert()
+        .code(1)
+        .stdout(predicate::str::contains("members1.gz:\t OK").and(predicate::str::contains("members2.gz:\t FAILED")))
+        .stderr(predicate::str::contains("members2.gz: invalid compressed data--crc error"));
+
+    // and so is a bad length in the first
+    let mut bad_length = two_members.clone();
+    bad_length[member.len() - 4] ^= 1;
+    File::create(dir.join("members2.gz"))?.write_all(&bad_length)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "members2.gz"]);
+    rstzip.assert()
+        .code(1)
+        .stderr(predicate::str::contains("members2.gz: invalid compressed data--length error"));
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn trailing_garbage () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("trailing_garbage")?;
+    let compressed = rstzip::compress(&b"data, then junk"[..], Vec::new(), 6)?;
+    File::create(dir.join("garbage1.gz"))?.write_all(&[&compressed[..], &b"junk"[..]].concat())?;
+    for flags in &[&["-t"][..], &["-dk"][..]] {
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(*flags).args(&["--", "garbage1.gz"]);
+        rstzip.assert()
+            .code(2)
+            .stderr(predicate::str::contains("garbage1.gz: decompression OK, trailing garbage ignored"));
+    }
+    // the data decompressed is kept
+    assert_eq!(std::fs::read(dir.join("garbage1"))?, b"data, then junk");
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn header_crc () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("header_crc")?;
+    let msg = b"One giant leap";
+    File::create(dir.join("hcrc1"))?.write_all(msg)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--header-crc", "--", "hcrc1"]);
+    rstzip.assert().success();
+
+    let mut compressed: Vec<u8> = Vec::new();
+    File::open(dir.join("hcrc1.gz"))?.read_to_end(&mut compressed)?;
+    assert_eq!(compressed[3] & 0b0000_0010, 0b0000_0010);
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "hcrc1.gz"]);
+    rstzip.assert().success();
+
+    // a damaged stored name is caught by decompress, test and list alike
+    compressed[11] ^= 0x20;
+    File::create(dir.join("hcrc1.gz"))?.write_all(&compressed)?;
+    for flag in &["-d", "-t", "-l"] {
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&[flag, "--", "hcrc1.gz"]);
+        rstzip.assert()
+            .code(1)
+            .stderr(predicate::str::contains("hcrc1.gz: header checksum"));
+    }
+    assert!(!dir.join("hcrc1").exists());
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn extra_subfields () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("extra_subfields")?;
+    let msg = b"Houston, Tranquility Base here. The Eagle has landed.";
+    File::create(dir.join("extra1"))?.write_all(msg)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--extra", "AP=0102ff", "--extra", "BC=", "--", "extra1"]);
+    rstzip.assert().success();
+
+    let mut input = File::open(dir.join("extra1.gz"))?;
+    let gz = rstzip::GzFile::read_header(&mut input)?;
+    assert_eq!(gz.subfields()?, vec![rstzip::Subfield::new(*b"AP", vec![1, 2, 0xff]),
+        rstzip::Subfield::new(*b"BC", vec![])]);
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "extra1.gz"]);
+    rstzip.assert()
+        .success()
+        .stdout(predicate::str::contains("extra AP=0102ff\n").and(predicate::str::contains("extra BC=\n")));
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--verbose", "1", "--", "extra1.gz"]);
+    rstzip.assert()
+        .success()
+        .stderr(predicate::str::contains("extra1.gz: extra field AP=0102ff"));
+    let mut decompressed = Vec::new();
+    File::open(dir.join("extra1"))?.read_to_end(&mut decompressed)?;
+    assert_eq!(&decompressed[..], &msg[..]);
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--extra", "A=00", "--", "extra1"]);
+    rstzip.assert().failure();
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn comment () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("comment")?;
+    File::create(dir.join("comment1"))?.write_all(b"That's one small step for man")?;
+    File::create(dir.join("comment1.txt"))?.write_all(b"exported by the nightly job\n")?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--comment-file", "comment1.txt", "--", "comment1"]);
+    rstzip.assert().success();
+
+    let mut input = File::open(dir.join("comment1.gz"))?;
+    let gz = rstzip::GzFile::read_header(&mut input)?;
+    assert_eq!(gz.comment(), Some("exported by the nightly job"));
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "comment1.gz"]);
+    rstzip.assert()
+        .success()
+        .stdout(predicate::str::contains("comment: exported by the nightly job\n"));
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--verbose", "1", "--", "comment1.gz"]);
+    rstzip.assert()
+        .success()
+        .stdout(predicate::str::contains("comment1.gz:\t OK\n\t comment: exported by the nightly job\n"));
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "comment1.gz"]);
+    rstzip.assert().success();
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--comment", "provenance: archive", "--", "comment1"]);
+    rstzip.assert().success();
+    let mut input = File::open(dir.join("comment1.gz"))?;
+    assert_eq!(rstzip::GzFile::read_header(&mut input)?.comment(), Some("provenance: archive"));
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn detect_format_by_magic () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("detect_format_by_magic")?;
+    File::create(dir.join("magic1.gz"))?.write_all(b"plain text, not compressed\n")?;
+    for flags in &[&["-d"][..], &["-t"][..], &["-d", "-c"][..]] {
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(*flags).args(&["--", "magic1.gz"]);
+        rstzip.assert()
+            .code(1)
+            .stderr("rstzip: magic1.gz: not in gzip format\n");
+    }
+    assert!(!dir.join("magic1").exists());
+    // like gzip -cdf, data in no known format is copied through
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-cdf", "--", "magic1.gz"]);
+    rstzip.assert()
+        .success()
+        .stdout("plain text, not compressed\n");
+
+    // the suffix doesn't matter when nothing is written to a file named after the input
+    let compressed = rstzip::compress(&b"gzip data under another name"[..], Vec::new(), 6)?;
+    File::create(dir.join("magic2.bin"))?.write_all(&compressed)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "magic2.bin"]);
+    rstzip.assert()
+        .success()
+        .stdout("gzip data under another name");
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "magic2.bin"]);
+    rstzip.assert().success();
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "magic2.bin"]);
+    rstzip.assert()
+        .code(2)
+        .stderr(predicate::str::contains("magic2.bin: unknown suffix -- ignored"));
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+/// A ZIP archive holding `entries` uncompressed
+fn stored_zip (entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in entries {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let mut fields = b"\x00\x00\x00\x00\x00\x00\x21\x00".to_vec();
+        fields.extend_from_slice(&crc.sum().to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&[0, 0]);
+
+        directory.extend_from_slice(b"PK\x01\x02\x0a\x00\x0a\x00");
+        directory.extend_from_slice(&fields);
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&(zip.len() as u32).to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+
+        zip.extend_from_slice(b"PK\x03\x04\x0a\x00");
+        zip.extend_from_slice(&fields);
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(data);
+    }
+    let start = zip.len() as u32;
+    zip.extend_from_slice(&directory);
+    zip.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+    zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&start.to_le_bytes());
+    zip.extend_from_slice(&[0, 0]);
+    zip
+}
+
+#[test]
+fn zip_first_entry () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("zip_first_entry")?;
+    File::create(dir.join("zip1.zip"))?.write_all(&stored_zip(&[("zip1", b"the only entry")]))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "zip1.zip"]);
+    rstzip.assert().success();
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "zip1.zip"]);
+    rstzip.assert()
+        .success()
+        .stdout("the only entry");
+
+    File::create(dir.join("zip2.zip"))?.write_all(&stored_zip(&[("zip2", b"first"), ("other", b"second")]))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "zip2.zip"]);
+    rstzip.assert()
+        .code(2)
+        .stderr("rstzip: zip2.zip has more than one entry--rest ignored\n");
+    let mut decompressed = Vec::new();
+    File::open(dir.join("zip2"))?.read_to_end(&mut decompressed)?;
+    assert_eq!(decompressed, b"first");
+    assert!(!dir.join("zip2.zip").exists());
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn zip_archive_tree () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("zip_archive_tree")?;
+    create_dir(dir.join("ziptree"))?;
+    create_dir(dir.join("ziptree/sub"))?;
+    File::create(dir.join("ziptree/a.txt"))?.write_all(b"top level")?;
+    File::create(dir.join("ziptree/sub/b.txt"))?.write_all(&[b'b'; 1000])?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--format", "zip", "-r", "--", "ziptree"]);
+    rstzip.assert().success();
+    // the tree is left in place
+    assert!(dir.join("ziptree/a.txt").exists());
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--", "ziptree.zip"]);
+    rstzip.assert()
+        .success()
+        .stdout(predicate::str::contains("ziptree/sub/b.txt")
+            .and(predicate::str::contains("ziptree/a.txt"))
+            .and(predicate::str::contains("(totals)")));
+
+    remove_dir_all(dir.join("ziptree"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--format", "zip", "--", "ziptree.zip"]);
+    rstzip.assert().success();
+    let mut extracted = Vec::new();
+    File::open(dir.join("ziptree/sub/b.txt"))?.read_to_end(&mut extracted)?;
+    assert_eq!(extracted, vec![b'b'; 1000]);
+    assert!(!dir.join("ziptree.zip").exists());
+
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn zip_archive_unsafe_path () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("zip_archive_unsafe_path")?;
+    File::create(dir.join("zipevil.zip"))?.write_all(&stored_zip(&[("fine", b"fine"), ("../zipevil", b"escaped")]))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--format", "zip", "--", "zipevil.zip"]);
+    rstzip.assert()
+        .code(1)
+        .stderr(predicate::str::contains("unsafe path"));
+    assert!(!dir.join("fine").exists());
+    assert!(dir.join("zipevil.zip").exists());
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn lzw_compress () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("lzw_compress")?;
+    // printf TOBEORNOTTOBEORTOBEORNOT | compress
+    let compressed = [0x1f, 0x9d, 0x90, 0x54, 0x9e, 0x08, 0x29, 0xf2, 0x44, 0x8a, 0x93, 0x27, 0x54, 0x02,
+        0x0e, 0x2c, 0xa8, 0x90, 0xa0, 0x41, 0x84];
+    File::create(dir.join("lzw1.Z"))?.write_all(&compressed)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "lzw1.Z"]);
+    rstzip.assert().success().stdout("TOBEORNOTTOBEORTOBEORNOT");
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "lzw1.Z"]);
+    rstzip.assert().success();
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "lzw1.Z"]);
+    rstzip.assert().success().stdout(predicate::str::contains("compr").and(predicate::str::contains("??")));
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "lzw1.Z"]);
+    rstzip.assert().success();
+    assert!(!dir.join("lzw1.Z").exists());
+    assert_eq!(std::fs::read(dir.join("lzw1"))?, b"TOBEORNOTTOBEORTOBEORNOT");
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn pack_and_lzh () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("pack_and_lzh")?;
+    // "abracadabra abracadabra", packed and compressed with LZH
+    let packed = [0x1f, 0x1e, 0x00, 0x00, 0x00, 0x17, 0x03, 0x00, 0x01, 0x04, 0x20, 0x61, 0x62, 0x63, 0x64,
+        0x72, 0x06, 0x04, 0x18, 0x30, 0x60, 0xc0, 0x83, 0x06, 0x0a];
+    let lzh = [0x1f, 0xa0, 0x00, 0x0a, 0x30, 0x28, 0x01, 0x42, 0x60, 0x67, 0x0b, 0x3f, 0xd5, 0xc7, 0xac, 0x7a,
+        0x80, 0x09, 0x2a, 0x96, 0x66, 0x43, 0xd8, 0x00, 0x00];
+    File::create(dir.join("pack1.z"))?.write_all(&packed)?;
+    File::create(dir.join("lzh1.bin"))?.write_all(&lzh)?;
+    for (file, method) in &[("pack1.z", "pack"), ("lzh1.bin", "lzh")] {
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["-dc", "--", file]);
+        rstzip.assert().success().stdout("abracadabra abracadabra");
+
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["-t", "--", file]);
+        rstzip.assert().success();
+
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["-l", "--verbose", "1", "--", file]);
+        rstzip.assert().success().stdout(predicate::str::contains(format!("\n{} ", method)));
+    }
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "pack1.z"]);
+    rstzip.assert().success();
+    assert_eq!(std::fs::read(dir.join("pack1"))?, b"abracadabra abracadabra");
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn zlib_and_raw () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("zlib_and_raw")?;
+    let data = b"zlib and raw deflate, zlib and raw deflate\n";
+    for (file, format) in &[("zlib1", "zlib"), ("raw1", "raw")] {
+        File::create(dir.join(file))?.write_all(data)?;
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["--format", format, "--", file]);
+        rstzip.assert().success();
+        let compressed = format!("{}.{}", file, format);
+        assert!(dir.join(&compressed).exists());
+
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["-dk", "--format", format, "--", &compressed]);
+        rstzip.assert().success();
+        assert_eq!(std::fs::read(dir.join(file))?, &data[..]);
+        remove_file(dir.join(file))?;
+    }
+
+    // zlib is recognized without being asked for, and its check value verified
+    let mut zlib = std::fs::read(dir.join("zlib1.zlib"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "zlib1.zlib"]);
+    rstzip.assert().success().stdout(predicate::str::contains("\nzlib ").and(predicate::str::contains("zlib1")));
+    let last = zlib.len() - 1;
+    zlib[last] ^= 1;
+    File::create(dir.join("zlib2.zlib"))?.write_all(&zlib)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "zlib2.zlib"]);
+    rstzip.assert().code(1).stderr(predicate::str::contains("adler32 error"));
+
+    // raw deflate has no magic number to recognize it by
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "raw1.raw"]);
+    rstzip.assert().code(1);
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--format", "raw", "--", "raw1.raw"]);
+    rstzip.assert().success().stdout(&data[..]);
+
+    // neither suffix is gzip's, so files named that way are still compressed
+    File::create(dir.join("photo1.raw"))?.write_all(data)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--", "photo1.raw"]);
+    rstzip.assert().success();
+    assert!(dir.join("photo1.raw.gz").exists());
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn zlib_lookalike_text () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("zlib_lookalike_text")?;
+    // "80" sets the preset dictionary flag, so it isn't taken for zlib at all, while "XG"
+    // passes every check of the zlib header, but what follows isn't deflate data
+    let texts = [("lookalike1.txt", "80 percent of the time\n"), ("lookalike2.txt", "XGA monitors are 1024x768\n")];
+    for (file, text) in &texts {
+        File::create(dir.join(file))?.write_all(text.as_bytes())?;
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["-dcf", "--", file]);
+        rstzip.assert()
+            .success()
+            .stdout(*text);
+    }
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--", "lookalike1.txt"]);
+    rstzip.assert().success();
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "lookalike2.txt"]);
+    rstzip.assert().code(1);
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn bgzf_blocks_and_range () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("bgzf_blocks_and_range")?;
+    let data: Vec<u8> = (0..200_000u32).flat_map(|i| format!("{}\n", i).into_bytes()).collect();
+    File::create(dir.join("bgzf1.txt"))?.write_all(&data)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-k", "--format", "bgzf", "--gzi", "--", "bgzf1.txt"]);
+    rstzip.assert().success();
+    let compressed = std::fs::read(dir.join("bgzf1.txt.gz"))?;
+    assert_eq!(&compressed[12..14], b"BC");
+    assert!(compressed.ends_with(&rstzip::formats::bgzf::EOF_MARKER));
+    assert!(dir.join("bgzf1.txt.gz.gzi").exists());
+
+    // listed with the sizes of all its blocks, not those of the empty block at the end
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "bgzf1.txt.gz"]);
+    rstzip.assert().success().stdout(predicate::str::contains("\nbgzf ")
+        .and(predicate::str::contains(format!("{}", data.len()))));
+
+    // with the index, then by walking the blocks
+    for _ in 0..2 {
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["--range", "1000000:20", "--", "bgzf1.txt.gz"]);
+        let out = rstzip.output()?;
+        assert!(out.status.success());
+        assert_eq!(out.stdout, &data[1_000_000..1_000_020]);
+        let _ = remove_file(dir.join("bgzf1.txt.gz.gzi"));
+    }
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-dc", "--", "bgzf1.txt.gz"]);
+    assert_eq!(rstzip.output()?.stdout, data);
+
+    // an index left from before the file was rewritten isn't trusted
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-kf", "--format", "bgzf", "--gzi", "--", "bgzf1.txt"]);
+    rstzip.assert().success();
+    let other: Vec<u8> = (0..50_000u32).flat_map(|i| format!("other {}\n", i).into_bytes()).collect();
+    File::create(dir.join("bgzf2.txt"))?.write_all(&other)?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--format", "bgzf", "--", "bgzf2.txt"]);
+    rstzip.assert().success();
+    std::fs::rename(dir.join("bgzf2.txt.gz"), dir.join("bgzf1.txt.gz"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--range", "300000:20", "--", "bgzf1.txt.gz"]);
+    assert_eq!(rstzip.output()?.stdout, &other[300_000..300_020]);
+    remove_file(dir.join("bgzf1.txt.gz.gzi"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    // ordinary gzip, which is read from the start
+    rstzip.args(&["-kf", "--", "bgzf1.txt"]);
+    rstzip.assert().success();
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--range", "0:10", "--", "bgzf1.txt.gz"]);
+    assert_eq!(rstzip.output()?.stdout, &data[..10]);
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn gzip_index_and_range () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("gzip_index_and_range")?;
+    let data: Vec<u8> = (0..400_000u32).flat_map(|i| format!("line {}\n", i * 7).into_bytes()).collect();
+    let compressed = rstzip::compress(&data[..], Vec::new(), 6)?;
+    File::create(dir.join("zran1.gz"))?.write_all(&compressed)?;
+
+    // read from the start without an index, then from an access point with one
+    for index in &[false, true] {
+        if *index {
+            let mut rstzip = rstzip_in(&dir)?;
+            rstzip.args(&["--index", "--verbose", "1", "--", "zran1.gz"]);
+            rstzip.assert().success().stdout(predicate::str::contains("access points"));
+            assert!(dir.join("zran1.gz.gzidx").exists() && dir.join("zran1.gz").exists());
+        }
+        let mut rstzip = rstzip_in(&dir)?;
+        rstzip.args(&["--range", "3000000:25", "--", "zran1.gz"]);
+        let out = rstzip.output()?;
+        assert!(out.status.success());
+        assert_eq!(out.stdout, &data[3_000_000..3_000_025]);
+    }
+
+    // an index no longer matching its file is refused
+    File::create(dir.join("zran1.gz"))?.write_all(&compressed[..compressed.len() - 1])?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--range", "0:10", "--", "zran1.gz"]);
+    rstzip.assert().code(1).stderr(predicate::str::contains("out of date"));
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn tar_archive_tree () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("tar_archive_tree")?;
+    create_dir(dir.join("tartree"))?;
+    create_dir(dir.join("tartree/sub"))?;
+    File::create(dir.join("tartree/a.txt"))?.write_all(b"top level")?;
+    File::create(dir.join("tartree/sub/b.txt"))?.write_all(&[b'b'; 1000])?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["--tar", "-r", "--", "tartree"]);
+    rstzip.assert().success();
+    // the tree is left in place, and the archive is an ordinary gzip file
+    assert!(dir.join("tartree/a.txt").exists());
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-t", "--", "tartree.tar.gz"]);
+    rstzip.assert().success();
+
+    remove_dir_all(dir.join("tartree"))?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--tar", "--", "tartree.tar.gz"]);
+    rstzip.assert().success();
+    let mut extracted = Vec::new();
+    File::open(dir.join("tartree/sub/b.txt"))?.read_to_end(&mut extracted)?;
+    assert_eq!(extracted, vec![b'b'; 1000]);
+    assert!(!dir.join("tartree.tar.gz").exists());
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn tar_archive_unsafe_path () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("tar_archive_unsafe_path")?;
+    // a ustar header for the file "../tarevil", holding one byte
+    let mut header = vec![0u8; 512];
+    header[..10].copy_from_slice(b"../tarevil");
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[124..136].copy_from_slice(b"00000000001\0");
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    let mut tar = header;
+    tar.push(b'x');
+    tar.resize(512 * 4, 0);
+    let mut gz = flate2::write::GzEncoder::new(File::create(dir.join("tarevil.tgz"))?, flate2::Compression::default());
+    gz.write_all(&tar)?;
+    gz.finish()?;
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--tar", "--", "tarevil.tgz"]);
+    rstzip.assert()
+        .code(1)
+        .stderr(predicate::str::contains("../tarevil: unsafe path in archive"));
+    assert!(dir.join("tarevil.tgz").exists());
+
+    // without --tar, .tgz decompresses to .tar as in gzip
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-d", "--", "tarevil.tgz"]);
+    rstzip.assert().success();
+    assert_eq!(std::fs::read(dir.join("tarevil.tar"))?, tar);
+    remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn multi_member_list () -> Result<(), Box<dyn std::error::Error>> {
+    let dir = scratch("multi_member_list")?;
+    let first = rstzip::compress(&b"the first member\n"[..], Vec::new(), 6)?;
+    let second = rstzip::compress(&[b'y'; 5000][..], Vec::new(), 6)?;
+    File::create(dir.join("members1.gz"))?.write_all(&[&first[..], &second[..]].concat())?;
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--", "members1.gz"]);
+    let out = rstzip.output()?;
+    let stdout_str = std::str::from_utf8(out.stdout.as_slice())?;
+    // as in gzip, only the last member's trailer is read
+    assert!(stdout_str.contains("5000") && !stdout_str.contains("5017"));
+    assert!(!stdout_str.contains("@0"));
+
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "members1.gz"]);
+    let out = rstzip.output()?;
+    let stdout_str = std::str::from_utf8(out.stdout.as_slice())?;
+    assert!(stdout_str.contains("5017"));
+    assert!(stdout_str.contains("  @0 "));
+    assert!(stdout_str.contains(&format!("  @{} ", first.len())));
+    let mut crc = flate2::Crc::new();
+    crc.update(b"the first member\n");
+    crc.update(&[b'y'; 5000]);
+    assert!(stdout_str.contains(&format!("{:x}", crc.sum())));
+
+    // junk after the last member is warned about
+    File::create(dir.join("members1.gz"))?.write_all(&[&first[..], &second[..], &b"junk"[..]].concat())?;
+    let mut rstzip = rstzip_in(&dir)?;
+    rstzip.args(&["-l", "--verbose", "1", "--", "members1.gz"]);
+    rstzip.assert()
+        .code(2)
+        .stdout(predicate::str::contains("5017"))
+        .stderr(predicate::str::contains("members1.gz: decompression OK, trailing garbage ignored"));
+    remove_dir_all(&dir)?;
+    Ok(())
+}
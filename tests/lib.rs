@@ -0,0 +1,161 @@
+use std::fs::{self, File, remove_file};
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use flate2::Compression;
+use rstzip::{Codec, GzFile, Header, Mode, Options, Tested, Warning, WorkData};
+
+#[test]
+fn header_inspection () -> Result<(), Box<dyn std::error::Error>> {
+    let data = b"Houston, Tranquility Base here. The Eagle has landed.";
+    let compressed = GzFile::encode(&data[..], Vec::new(), Some("eagle.txt"), Some(14_200_000),
+        Compression::new(9))?;
+
+    let mut input = &compressed[..];
+    let gz = GzFile::read_header(&mut input)?;
+    assert_eq!(gz.name(), Some("eagle.txt"));
+    assert_eq!(gz.mtime(), 14_200_000);
+    assert_eq!(gz.compression_method(), 8);
+    assert_eq!(gz.comment(), None);
+    assert_eq!(gz.header_len(), 10 + "eagle.txt".len() + 1);
+
+    let mut output: Vec<u8> = Vec::new();
+    gz.decompress(input, &mut output)?;
+    assert_eq!(&output[..], &data[..]);
+    Ok(())
+}
+
+#[test]
+fn list_entry () -> Result<(), Box<dyn std::error::Error>> {
+    let data = vec![b'x'; 10_000];
+    let compressed = rstzip::compress(&data[..], Vec::new(), 6)?;
+    File::create("lib_list1.gz")?.write_all(&compressed)?;
+
+    let entry = rstzip::list("lib_list1.gz")?;
+    assert_eq!(entry.method, "defla");
+    assert_eq!(entry.compressed_size, compressed.len() as u64);
+    assert_eq!(entry.uncompressed_size, Some(10_000));
+    assert!(entry.ratio() > 90.0);
+
+    remove_file("lib_list1.gz")?;
+    Ok(())
+}
+
+#[test]
+fn run_with_options () -> Result<(), Box<dyn std::error::Error>> {
+    let data = b"compressed without any command line arguments";
+    File::create("lib_run1")?.write_all(data)?;
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&messages);
+    let mut options = Options::new(Mode::Compress)
+        .with_sink(move |msg| sink.lock().unwrap().push(String::from(msg)));
+    options.level = 9;
+    options.keep = true;
+    rstzip::run(vec![PathBuf::from("lib_run1")], &options)?;
+    assert!(messages.lock().unwrap().is_empty());
+
+    // compressing the output again is refused, and the warning goes to the sink
+    match rstzip::run(vec![PathBuf::from("lib_run1.gz")], &options) {
+        Err(rstzip::Error::Warning { warning, .. }) => assert_eq!(warning, Warning::HasSuffix(String::from("gz"))),
+        other => panic!("expected a warning, got {:?}", other)
+    }
+    assert_eq!(messages.lock().unwrap()[0], "rstzip: lib_run1.gz already has .gz suffix -- unchanged");
+
+    remove_file("lib_run1")?;
+    options.mode = Mode::Decompress;
+    options.keep = false;
+    rstzip::run(vec![PathBuf::from("lib_run1.gz")], &options)?;
+    assert_eq!(fs::read("lib_run1")?, &data[..]);
+    assert!(fs::metadata("lib_run1.gz").is_err());
+
+    remove_file("lib_run1")?;
+    Ok(())
+}
+
+#[test]
+fn depth_limit () -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all("lib_depth1/a/b")?;
+    File::create("lib_depth1/a/shallow")?.write_all(b"within reach")?;
+    File::create("lib_depth1/a/b/deep")?.write_all(b"out of reach")?;
+
+    let mut options = Options::new(Mode::Compress).with_sink(|_| ());
+    options.recursive = true;
+    options.max_depth = 2;
+    match rstzip::run(vec![PathBuf::from("lib_depth1")], &options) {
+        Err(rstzip::Error::Warning { path, warning }) => {
+            assert_eq!(path, PathBuf::from("lib_depth1/a/b"));
+            assert_eq!(warning, Warning::TooDeep);
+        },
+        other => panic!("expected a warning, got {:?}", other)
+    }
+    assert!(fs::metadata("lib_depth1/a/shallow.gz").is_ok());
+    assert!(fs::metadata("lib_depth1/a/b/deep").is_ok());
+
+    fs::remove_dir_all("lib_depth1")?;
+    Ok(())
+}
+
+/// A format that stores data as it is, behind a magic number
+struct Stored;
+
+impl Codec for Stored {
+    fn name (&self) -> &'static str {
+        "stored"
+    }
+
+    fn detect (&self, magic: &[u8]) -> bool {
+        magic.starts_with(b"STOR")
+    }
+
+    fn read_header (&self, input: &mut dyn BufRead) -> io::Result<Header> {
+        let mut raw = vec![0u8; 4];
+        input.read_exact(&mut raw)?;
+        Ok(Header { raw, ..Header::default() })
+    }
+
+    fn decode (&self, _header: &Header, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<Tested> {
+        let size = io::copy(input, output)?;
+        Ok(Tested { members: 1, size, ..Tested::default() })
+    }
+
+    fn test (&self, _header: &Header, input: &mut dyn BufRead) -> io::Result<Tested> {
+        let size = io::copy(input, &mut io::sink())?;
+        Ok(Tested { members: 1, size, ..Tested::default() })
+    }
+
+    fn encode (&self, input: &mut dyn Read, output: &mut dyn Write, _wdata: Option<WorkData>,
+        _opt: &Options) -> io::Result<()>
+    {
+        output.write_all(b"STOR")?;
+        io::copy(input, output).map(|_| ())
+    }
+}
+
+#[test]
+fn registered_codec () -> Result<(), Box<dyn std::error::Error>> {
+    let data = b"kept exactly as it was";
+    File::create("lib_codec1")?.write_all(data)?;
+
+    let mut options = Options::new(Mode::Compress).with_sink(|_| ());
+    options.codecs.register(Stored);
+    options.format = String::from("stored");
+    rstzip::run(vec![PathBuf::from("lib_codec1")], &options)?;
+    assert_eq!(fs::read("lib_codec1.gz")?, [&b"STOR"[..], &data[..]].concat());
+    assert_eq!(options.codecs.detect(b"STOR").map(|codec| codec.name()), Some("stored"));
+
+    options.mode = Mode::Test;
+    rstzip::run(vec![PathBuf::from("lib_codec1.gz")], &options)?;
+    options.mode = Mode::Decompress;
+    rstzip::run(vec![PathBuf::from("lib_codec1.gz")], &options)?;
+    assert_eq!(fs::read("lib_codec1")?, &data[..]);
+
+    // without the codec, the data isn't recognized
+    File::create("lib_codec1.gz")?.write_all(b"STORthe rest")?;
+    let options = Options::new(Mode::Test).with_sink(|_| ());
+    assert!(rstzip::run(vec![PathBuf::from("lib_codec1.gz")], &options).is_err());
+
+    remove_file("lib_codec1")?;
+    remove_file("lib_codec1.gz")?;
+    Ok(())
+}